@@ -1,6 +1,6 @@
 use crate::constants::{
-    BOLD, DEFAULT_COLOR, FAINT, GREEN, INVERSE_VIDEO, NORMAL, NORMAL_INTENSITY, NO_INVERSE_VIDEO,
-    RED, YELLOW,
+    BLUE, BOLD, CYAN, DEFAULT_COLOR, FAINT, GREEN, INVERSE_VIDEO, MAGENTA, NORMAL,
+    NORMAL_INTENSITY, NO_INVERSE_VIDEO, NO_UNDERLINE, RED, UNDERLINE, YELLOW,
 };
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -9,6 +9,9 @@ pub enum Color {
     Red,
     Green,
     Yellow,
+    Blue,
+    Magenta,
+    Cyan,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -23,6 +26,7 @@ pub struct AnsiStyle {
     pub(crate) color: Color,
     pub(crate) weight: Weight,
     pub(crate) inverse: bool,
+    pub(crate) underline: bool,
     pub(crate) url: Option<url::Url>,
 }
 
@@ -30,6 +34,7 @@ pub const ANSI_STYLE_NORMAL: AnsiStyle = AnsiStyle {
     color: Color::Default,
     weight: Weight::Normal,
     inverse: false,
+    underline: false,
     url: None,
 };
 
@@ -44,7 +49,10 @@ impl AnsiStyle {
     }
 
     fn normal_except_hyperlink(&self) -> bool {
-        self.color == Color::Default && self.weight == Weight::Normal && !self.inverse
+        self.color == Color::Default
+            && self.weight == Weight::Normal
+            && !self.inverse
+            && !self.underline
     }
 
     /// Renders a (possibly empty) ANSI escape sequence to switch to this style
@@ -81,6 +89,13 @@ impl AnsiStyle {
             return_me.push_str(NO_INVERSE_VIDEO);
         }
 
+        if self.underline && !before.underline {
+            return_me.push_str(UNDERLINE);
+        }
+        if !self.underline && before.underline {
+            return_me.push_str(NO_UNDERLINE);
+        }
+
         if self.weight != before.weight {
             if before.weight != Weight::Normal {
                 // Turn off bold or faint
@@ -100,6 +115,9 @@ impl AnsiStyle {
                 Color::Red => return_me.push_str(RED),
                 Color::Green => return_me.push_str(GREEN),
                 Color::Yellow => return_me.push_str(YELLOW),
+                Color::Blue => return_me.push_str(BLUE),
+                Color::Magenta => return_me.push_str(MAGENTA),
+                Color::Cyan => return_me.push_str(CYAN),
             }
         }
 
@@ -111,6 +129,7 @@ impl AnsiStyle {
             color,
             weight: self.weight,
             inverse: self.inverse,
+            underline: self.underline,
             url: self.url.clone(),
         };
     }
@@ -120,6 +139,7 @@ impl AnsiStyle {
             color: self.color,
             weight: self.weight,
             inverse,
+            underline: self.underline,
             url: self.url.clone(),
         };
     }
@@ -129,6 +149,17 @@ impl AnsiStyle {
             color: self.color,
             weight,
             inverse: self.inverse,
+            underline: self.underline,
+            url: self.url.clone(),
+        };
+    }
+
+    pub fn with_underline(&self, underline: bool) -> AnsiStyle {
+        return AnsiStyle {
+            color: self.color,
+            weight: self.weight,
+            inverse: self.inverse,
+            underline,
             url: self.url.clone(),
         };
     }
@@ -138,6 +169,7 @@ impl AnsiStyle {
             color: self.color,
             weight: self.weight,
             inverse: self.inverse,
+            underline: self.underline,
             url: Some(url),
         };
     }
@@ -254,6 +286,7 @@ mod tests {
             color: Color::Default,
             weight: Weight::Normal,
             inverse: false,
+            underline: false,
             url: Some(url.clone()),
         };
 
@@ -279,12 +312,14 @@ mod tests {
             color: Color::Red,
             weight: Weight::Normal,
             inverse: false,
+            underline: false,
             url: None,
         };
         let red_link = AnsiStyle {
             color: Color::Red,
             weight: Weight::Normal,
             inverse: false,
+            underline: false,
             url: Some(url.clone()),
         };
         assert_eq!(ANSI_STYLE_NORMAL.from(&red), "\x1b[0m");
@@ -295,12 +330,14 @@ mod tests {
             color: Color::Default,
             weight: Weight::Bold,
             inverse: false,
+            underline: false,
             url: None,
         };
         let bold_link = AnsiStyle {
             color: Color::Default,
             weight: Weight::Bold,
             inverse: false,
+            underline: false,
             url: Some(url.clone()),
         };
         assert_eq!(ANSI_STYLE_NORMAL.from(&bold), "\x1b[0m");
@@ -311,18 +348,29 @@ mod tests {
             color: Color::Default,
             weight: Weight::Normal,
             inverse: true,
+            underline: false,
             url: None,
         };
         let inverse_link = AnsiStyle {
             color: Color::Default,
             weight: Weight::Normal,
             inverse: true,
-            url: Some(url),
+            underline: false,
+            url: Some(url.clone()),
         };
         assert_eq!(ANSI_STYLE_NORMAL.from(&inverse), "\x1b[0m");
         assert_eq!(
             ANSI_STYLE_NORMAL.from(&inverse_link),
             "\x1b]8;;\x1b\\\x1b[0m"
         );
+
+        // Underline
+        let underline = ANSI_STYLE_NORMAL.with_underline(true);
+        let underline_link = underline.with_url(url);
+        assert_eq!(ANSI_STYLE_NORMAL.from(&underline), "\x1b[0m");
+        assert_eq!(
+            ANSI_STYLE_NORMAL.from(&underline_link),
+            "\x1b]8;;\x1b\\\x1b[0m"
+        );
     }
 }
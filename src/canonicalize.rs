@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tempfile::TempDir;
+
+use crate::key_path::StructuredFormat;
+use crate::notebook::render_notebook;
+
+/// Pretty print a JSON or YAML document with all object keys sorted, so that
+/// minified or reordered documents give meaningful diffs, see
+/// `--canonicalize`.
+///
+/// Jupyter notebooks are rendered as a list of cells, see
+/// `notebook::render_notebook()`.
+pub(crate) fn canonicalize(format: StructuredFormat, text: &str) -> Result<String, String> {
+    match format {
+        StructuredFormat::Json => {
+            // serde_json sorts object keys unless its preserve_order feature
+            // is enabled
+            let value: serde_json::Value =
+                serde_json::from_str(text).map_err(|error| error.to_string())?;
+            let pretty = serde_json::to_string_pretty(&value).map_err(|error| error.to_string())?;
+            return Ok(pretty + "\n");
+        }
+        StructuredFormat::Yaml => {
+            // Go through serde_json values to get the keys sorted
+            let mut documents = Vec::new();
+            for document in serde_norway::Deserializer::from_str(text) {
+                let value =
+                    serde_json::Value::deserialize(document).map_err(|error| error.to_string())?;
+                documents.push(serde_norway::to_string(&value).map_err(|error| error.to_string())?);
+            }
+            return Ok(documents.join("---\n"));
+        }
+        StructuredFormat::Notebook => return render_notebook(text),
+        StructuredFormat::Csv | StructuredFormat::Tsv => {
+            return Err("Only JSON and YAML files can be canonicalized".to_string());
+        }
+    }
+}
+
+/// Canonical copies of two files, in a temporary directory that is removed
+/// when this is dropped.
+pub(crate) struct CanonicalCopies {
+    // Only kept around for removing the directory when we're dropped
+    _directory: TempDir,
+    pub(crate) path1: PathBuf,
+    pub(crate) path2: PathBuf,
+}
+
+impl CanonicalCopies {
+    pub(crate) fn new(path1: &Path, path2: &Path) -> Result<CanonicalCopies, String> {
+        let directory = tempfile::Builder::new()
+            .prefix("riff-canonicalize-")
+            .tempdir()
+            .map_err(|error| error.to_string())?;
+
+        let copy1 = canonical_copy(path1, &directory.path().join("1"))?;
+        let copy2 = canonical_copy(path2, &directory.path().join("2"))?;
+        return Ok(CanonicalCopies {
+            _directory: directory,
+            path1: copy1,
+            path2: copy2,
+        });
+    }
+}
+
+/// Write a canonical copy of `source` into `directory`, keeping the file name
+/// so that the file type can still be told from it.
+fn canonical_copy(source: &Path, directory: &Path) -> Result<PathBuf, String> {
+    let name = source.to_string_lossy();
+    let Some(format) = StructuredFormat::from_filename(&name) else {
+        return Err(format!(
+            "Only JSON and YAML files can be canonicalized: {name}"
+        ));
+    };
+
+    let text = fs::read_to_string(source).map_err(|error| format!("{error}: {name}"))?;
+    let canonical = canonicalize(format, &text).map_err(|error| format!("{error}: {name}"))?;
+
+    fs::create_dir_all(directory).map_err(|error| error.to_string())?;
+    let destination = directory.join(source.file_name().unwrap_or_default());
+    fs::write(&destination, canonical).map_err(|error| error.to_string())?;
+    return Ok(destination);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_canonicalize_json() {
+        assert_eq!(
+            canonicalize(StructuredFormat::Json, r#"{"b":[1,2],"a":{"d":null,"c":true}}"#)
+                .unwrap(),
+            "{\n  \"a\": {\n    \"c\": true,\n    \"d\": null\n  },\n  \"b\": [\n    1,\n    2\n  ]\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_yaml() {
+        assert_eq!(
+            canonicalize(StructuredFormat::Yaml, "b: {y: 1, x: 2}\na: [1, 2]\n").unwrap(),
+            "a:\n- 1\n- 2\nb:\n  x: 2\n  y: 1\n"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_yaml_documents() {
+        assert_eq!(
+            canonicalize(StructuredFormat::Yaml, "b: 1\na: 2\n---\nc: 3\n").unwrap(),
+            "a: 2\nb: 1\n---\nc: 3\n"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_invalid() {
+        assert!(canonicalize(StructuredFormat::Json, "{").is_err());
+    }
+}
@@ -1,9 +1,144 @@
+use crate::commit_subject::format_subject;
 use crate::constants::*;
+use crate::forge::ForgeLinks;
+use crate::hyperlink::{hyperlink, Hyperlinker};
+use crate::repository;
 use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
 
-// Highlight lines starting with "commit "
+// Highlight lines starting with "commit ", and `git log --oneline` lines
 
-pub fn format_commit_line(line: &str, highlight_background: bool) -> String {
+/// Like `97f653d (HEAD -> main, origin/main) Bump version number`. Requiring
+/// a digit in the hash keeps us from matching words like "defaced".
+static ONELINE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([0-9a-f]{7,40})(?: \(([^()]*)\))? (.*)$").unwrap());
+
+/// Remotes of the repository in the current directory, for telling
+/// remote-tracking branches like `origin/main` from local branches like
+/// `walles/threaded`
+static REMOTE_NAMES: Lazy<Vec<String>> = Lazy::new(|| {
+    let mut remote_names = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| repository::toplevel(&cwd))
+        .map(|toplevel| repository::remote_names(&toplevel))
+        .unwrap_or_default();
+    for common in ["origin", "upstream"] {
+        if !remote_names.iter().any(|name| name == common) {
+            remote_names.push(common.to_string());
+        }
+    }
+    return remote_names;
+});
+
+/// One ref from a `git log --decorate` decoration, like `tag: 2.21.0`
+#[derive(Debug, PartialEq)]
+enum Decoration<'a> {
+    /// `HEAD -> main` if on a branch, otherwise `HEAD`
+    Head(Option<&'a str>),
+    Tag(&'a str),
+    LocalBranch(&'a str),
+
+    /// Remote-tracking branch, like `origin/main`
+    RemoteBranch(&'a str),
+
+    /// `refs/stash`
+    Stash,
+
+    /// `grafted` or `replaced`
+    Marker(&'a str),
+}
+
+impl<'a> Decoration<'a> {
+    fn parse(part: &'a str, remote_names: &[String]) -> Self {
+        if part == "HEAD" {
+            return Decoration::Head(None);
+        }
+        if let Some(branch) = part.strip_prefix("HEAD -> ") {
+            return Decoration::Head(Some(branch));
+        }
+        if let Some(tag) = part.strip_prefix("tag: ") {
+            return Decoration::Tag(tag);
+        }
+        if part == "refs/stash" {
+            return Decoration::Stash;
+        }
+        if part == "grafted" || part == "replaced" {
+            return Decoration::Marker(part);
+        }
+        if part.starts_with("refs/remotes/") {
+            return Decoration::RemoteBranch(part);
+        }
+        if let Some((remote, _)) = part.split_once('/') {
+            if remote_names.iter().any(|name| name == remote) {
+                return Decoration::RemoteBranch(part);
+            }
+        }
+        return Decoration::LocalBranch(part);
+    }
+
+    /// For pairing local branches with their upstreams, `main` for both
+    /// `refs/heads/main` and `origin/main`
+    fn branch_name(&self) -> Option<&'a str> {
+        return match self {
+            Decoration::Head(Some(branch)) | Decoration::LocalBranch(branch) => {
+                Some(branch.strip_prefix("refs/heads/").unwrap_or(branch))
+            }
+            Decoration::RemoteBranch(branch) => {
+                let branch = branch.strip_prefix("refs/remotes/").unwrap_or(branch);
+                branch.split_once('/').map(|(_, name)| name)
+            }
+            _ => None,
+        };
+    }
+
+    fn render(&self, current_branch: &Option<String>, forge_links: Option<&ForgeLinks>) -> String {
+        let link_branch = |branch: &str| {
+            return linked(
+                branch,
+                forge_links.and_then(|links| links.branch_url(branch)),
+            );
+        };
+
+        return match self {
+            Decoration::Head(None) => format!("{BOLD}{CYAN}HEAD{NORMAL_INTENSITY}"),
+            Decoration::Head(Some(branch)) => format!(
+                "{BOLD}{CYAN}HEAD -> {GREEN}{}{NORMAL_INTENSITY}",
+                link_branch(branch)
+            ),
+            Decoration::Tag(tag) => {
+                // Implicitly yellow since both the commas and the surrounding
+                // parentheses are also yellow.
+                let tag = linked(tag, forge_links.and_then(|links| links.tag_url(tag)));
+                format!("{BOLD}tag: {tag}{NORMAL_INTENSITY}")
+            }
+            Decoration::LocalBranch(branch) => {
+                if current_branch.as_deref() == Some(*branch) {
+                    format!("{BOLD}{GREEN}{}{NORMAL_INTENSITY}", link_branch(branch))
+                } else {
+                    format!("{GREEN}{}", link_branch(branch))
+                }
+            }
+            Decoration::RemoteBranch(branch) => {
+                let branch = linked(
+                    branch,
+                    forge_links.and_then(|links| links.remote_branch_url(branch)),
+                );
+                format!("{BOLD}{RED}{branch}{NORMAL_INTENSITY}")
+            }
+            Decoration::Stash => format!("{BOLD}{MAGENTA}refs/stash{NORMAL_INTENSITY}"),
+            Decoration::Marker(marker) => format!("{BOLD}{BLUE}{marker}{NORMAL_INTENSITY}"),
+        };
+    }
+}
+
+/// If `forge_links` is set, the commit hash, tags and branches are linked to
+/// the repository's web UI.
+pub fn format_commit_line(
+    line: &str,
+    highlight_background: bool,
+    forge_links: Option<&ForgeLinks>,
+) -> String {
     let header: String = if highlight_background {
         YELLOW.to_string() + BLUE_TO_END_OF_LINE
     } else {
@@ -13,58 +148,138 @@ pub fn format_commit_line(line: &str, highlight_background: bool) -> String {
     let parts = line.split('(').collect::<Vec<_>>();
     if parts.len() == 1 {
         // Just "commit: 123abc", color it all yellow
-        return format!("{header}{line}{NORMAL}");
+        return format!("{header}{}{NORMAL}", link_commit(line, forge_links));
     }
 
-    let commit_part = parts[0].trim();
-    let without_trailing_parenthesis = parts[1].strip_suffix(')');
-    if without_trailing_parenthesis.is_none() {
+    let commit_part = link_commit(parts[0].trim(), forge_links);
+    let Some(decorations) = parts[1].strip_suffix(')') else {
         // No final parenthesis, this is weird, fall back to showing everything
         // in yellow
         return format!("{header}{line}{NORMAL}");
-    }
-
-    let parenthesis_parts = without_trailing_parenthesis
-        .unwrap()
-        .split(", ")
-        .collect_vec();
-    let current_branch = compute_current_branch(&parenthesis_parts);
+    };
 
-    let comma = format!("{YELLOW}, ");
     return format!(
-        "{}{} ({}{}){}",
-        header,
-        commit_part,
-        parenthesis_parts
-            .iter()
-            .map(|part| format_commit_part(part, &current_branch))
-            .join(&comma),
-        YELLOW,
-        NORMAL
+        "{header}{commit_part} ({}{YELLOW}){NORMAL}",
+        format_decorations(decorations, forge_links, &REMOTE_NAMES)
     );
 }
 
-fn format_commit_part(part: &str, current_branch: &Option<String>) -> String {
-    if part.starts_with("tag: ") {
-        // Implicitly yellow since both the commas and the surrounding
-        // parentheses are also yellow.
-        return format!("{BOLD}{part}{NORMAL_INTENSITY}");
+/// Format a `git log --oneline` line, like `97f653d (HEAD -> main) Bump
+/// version number`. Returns `None` if `line` isn't one.
+pub(crate) fn format_oneline_commit_line(
+    line: &str,
+    hyperlinker: &Hyperlinker,
+    lint: bool,
+) -> Option<String> {
+    let captures = oneline_captures(line)?;
+    let hash = captures.get(1).unwrap().as_str();
+    let forge_links = hyperlinker.forge_links();
+
+    let hash_url = forge_links.and_then(|links| links.commit_url(hash));
+    let mut formatted = format!("{YELLOW}{}", linked(hash, hash_url));
+    if let Some(decorations) = captures.get(2) {
+        formatted.push_str(&format!(
+            " ({}{YELLOW})",
+            format_decorations(decorations.as_str(), forge_links, &REMOTE_NAMES)
+        ));
     }
+    formatted.push_str(NORMAL);
+    formatted.push(' ');
 
-    // FIXME: Can we do this with one readable if-statement instead?
-    if let Some(current_branch_4_realz) = current_branch {
-        if current_branch_4_realz == part {
-            return format!("{BOLD}{GREEN}{part}{NORMAL_INTENSITY}");
-        }
+    let subject = captures.get(3).unwrap().as_str();
+    formatted.push_str(&format_subject(subject, hyperlinker.issue_links(), lint));
+    return Some(formatted);
+}
+
+/// Is this a `git log --oneline` line? Lines like `1234567 rows inserted.`
+/// look the same, so unless we know we're `in_log`, we also want decorations
+/// and a commit hash the repository in the current directory knows about.
+pub(crate) fn is_oneline_commit_line(line: &str, in_log: bool) -> bool {
+    let Some(captures) = oneline_captures(line) else {
+        return false;
+    };
+    if in_log {
+        return true;
     }
+    if captures.get(2).is_none() {
+        return false;
+    }
+
+    let hash = captures.get(1).unwrap().as_str();
+    return std::env::current_dir()
+        .ok()
+        .and_then(|cwd| repository::toplevel(&cwd))
+        .is_some_and(|toplevel| repository::has_object(&toplevel, hash));
+}
 
-    // Handle "HEAD -> current_branch"
-    if let Some(head_branch) = part.strip_prefix("HEAD -> ") {
-        return format!("{BOLD}{CYAN}HEAD -> {GREEN}{head_branch}{NORMAL_INTENSITY}");
+fn oneline_captures(line: &str) -> Option<regex::Captures<'_>> {
+    let captures = ONELINE.captures(line)?;
+    let hash = captures.get(1).unwrap().as_str();
+    if !hash.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    return Some(captures);
+}
+
+/// `decorations` is what's inside the parentheses, like `HEAD -> main,
+/// origin/main`. Local branches are shown together with their upstreams,
+/// like `main ⇄ origin/main`.
+fn format_decorations(
+    decorations: &str,
+    forge_links: Option<&ForgeLinks>,
+    remote_names: &[String],
+) -> String {
+    let parts = decorations.split(", ").collect_vec();
+    let current_branch = compute_current_branch(&parts);
+    let decorations = parts
+        .iter()
+        .map(|part| Decoration::parse(part, remote_names))
+        .collect_vec();
+
+    // Index of the remote branch paired with each local branch
+    let mut upstreams: Vec<Option<usize>> = vec![None; decorations.len()];
+    for (local_index, local) in decorations.iter().enumerate() {
+        if !matches!(local, Decoration::Head(_) | Decoration::LocalBranch(_)) {
+            continue;
+        }
+        upstreams[local_index] = decorations.iter().position(|remote| {
+            return matches!(remote, Decoration::RemoteBranch(_))
+                && remote.branch_name() == local.branch_name();
+        });
     }
 
-    // Assume this is a branch, but not the current one
-    return format!("{BOLD}{RED}{part}{NORMAL_INTENSITY}");
+    let comma = format!("{YELLOW}, ");
+    return decorations
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !upstreams.contains(&Some(*index)))
+        .map(|(index, decoration)| {
+            let rendered = decoration.render(&current_branch, forge_links);
+            let Some(upstream) = upstreams[index] else {
+                return rendered;
+            };
+            return format!(
+                "{rendered}{YELLOW} ⇄ {}",
+                decorations[upstream].render(&current_branch, forge_links)
+            );
+        })
+        .join(&comma);
+}
+
+/// Link the hash in `commit 123abc`
+fn link_commit(commit_part: &str, forge_links: Option<&ForgeLinks>) -> String {
+    let Some((commit, hash)) = commit_part.split_once(' ') else {
+        return commit_part.to_string();
+    };
+    let url = forge_links.and_then(|links| links.commit_url(hash.trim()));
+    return format!("{commit} {}", linked(hash, url));
+}
+
+fn linked(text: &str, url: Option<url::Url>) -> String {
+    return match url {
+        Some(url) => hyperlink(&url, text),
+        None => text.to_string(),
+    };
 }
 
 fn compute_current_branch(candidates: &Vec<&str>) -> Option<String> {
@@ -170,10 +385,6 @@ mod tests {
             BOLD +
             "tag: 2.21.0"+
             NORMAL_INTENSITY + YELLOW +  // NOTE: This yellow is not strictly needed, but having it simplifies the comma code
-            ", "+
-            BOLD + RED +
-            "origin/master" +
-            NORMAL_INTENSITY + YELLOW +
             ", " +
             BOLD + RED +
             "origin/HEAD" +
@@ -182,9 +393,104 @@ mod tests {
             BOLD + GREEN +
             "master" +
             NORMAL_INTENSITY + YELLOW +
+            " ⇄ " +
+            BOLD + RED +
+            "origin/master" +
+            NORMAL_INTENSITY + YELLOW +
             ")" +
             NORMAL,
         // This commit is from the master branch
-        format_commit_line("commit 62da46c7b300321119d399bdc69bfb2d56d5da57 (tag: 2.21.0, origin/master, origin/HEAD, master)", true));
+        format_commit_line("commit 62da46c7b300321119d399bdc69bfb2d56d5da57 (tag: 2.21.0, origin/master, origin/HEAD, master)", true, None));
+    }
+
+    #[test]
+    fn test_parse_decoration() {
+        let remote_names = vec!["origin".to_string(), "fork".to_string()];
+        let parse = |part| Decoration::parse(part, &remote_names);
+        assert_eq!(parse("HEAD"), Decoration::Head(None));
+        assert_eq!(parse("HEAD -> main"), Decoration::Head(Some("main")));
+        assert_eq!(parse("tag: 2.21.0"), Decoration::Tag("2.21.0"));
+        assert_eq!(parse("fork/main"), Decoration::RemoteBranch("fork/main"));
+        assert_eq!(
+            parse("refs/remotes/origin/main"),
+            Decoration::RemoteBranch("refs/remotes/origin/main")
+        );
+        assert_eq!(
+            parse("walles/threaded"),
+            Decoration::LocalBranch("walles/threaded")
+        );
+        assert_eq!(parse("refs/stash"), Decoration::Stash);
+        assert_eq!(parse("grafted"), Decoration::Marker("grafted"));
+        assert_eq!(parse("replaced"), Decoration::Marker("replaced"));
+    }
+
+    #[test]
+    fn test_format_decorations_groups_upstreams() {
+        let remote_names = vec!["origin".to_string()];
+        assert_eq!(
+            format_decorations(
+                "HEAD -> refs/heads/main, refs/remotes/origin/main, refs/stash, feature",
+                None,
+                &remote_names
+            ),
+            format!(
+                "{BOLD}{CYAN}HEAD -> {GREEN}refs/heads/main{NORMAL_INTENSITY}{YELLOW} ⇄ {BOLD}{RED}refs/remotes/origin/main{NORMAL_INTENSITY}{YELLOW}, {BOLD}{MAGENTA}refs/stash{NORMAL_INTENSITY}{YELLOW}, {GREEN}feature"
+            )
+        );
+    }
+
+    #[test]
+    fn test_is_oneline_commit_line() {
+        assert!(is_oneline_commit_line("1234567 rows inserted.", true));
+        assert!(!is_oneline_commit_line("1234567 rows inserted.", false));
+
+        // Decorated, but not a commit we know about
+        assert!(!is_oneline_commit_line("abcdef12 (foo) bar", false));
+    }
+
+    #[test]
+    fn test_format_oneline_commit_line() {
+        assert_eq!(
+            format_oneline_commit_line(
+                "97f653d (tag: 3.0.0) fix: Bump version number",
+                &Hyperlinker::default(),
+                false
+            ),
+            Some(format!(
+                "{YELLOW}97f653d ({BOLD}tag: 3.0.0{NORMAL_INTENSITY}{YELLOW}){NORMAL} {BOLD}fix{NORMAL_INTENSITY}: Bump version number"
+            ))
+        );
+        assert_eq!(
+            format_oneline_commit_line("defaced the wall", &Hyperlinker::default(), false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_commit_line_links() {
+        let links = ForgeLinks::new(None, Some("https://example.com/{ref}")).unwrap();
+        let link = |reference: &str| {
+            return hyperlink(
+                &url::Url::parse(&format!("https://example.com/{reference}")).unwrap(),
+                reference,
+            );
+        };
+        assert_eq!(
+            format_commit_line(
+                "commit 62da46c (HEAD -> main, tag: 2.21.0)",
+                false,
+                Some(&links)
+            ),
+            format!(
+                "{YELLOW}commit {} ({BOLD}{CYAN}HEAD -> {GREEN}{}{NORMAL_INTENSITY}{YELLOW}, {BOLD}tag: {}{NORMAL_INTENSITY}{YELLOW}){NORMAL}",
+                link("62da46c"),
+                link("main"),
+                link("2.21.0"),
+            )
+        );
+        assert_eq!(
+            format_commit_line("commit 62da46c", false, Some(&links)),
+            format!("{YELLOW}commit {}{NORMAL}", link("62da46c"))
+        );
     }
 }
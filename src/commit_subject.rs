@@ -0,0 +1,160 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::constants::*;
+use crate::issue_links::{link_issues, IssueLink};
+
+// Highlight the first line of commit messages in `git log` output
+
+/// Subjects like `feat(parser)!: Add a thing`. Types are lowercase in
+/// practice, and requiring that keeps us from matching things like `Note:`.
+static CONVENTIONAL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<type>[a-z]+)(?:\((?P<scope>[^()]*)\))?(?P<breaking>!)?: (?P<description>.+)$")
+        .unwrap()
+});
+
+/// Longer subjects get truncated in many places, like on GitHub
+const MAX_SUBJECT_LENGTH: usize = 72;
+
+/// A Conventional Commits subject line, see
+/// <https://www.conventionalcommits.org/>
+#[derive(Debug, PartialEq)]
+struct ConventionalSubject<'a> {
+    kind: &'a str,
+    scope: Option<&'a str>,
+    breaking: bool,
+    description: &'a str,
+}
+
+impl<'a> ConventionalSubject<'a> {
+    fn parse(subject: &'a str) -> Option<Self> {
+        let captures = CONVENTIONAL.captures(subject)?;
+        return Some(ConventionalSubject {
+            kind: captures.name("type").unwrap().as_str(),
+            scope: captures.name("scope").map(|scope| scope.as_str()),
+            breaking: captures.name("breaking").is_some(),
+            description: captures.name("description").unwrap().as_str(),
+        });
+    }
+
+    fn render(&self, issue_links: &[IssueLink]) -> String {
+        let mut rendered = format!("{BOLD}{}{NORMAL_INTENSITY}", self.kind);
+        if let Some(scope) = self.scope {
+            rendered.push_str(&format!("({CYAN}{scope}{DEFAULT_COLOR})"));
+        }
+        if self.breaking {
+            rendered.push_str(&format!("{BOLD}{RED}!{DEFAULT_COLOR}{NORMAL_INTENSITY}"));
+        }
+        rendered.push_str(": ");
+        rendered.push_str(&link_issues(self.description, issue_links));
+        return rendered;
+    }
+}
+
+/// `line` is the first commit message line, indented by four spaces. With
+/// `lint` set, style problems are noted at the end of the line.
+pub(crate) fn format_subject(line: &str, issue_links: &[IssueLink], lint: bool) -> String {
+    let subject = line.trim_start();
+    let indentation = &line[..line.len() - subject.len()];
+
+    let mut rendered = indentation.to_string();
+    match ConventionalSubject::parse(subject) {
+        Some(conventional) => rendered.push_str(&conventional.render(issue_links)),
+        None => rendered.push_str(&link_issues(subject, issue_links)),
+    }
+
+    let problems = if lint {
+        subject_problems(subject)
+    } else {
+        vec![]
+    };
+    if !problems.is_empty() {
+        rendered.push_str(&lint_note(&problems));
+    }
+    return rendered;
+}
+
+/// For `--lint-commit-messages`, appended to lines with problems
+pub(crate) fn lint_note(problems: &[String]) -> String {
+    return format!("  {YELLOW}← {}{NORMAL}", problems.join(", "));
+}
+
+fn subject_problems(subject: &str) -> Vec<String> {
+    let mut problems = vec![];
+
+    if subject.chars().count() > MAX_SUBJECT_LENGTH {
+        problems.push(format!("longer than {MAX_SUBJECT_LENGTH} characters"));
+    }
+
+    // "..." is fine, it's not a period
+    if subject.ends_with('.') && !subject.ends_with("..") {
+        problems.push("ends with a period".to_string());
+    }
+
+    return problems;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_conventional() {
+        assert_eq!(
+            ConventionalSubject::parse("feat(parser)!: Parse everything"),
+            Some(ConventionalSubject {
+                kind: "feat",
+                scope: Some("parser"),
+                breaking: true,
+                description: "Parse everything",
+            })
+        );
+        assert_eq!(
+            ConventionalSubject::parse("fix: Don't crash"),
+            Some(ConventionalSubject {
+                kind: "fix",
+                scope: None,
+                breaking: false,
+                description: "Don't crash",
+            })
+        );
+        assert_eq!(ConventionalSubject::parse("Note: Not conventional"), None);
+        assert_eq!(ConventionalSubject::parse("Fix the parser"), None);
+    }
+
+    #[test]
+    fn test_format_subject() {
+        assert_eq!(
+            format_subject("    feat(parser)!: Parse everything", &[], false),
+            format!(
+                "    {BOLD}feat{NORMAL_INTENSITY}({CYAN}parser{DEFAULT_COLOR}){BOLD}{RED}!{DEFAULT_COLOR}{NORMAL_INTENSITY}: Parse everything"
+            )
+        );
+        assert_eq!(
+            format_subject("    Fix the parser.", &[], false),
+            "    Fix the parser."
+        );
+        assert_eq!(
+            format_subject("    Fix the parser.", &[], true),
+            format!("    Fix the parser.  {YELLOW}← ends with a period{NORMAL}")
+        );
+    }
+
+    #[test]
+    fn test_subject_problems() {
+        assert!(subject_problems("Fix the parser").is_empty());
+        assert!(subject_problems("Fix the parser...").is_empty());
+        assert!(subject_problems(&"x".repeat(72)).is_empty());
+
+        assert_eq!(
+            subject_problems(&format!("{}.", "x".repeat(72))),
+            vec![
+                "longer than 72 characters".to_string(),
+                "ends with a period".to_string()
+            ]
+        );
+    }
+}
@@ -4,10 +4,12 @@ use threadpool::ThreadPool;
 
 use crate::constants::{GREEN, INVERSE_VIDEO, NORMAL};
 use crate::lines_highlighter::{LineAcceptance, LinesHighlighter, Response};
+use crate::refiner::Formatter;
 use crate::string_future::StringFuture;
+use crate::token_collector;
 use crate::token_collector::{Style, LINE_STYLE_CONFLICT_OLD};
 use crate::token_collector::{LINE_STYLE_CONFLICT_BASE, LINE_STYLE_CONFLICT_NEW};
-use crate::{refiner, token_collector};
+use crate::tokenizer::Profile;
 
 const CONFLICTS_HEADER1: &str = "<<<<<<<";
 const CONFLICTS_HEADER2: &str = "++<<<<<<<";
@@ -43,6 +45,8 @@ pub(crate) struct ConflictsHighlighter {
 
     /// `>>>>>>> branch`, marks the end of `c2` and the whole conflict
     footer: String,
+
+    formatter: Formatter,
 }
 
 impl LinesHighlighter for ConflictsHighlighter {
@@ -142,7 +146,7 @@ impl ConflictsHighlighter {
     /// Create a new LinesHighlighter from a line of input.
     ///
     /// Returns None if this line doesn't start a new LinesHighlighter.
-    pub(crate) fn from_line(line: &str) -> Option<Self>
+    pub(crate) fn from_line(line: &str, formatter: Formatter) -> Option<Self>
     where
         Self: Sized,
     {
@@ -159,6 +163,10 @@ impl ConflictsHighlighter {
             base: String::new(),
             base_line_prefixes: Vec::new(),
             c2: String::new(),
+
+            // Conflict sections often span half statements and comments, so
+            // the language specific tokenizers don't help there
+            formatter: formatter.with_tokenizer_profile(Profile::Generic),
         });
     }
 
@@ -192,11 +200,12 @@ impl ConflictsHighlighter {
         let c2_header = self.c2_header.clone();
         let c2 = self.c2.clone();
         let footer = self.footer.clone();
+        let formatter = self.formatter.clone();
         return StringFuture::from_function(
             move || {
                 let c1_or_newline = if c1.is_empty() { "\n" } else { &c1 };
                 let c2_or_newline = if c2.is_empty() { "\n" } else { &c2 };
-                let (c1_tokens, c2_tokens) = refiner::diff(c1_or_newline, c2_or_newline);
+                let (c1_tokens, c2_tokens) = formatter.diff(c1_or_newline, c2_or_newline);
 
                 let c1_style = if base_header.is_empty() {
                     LINE_STYLE_CONFLICT_OLD.clone()
@@ -263,6 +272,7 @@ impl ConflictsHighlighter {
         let c2_header = self.c2_header.clone();
         let c2 = self.c2.clone();
         let footer = self.footer.clone();
+        let formatter = self.formatter.clone();
 
         return StringFuture::from_function(
             move || {
@@ -270,7 +280,7 @@ impl ConflictsHighlighter {
 
                 let c1_or_newline = if c1.is_empty() { "\n" } else { &c1 };
                 let (mut base_vs_c1_tokens, c1_tokens) =
-                    refiner::diff(base_or_newline, c1_or_newline);
+                    formatter.diff(base_or_newline, c1_or_newline);
                 if c1.is_empty() {
                     // In the base, show only diffs vs c2
                     base_vs_c1_tokens.iter_mut().for_each(|token| {
@@ -282,7 +292,7 @@ impl ConflictsHighlighter {
 
                 let c2_or_newline = if c2.is_empty() { "\n" } else { &c2 };
                 let (mut base_vs_c2_tokens, c2_tokens) =
-                    refiner::diff(base_or_newline, c2_or_newline);
+                    formatter.diff(base_or_newline, c2_or_newline);
                 if c2.is_empty() {
                     // In the base, show only diffs vs c1
                     base_vs_c2_tokens.iter_mut().for_each(|token| {
@@ -1,9 +1,13 @@
 pub const OLD: &str = "\x1b[31m"; // Red
 pub const PARSE_ERROR: &str = "\x1b[33m\x1b[7m"; // Inverse yellow
+pub const EVIL_MERGE: &str = "\x1b[1m\x1b[35m\x1b[7m"; // Bold inverse magenta
 
 pub const INVERSE_VIDEO: &str = "\x1b[7m";
 pub const NO_INVERSE_VIDEO: &str = "\x1b[27m";
 
+pub const UNDERLINE: &str = "\x1b[4m";
+pub const NO_UNDERLINE: &str = "\x1b[24m";
+
 pub const NO_EOF_NEWLINE_COLOR: &str = "\x1b[2m"; // Faint
 
 pub const BOLD: &str = "\x1b[1m";
@@ -15,6 +19,8 @@ pub const YELLOW: &str = "\x1b[33m";
 pub const GREEN: &str = "\x1b[32m";
 pub const CYAN: &str = "\x1b[36m";
 pub const RED: &str = "\x1b[31m";
+pub const BLUE: &str = "\x1b[34m";
+pub const MAGENTA: &str = "\x1b[35m";
 
 // Dark blue: https://en.wikipedia.org/wiki/ANSI_escape_code#8-bit
 pub const BLUE_TO_END_OF_LINE: &str = "\x1b[48;5;17m\x1b[0K";
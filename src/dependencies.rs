@@ -0,0 +1,635 @@
+use std::collections::BTreeMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::constants::{BOLD, FAINT, GREEN, NORMAL, RED, YELLOW};
+use crate::hunk_header::HunkHeader;
+use crate::key_path::json_hunk_line_paths;
+
+/// `name = "1.2.3"` in `Cargo.toml` or `Cargo.lock`
+static TOML_STRING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\s*([A-Za-z0-9_.-]+)\s*=\s*"([^"]*)""#).unwrap());
+
+/// `name = { version = "1.2.3", ... }` in `Cargo.toml`
+static TOML_TABLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^\s*([A-Za-z0-9_-]+)\s*=\s*\{(?:.*[\s,{])?version\s*=\s*"([^"]*)""#).unwrap()
+});
+
+/// `name = { path = "../name" }` or `name = { git = "..." }` in
+/// `Cargo.toml`, no version
+static TOML_UNVERSIONED_TABLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^\s*([A-Za-z0-9_-]+)\s*=\s*\{(?:.*[\s,{])?(?:path|git|workspace)\s*="#).unwrap()
+});
+
+/// `"name": "^1.2.3"` in `package.json`
+static JSON_STRING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\s*"([^"]+)"\s*:\s*"([^"]*)""#).unwrap());
+
+/// `name==1.2.3`, `name[extra]>=1.2` or just `name` in `requirements.txt`
+static REQUIREMENT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^([A-Za-z0-9][A-Za-z0-9_.-]*)(?:\[[^\]]*\])?\s*((?:===|==|~=|!=|>=|<=|>|<)[^;#]*)?",
+    )
+    .unwrap()
+});
+
+/// `require example.com/module v1.2.3` or just `example.com/module v1.2.3`
+/// inside of a `require ( ... )` block in `go.mod`
+static GO_REQUIREMENT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:require\s+)?([A-Za-z0-9][^\s]*[./][^\s]*)\s+(v[^\s]+)").unwrap()
+});
+
+/// Manifest and lock files we can list dependency changes for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ManifestFormat {
+    CargoToml,
+    CargoLock,
+    PackageJson,
+    Requirements,
+    GoMod,
+}
+
+impl ManifestFormat {
+    /// `path` is a file name from a `---` or `+++` line, without any
+    /// timestamp.
+    pub(crate) fn from_filename(path: &str) -> Option<Self> {
+        let basename = path.rsplit('/').next().unwrap_or(path);
+        return match basename {
+            "Cargo.toml" => Some(ManifestFormat::CargoToml),
+            "Cargo.lock" => Some(ManifestFormat::CargoLock),
+            "package.json" => Some(ManifestFormat::PackageJson),
+            "go.mod" => Some(ManifestFormat::GoMod),
+            _ if basename.starts_with("requirements") && basename.ends_with(".txt") => {
+                Some(ManifestFormat::Requirements)
+            }
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DependencyChange {
+    Added {
+        name: String,
+        version: String,
+    },
+    Removed {
+        name: String,
+        version: String,
+    },
+    Changed {
+        name: String,
+        old: String,
+        new: String,
+    },
+}
+
+/// One two-version hunk, with its lines prefixed by `-`, `+` or ` `
+#[derive(Debug)]
+struct Hunk {
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<String>,
+}
+
+/// Collects the hunks of a manifest or lock file, so that we can list the
+/// dependency changes at the end of the file section.
+#[derive(Debug)]
+pub(crate) struct DependencyCollector {
+    format: ManifestFormat,
+    hunks: Vec<Hunk>,
+
+    /// False while in a hunk we can't parse, like a merge hunk
+    collecting: bool,
+}
+
+impl DependencyCollector {
+    pub(crate) fn new(format: ManifestFormat) -> Self {
+        return DependencyCollector {
+            format,
+            hunks: Vec::new(),
+            collecting: false,
+        };
+    }
+
+    /// Hunk headers and hunk lines of the file, in order
+    pub(crate) fn consume_line(&mut self, line: &str) {
+        if let Some(hunk_header) = HunkHeader::parse(line) {
+            // Merge diffs aren't supported
+            self.collecting = hunk_header.linecounts.len() == 2;
+            if self.collecting {
+                self.hunks.push(Hunk {
+                    old_start: hunk_header.old_start(),
+                    new_start: hunk_header.new_start(),
+                    lines: Vec::new(),
+                });
+            }
+            return;
+        }
+
+        if !self.collecting || !line.starts_with(['-', '+', ' ']) {
+            return;
+        }
+        if let Some(hunk) = self.hunks.last_mut() {
+            hunk.lines.push(line.to_string());
+        }
+    }
+
+    pub(crate) fn changes(&self) -> Vec<DependencyChange> {
+        let mut old: Vec<(String, String)> = Vec::new();
+        let mut new: Vec<(String, String)> = Vec::new();
+        for hunk in &self.hunks {
+            let paths = if self.format == ManifestFormat::PackageJson {
+                json_hunk_line_paths(&hunk.lines, hunk.old_start <= 1, hunk.new_start <= 1)
+            } else {
+                vec![String::new(); hunk.lines.len()]
+            };
+
+            for (prefix, side) in [('-', &mut old), ('+', &mut new)] {
+                let side_lines = hunk
+                    .lines
+                    .iter()
+                    .zip(&paths)
+                    .filter(|(line, _)| line.starts_with([prefix, ' ']))
+                    .map(|(line, path)| SideLine {
+                        text: &line[1..],
+                        changed: line.starts_with(prefix),
+                        path,
+                    })
+                    .collect::<Vec<_>>();
+                side.extend(changed_dependencies(self.format, &side_lines));
+            }
+        }
+
+        return compare(old, new);
+    }
+}
+
+/// One line of either the old or the new side of a hunk
+struct SideLine<'a> {
+    text: &'a str,
+
+    /// True for `-` lines on the old side and `+` lines on the new side
+    changed: bool,
+
+    /// The JSON key path of this line, for `package.json`
+    path: &'a str,
+}
+
+/// Names and versions of the dependencies on changed lines
+fn changed_dependencies(format: ManifestFormat, lines: &[SideLine]) -> Vec<(String, String)> {
+    let mut dependencies = Vec::new();
+
+    // Cargo.toml section, Cargo.lock package name or go.mod block, and
+    // whether that line changed
+    let mut current: Option<(String, bool)> = None;
+
+    for line in lines {
+        match format {
+            ManifestFormat::CargoToml => {
+                let text = line.text.trim();
+                if let Some(section) = text.strip_prefix('[') {
+                    let section = section.trim_end_matches(']').trim();
+                    current = Some((section.to_string(), line.changed));
+                    continue;
+                }
+                if !line.changed {
+                    continue;
+                }
+
+                let section = current.as_ref().map(|(section, _)| section.as_str());
+                if let Some(dependency) = cargo_toml_dependency(section, text) {
+                    dependencies.push(dependency);
+                }
+            }
+            ManifestFormat::CargoLock => {
+                if line.text.trim() == "[[package]]" {
+                    current = None;
+                    continue;
+                }
+                let Some(captures) = TOML_STRING.captures(line.text) else {
+                    continue;
+                };
+                match &captures[1] {
+                    "name" => current = Some((captures[2].to_string(), line.changed)),
+                    "version" => {
+                        if let Some((name, name_changed)) = &current {
+                            if *name_changed || line.changed {
+                                dependencies.push((name.clone(), captures[2].to_string()));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            ManifestFormat::PackageJson => {
+                if !line.changed {
+                    continue;
+                }
+                if let Some(dependency) = package_json_dependency(line.path, line.text) {
+                    dependencies.push(dependency);
+                }
+            }
+            ManifestFormat::Requirements => {
+                let text = line.text.trim();
+                if !line.changed || text.starts_with(['#', '-']) {
+                    continue;
+                }
+                if let Some(captures) = REQUIREMENT.captures(text) {
+                    let version = captures.get(2).map_or("", |version| version.as_str());
+                    let version = version.trim().strip_prefix("==").unwrap_or(version.trim());
+                    dependencies.push((captures[1].to_string(), version.to_string()));
+                }
+            }
+            ManifestFormat::GoMod => {
+                let text = line.text.trim();
+                if let Some(block) = text.strip_suffix('(') {
+                    current = Some((block.trim().to_string(), line.changed));
+                    continue;
+                }
+                if text == ")" {
+                    current = None;
+                    continue;
+                }
+                if !line.changed {
+                    continue;
+                }
+
+                // Outside of blocks, or in blocks that started before the
+                // hunk, only requirements have versions and no arrows
+                let in_require_block = match &current {
+                    Some((block, _)) => block == "require",
+                    None => !text.contains("=>"),
+                };
+                if !in_require_block && !text.starts_with("require ") {
+                    continue;
+                }
+                if let Some(captures) = GO_REQUIREMENT.captures(text) {
+                    dependencies.push((captures[1].to_string(), captures[2].to_string()));
+                }
+            }
+        }
+    }
+
+    return dependencies;
+}
+
+/// `section` is the name of the current `[section]`, if we have seen it.
+///
+/// Outside of known sections, only inline tables saying where to get the
+/// dependency from count. Plain `key = "value"` lines could be from any
+/// section, like `[package]`.
+fn cargo_toml_dependency(section: Option<&str>, text: &str) -> Option<(String, String)> {
+    if let Some(section) = section {
+        if let Some(name) = section
+            .rsplit_once('.')
+            .filter(|(table, _)| table.ends_with("dependencies"))
+            .map(|(_, name)| name)
+        {
+            // [dependencies.name] tables
+            let captures = TOML_STRING.captures(text)?;
+            return (&captures[1] == "version")
+                .then(|| (name.to_string(), captures[2].to_string()));
+        }
+        if !section.ends_with("dependencies") {
+            return None;
+        }
+    }
+
+    if let Some(captures) = TOML_TABLE.captures(text) {
+        return Some((captures[1].to_string(), captures[2].to_string()));
+    }
+    if let Some(captures) = TOML_UNVERSIONED_TABLE.captures(text) {
+        return Some((captures[1].to_string(), String::new()));
+    }
+    // Plain strings only count inside of known dependency sections
+    section?;
+    let captures = TOML_STRING.captures(text)?;
+    return Some((captures[1].to_string(), captures[2].to_string()));
+}
+
+fn package_json_dependency(path: &str, text: &str) -> Option<(String, String)> {
+    let captures = JSON_STRING.captures(text)?;
+    let name = &captures[1];
+    let version = &captures[2];
+
+    let parent = path.strip_suffix(name)?;
+    let parent = parent.strip_suffix('.').unwrap_or(parent);
+    let parent_key = parent.rsplit('.').next().unwrap_or(parent);
+    let is_dependency = if parent_key == "…" {
+        // We don't know where we are, go by what the version looks like
+        version.starts_with(['^', '~']) && !["node", "npm"].contains(&name)
+    } else {
+        parent_key.ends_with("ependencies")
+    };
+    if !is_dependency {
+        return None;
+    }
+    return Some((name.to_string(), version.to_string()));
+}
+
+/// Dependencies that are on the same line on both sides didn't change
+fn compare(old: Vec<(String, String)>, new: Vec<(String, String)>) -> Vec<DependencyChange> {
+    // Name to old and new versions
+    let mut versions: BTreeMap<String, (Vec<String>, Vec<String>)> = BTreeMap::new();
+    for (name, version) in old {
+        versions.entry(name).or_default().0.push(version);
+    }
+    for (name, version) in new {
+        versions.entry(name).or_default().1.push(version);
+    }
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for (name, (mut old_versions, mut new_versions)) in versions {
+        old_versions.retain(|version| {
+            let Some(index) = new_versions.iter().position(|new| new == version) else {
+                return true;
+            };
+            new_versions.remove(index);
+            return false;
+        });
+
+        if let ([old], [new]) = (old_versions.as_slice(), new_versions.as_slice()) {
+            changed.push(DependencyChange::Changed {
+                name,
+                old: old.clone(),
+                new: new.clone(),
+            });
+            continue;
+        }
+        for version in old_versions {
+            removed.push(DependencyChange::Removed {
+                name: name.clone(),
+                version,
+            });
+        }
+        for version in new_versions {
+            added.push(DependencyChange::Added {
+                name: name.clone(),
+                version,
+            });
+        }
+    }
+
+    added.extend(removed);
+    added.extend(changed);
+    return added;
+}
+
+/// Render a table of dependency changes to put at the end of a file section.
+/// Returns an empty string if there are no changes.
+pub(crate) fn render_dependency_table(changes: &[DependencyChange]) -> String {
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let name_width = changes
+        .iter()
+        .map(|change| match change {
+            DependencyChange::Added { name, .. }
+            | DependencyChange::Removed { name, .. }
+            | DependencyChange::Changed { name, .. } => name.chars().count(),
+        })
+        .max()
+        .unwrap_or_default();
+
+    let mut rendered = format!("{BOLD}Dependencies:{NORMAL}\n");
+    for change in changes {
+        let (color, label, name, versions) = match change {
+            DependencyChange::Added { name, version } => (GREEN, "added", name, version.clone()),
+            DependencyChange::Removed { name, version } => (RED, "removed", name, version.clone()),
+            DependencyChange::Changed { name, old, new } => {
+                let label = if is_downgrade(old, new) {
+                    "downgraded"
+                } else {
+                    "upgraded"
+                };
+                (YELLOW, label, name, format!("{old} → {new}"))
+            }
+        };
+        rendered.push_str(&format!(
+            "  {color}{label:<10}{NORMAL} {name:<name_width$}  {FAINT}{versions}{NORMAL}\n"
+        ));
+    }
+
+    return rendered;
+}
+
+/// Compares the numbers in two version strings, like `^1.2.10` and `1.2.9`
+fn is_downgrade(old: &str, new: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        return version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|number| number.parse().ok())
+            .collect();
+    };
+    return numbers(new) < numbers(old);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    fn changes(format: ManifestFormat, lines: &[&str]) -> Vec<DependencyChange> {
+        let mut collector = DependencyCollector::new(format);
+        for line in lines {
+            collector.consume_line(line);
+        }
+        return collector.changes();
+    }
+
+    fn changed(name: &str, old: &str, new: &str) -> DependencyChange {
+        return DependencyChange::Changed {
+            name: name.to_string(),
+            old: old.to_string(),
+            new: new.to_string(),
+        };
+    }
+
+    fn added(name: &str, version: &str) -> DependencyChange {
+        return DependencyChange::Added {
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+    }
+
+    fn removed(name: &str, version: &str) -> DependencyChange {
+        return DependencyChange::Removed {
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+    }
+
+    #[test]
+    fn test_cargo_toml() {
+        let lines = [
+            "@@ -1,12 +1,12 @@",
+            " [package]",
+            " name = \"riffdiff\"",
+            "-version = \"3.3.0\"",
+            "+version = \"3.4.0\"",
+            " ",
+            " [dependencies]",
+            "-serde = { version = \"1.0.190\", features = [\"derive\"] }",
+            "+serde = { version = \"1.0.195\", features = [\"derive\"] }",
+            "-log = \"0.4.20\"",
+            "+tokio = \"1.35.0\"",
+            "+helper = { path = \"../helper\" }",
+            " ",
+            " [dependencies.regex]",
+            "-version = \"1.10\"",
+            "+version = \"1.9\"",
+            "@@ -30,3 +30,3 @@",
+            "-edition = \"2018\"",
+            "+edition = \"2021\"",
+            "-threadpool = { version = \"1.8.0\" }",
+            "+threadpool = { version = \"1.8.1\" }",
+            "-description = \"Diff filter\"",
+            "+description = \"Diff filter highlighting changes\"",
+            "-bytes = \"1.5\"",
+            "+bytes = \"1.6\"",
+        ];
+        assert_eq!(
+            changes(ManifestFormat::CargoToml, &lines),
+            vec![
+                added("helper", ""),
+                added("tokio", "1.35.0"),
+                removed("log", "0.4.20"),
+                changed("regex", "1.10", "1.9"),
+                changed("serde", "1.0.190", "1.0.195"),
+                changed("threadpool", "1.8.0", "1.8.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cargo_lock() {
+        let lines = [
+            "@@ -10,12 +10,16 @@",
+            " [[package]]",
+            " name = \"serde\"",
+            "-version = \"1.0.190\"",
+            "+version = \"1.0.195\"",
+            " source = \"registry+https://github.com/rust-lang/crates.io-index\"",
+            "-checksum = \"91d3c334\"",
+            "+checksum = \"63261df4\"",
+            " ",
+            "+[[package]]",
+            "+name = \"tokio\"",
+            "+version = \"1.35.0\"",
+            "+",
+            " [[package]]",
+            " name = \"unicode-width\"",
+            " version = \"0.1.11\"",
+        ];
+        assert_eq!(
+            changes(ManifestFormat::CargoLock, &lines),
+            vec![
+                added("tokio", "1.35.0"),
+                changed("serde", "1.0.190", "1.0.195")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_package_json() {
+        let lines = [
+            "@@ -1,10 +1,10 @@",
+            " {",
+            "   \"name\": \"app\",",
+            "-  \"version\": \"1.0.0\",",
+            "+  \"version\": \"1.1.0\",",
+            "   \"dependencies\": {",
+            "-    \"react\": \"^18.2.0\",",
+            "+    \"react\": \"^18.3.1\",",
+            "     \"lodash\": \"^4.17.21\"",
+            "   },",
+            "   \"devDependencies\": {",
+            "+    \"@types/node\": \"^20.0.0\",",
+            "     \"typescript\": \"^5.0.0\"",
+            "@@ -30,3 +30,3 @@",
+            "-    \"left-pad\": \"^1.3.0\",",
+            "     \"webpack\": \"^5.0.0\"",
+        ];
+        assert_eq!(
+            changes(ManifestFormat::PackageJson, &lines),
+            vec![
+                added("@types/node", "^20.0.0"),
+                removed("left-pad", "^1.3.0"),
+                changed("react", "^18.2.0", "^18.3.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requirements() {
+        let lines = [
+            "@@ -1,5 +1,5 @@",
+            " # Pinned",
+            "-requests==2.31.0",
+            "+requests==2.32.0",
+            "-Django>=4.2",
+            "+uvicorn[standard]",
+            " -r common.txt",
+        ];
+        assert_eq!(
+            changes(ManifestFormat::Requirements, &lines),
+            vec![
+                added("uvicorn", ""),
+                removed("Django", ">=4.2"),
+                changed("requests", "2.31.0", "2.32.0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_go_mod() {
+        let lines = [
+            "@@ -1,8 +1,8 @@",
+            " module example.com/app",
+            " ",
+            "-go 1.21",
+            "+go 1.22",
+            " ",
+            " require (",
+            "-\tgithub.com/pkg/errors v0.9.0",
+            "+\tgithub.com/pkg/errors v0.9.1",
+            "+\tgolang.org/x/text v0.14.0 // indirect",
+            " )",
+        ];
+        assert_eq!(
+            changes(ManifestFormat::GoMod, &lines),
+            vec![
+                added("golang.org/x/text", "v0.14.0"),
+                changed("github.com/pkg/errors", "v0.9.0", "v0.9.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_downgrade() {
+        assert!(!is_downgrade("1.0.190", "1.0.195"));
+        assert!(is_downgrade("1.10", "1.9"));
+        assert!(!is_downgrade("^18.2.0", "^18.3.1"));
+        assert!(!is_downgrade("1.0", "1.0.1"));
+    }
+
+    #[test]
+    fn test_from_filename() {
+        assert_eq!(
+            ManifestFormat::from_filename("b/Cargo.toml"),
+            Some(ManifestFormat::CargoToml)
+        );
+        assert_eq!(
+            ManifestFormat::from_filename("b/requirements-dev.txt"),
+            Some(ManifestFormat::Requirements)
+        );
+        assert_eq!(ManifestFormat::from_filename("b/Cargo.tomlx"), None);
+    }
+}
@@ -4,16 +4,26 @@ use threadpool::ThreadPool;
 
 use crate::lines_highlighter::LineAcceptance;
 use crate::lines_highlighter::{LinesHighlighter, Response};
-use crate::refiner::diff;
 use crate::string_future::StringFuture;
 use crate::token_collector::{
     render, Style, StyledToken, LINE_STYLE_NEW_FILENAME, LINE_STYLE_OLD_FILENAME,
 };
+use crate::tokenizer::Profile;
 
+use crate::constants::{BOLD, EVIL_MERGE, FAINT, NORMAL};
+use crate::dependencies::{render_dependency_table, DependencyCollector, ManifestFormat};
+use crate::generated::is_generated;
 use crate::hunk_header::HunkHeader;
 use crate::hunk_highlighter::HunkLinesHighlighter;
+use crate::hyperlink::{FileLink, Hyperlinker};
+use crate::key_path::{KeyPathCollector, StructuredFormat};
+use crate::plusminus_lines_highlighter::is_evil_merge_prefix;
 use crate::refiner::Formatter;
 
+/// Holding back output breaks streaming, so manifest and lock file sections
+/// longer than this get their dependency table at the end instead
+const MAX_HELD_BACK_LINES: usize = 10_000;
+
 pub(crate) struct FileHighlighter {
     /// May or may not end with one or more tabs + a timestamp string.
     old_name: String,
@@ -27,8 +37,45 @@ pub(crate) struct FileHighlighter {
     /// Current hunk highlighter, if any
     sub_highlighter: Option<Box<dyn LinesHighlighter>>,
 
-    /// URL to the file we're currently highlighting, if any
-    url: Option<url::Url>,
+    /// Link to the file we're currently highlighting, if it exists
+    link: Option<FileLink>,
+
+    /// Set for JSON, YAML, CSV and TSV files, see `KeyPathCollector`
+    structured_format: Option<StructuredFormat>,
+
+    /// For CSV and TSV files, the first line of the file once we have seen
+    /// it. Used for naming changed columns in later hunks.
+    header_row: Option<String>,
+
+    /// Set while the first line of the file is still to come in the current
+    /// hunk
+    expecting_header_row: bool,
+
+    /// Set for generated files and lock files, which we summarize in one
+    /// line rather than show. Counts added and removed lines.
+    collapsed: Option<(usize, usize)>,
+
+    /// Set for manifest and lock files, see `DependencyCollector`
+    dependencies: Option<DependencyCollector>,
+
+    /// Added and removed lines in merge diffs that are in neither parent, see
+    /// `plusminus_lines_highlighter::is_evil_merge_prefix()`
+    evil_merge: (usize, usize),
+
+    /// Set between unresolved conflict markers, where lines are in neither
+    /// parent without being evil merges
+    in_conflict: bool,
+
+    /// With `dependencies` set, the file section is held back until we know
+    /// what to put in the dependency table at its top. See
+    /// `MAX_HELD_BACK_LINES`.
+    held_back: Option<Vec<StringFuture>>,
+
+    /// How many lines `held_back` was made from
+    held_back_lines: usize,
+
+    /// Length of the `-`, `+` or ` ` prefix of the lines in the current hunk
+    prefix_length: usize,
 
     /// Set once the `--- ` / `+++ ` header has been emitted.
     ///
@@ -50,13 +97,90 @@ fn without_timestamp(name: &str) -> &str {
 
 impl LinesHighlighter for FileHighlighter {
     fn consume_line(&mut self, line: &str, thread_pool: &ThreadPool) -> Result<Response, String> {
+        let mut response = self.consume_line_unbuffered(line, thread_pool)?;
+        let Some(dependencies) = self.dependencies.as_mut() else {
+            return Ok(response);
+        };
+
+        let done = response.line_accepted == LineAcceptance::RejectedDone;
+        if self.header_rendered && !done {
+            dependencies.consume_line(line);
+        }
+
+        let Some(held_back) = self.held_back.as_mut() else {
+            // Too long to hold back, the table goes at the end instead
+            if done {
+                response.highlighted.extend(self.dependency_table());
+            }
+            return Ok(response);
+        };
+
+        held_back.append(&mut response.highlighted);
+        self.held_back_lines += 1;
+        if done {
+            response.highlighted = self.release_held_back();
+        } else if self.held_back_lines > MAX_HELD_BACK_LINES {
+            response.highlighted = self.held_back.take().unwrap_or_default();
+        }
+        return Ok(response);
+    }
+
+    fn consume_eof(&mut self, thread_pool: &ThreadPool) -> Result<Vec<StringFuture>, String> {
+        let mut highlights = self.consume_eof_unbuffered(thread_pool)?;
+        let Some(held_back) = self.held_back.as_mut() else {
+            highlights.extend(self.dependency_table());
+            return Ok(highlights);
+        };
+
+        held_back.append(&mut highlights);
+        return Ok(self.release_held_back());
+    }
+}
+
+impl FileHighlighter {
+    fn consume_line_unbuffered(
+        &mut self,
+        line: &str,
+        thread_pool: &ThreadPool,
+    ) -> Result<Response, String> {
         assert!(!self.old_name.is_empty());
 
         if self.new_name.is_empty() {
             // Header phase: waiting for +++
             if let Some(new_name) = line.strip_prefix("+++ ") {
                 self.new_name.push_str(new_name);
-                self.url = hyperlink_filename(without_timestamp(new_name));
+                self.link = hyperlink_filename(without_timestamp(new_name))
+                    .map(|url| self.formatter.hyperlinker().file_link(&url));
+
+                // Deleted files are named after the old name
+                let name = if without_timestamp(new_name) == "/dev/null" {
+                    &self.old_name
+                } else {
+                    new_name
+                };
+                if let Some(profile) = self.formatter.notebook_cells() {
+                    // Rendered cells rather than notebook JSON
+                    self.formatter = self.formatter.with_tokenizer_profile(profile);
+                    self.structured_format = None;
+                } else {
+                    self.formatter = self
+                        .formatter
+                        .with_tokenizer_profile(Profile::from_filename(without_timestamp(name)));
+                    self.structured_format =
+                        StructuredFormat::from_filename(without_timestamp(name));
+                }
+                if self.formatter.collapse_generated() {
+                    let file = hyperlink_filename(without_timestamp(name))
+                        .and_then(|url| url.to_file_path().ok());
+                    if is_generated(without_timestamp(name), file.as_deref()) {
+                        self.collapsed = Some((0, 0));
+                    }
+                }
+                self.dependencies = ManifestFormat::from_filename(without_timestamp(name))
+                    .map(DependencyCollector::new);
+                if self.dependencies.is_some() {
+                    self.held_back = Some(Vec::new());
+                }
                 return Ok(Response {
                     line_accepted: LineAcceptance::AcceptedWantMore,
                     // The header itself is rendered from the next line, once
@@ -80,31 +204,51 @@ impl LinesHighlighter for FileHighlighter {
                 Some(hunk_header) => Some(hunk_header.first_modified_line()?),
                 None => None,
             };
-            highlights.push(StringFuture::from_string(self.highlighted(first_hunk_line)));
+            if self.collapsed.is_none() {
+                highlights.push(StringFuture::from_string(self.highlighted(first_hunk_line)));
+            }
             self.header_rendered = true;
 
             return match parsed_hunk_header {
                 Some(hunk_header) => {
-                    self.sub_highlighter = Some(Box::new(HunkLinesHighlighter::from_parsed(
-                        hunk_header,
-                        self.formatter.clone(),
-                        &self.url,
-                    )?));
+                    self.start_hunk(hunk_header)?;
                     Ok(Response {
                         line_accepted: LineAcceptance::AcceptedWantMore,
                         highlighted: highlights,
                     })
                 }
-                None => Ok(Response {
-                    line_accepted: LineAcceptance::RejectedDone,
-                    highlighted: highlights,
-                }),
+                None => {
+                    highlights.extend(self.collapsed_summary());
+                    Ok(Response {
+                        line_accepted: LineAcceptance::RejectedDone,
+                        highlighted: highlights,
+                    })
+                }
             };
         }
 
+        if self.expecting_header_row && self.sub_highlighter.is_some() {
+            if let Some(header_row) = line.strip_prefix([' ', '+']) {
+                self.header_row = Some(header_row.to_string());
+                self.expecting_header_row = false;
+            }
+        }
+
         if let Some(ref mut highlighter) = self.sub_highlighter {
             let resp = highlighter.consume_line(line, thread_pool)?;
             highlights.extend(resp.highlighted);
+            let accepted = resp.line_accepted != LineAcceptance::RejectedDone;
+            if let Some((added, removed)) = self.collapsed.as_mut().filter(|_| accepted) {
+                let prefix = line.get(..self.prefix_length).unwrap_or_default();
+                if prefix.contains('+') {
+                    *added += 1;
+                } else if prefix.contains('-') {
+                    *removed += 1;
+                }
+            }
+            if accepted {
+                self.count_evil_merge_line(line);
+            }
             match resp.line_accepted {
                 LineAcceptance::AcceptedWantMore => {
                     return Ok(Response {
@@ -128,10 +272,8 @@ impl LinesHighlighter for FileHighlighter {
         }
 
         // Not in a sub-highlighter: look for hunk header
-        if let Some(hunk_highlighter) =
-            HunkLinesHighlighter::from_line(line, self.formatter.clone(), &self.url)?
-        {
-            self.sub_highlighter = Some(Box::new(hunk_highlighter));
+        if let Some(hunk_header) = HunkHeader::parse(line) {
+            self.start_hunk(hunk_header)?;
             return Ok(Response {
                 line_accepted: LineAcceptance::AcceptedWantMore,
                 highlighted: highlights,
@@ -139,34 +281,39 @@ impl LinesHighlighter for FileHighlighter {
         }
 
         // Otherwise we're done
+        highlights.extend(self.collapsed_summary());
+        highlights.extend(self.evil_merge_summary());
         return Ok(Response {
             line_accepted: LineAcceptance::RejectedDone,
             highlighted: highlights,
         });
     }
 
-    fn consume_eof(&mut self, thread_pool: &ThreadPool) -> Result<Vec<StringFuture>, String> {
+    fn consume_eof_unbuffered(
+        &mut self,
+        thread_pool: &ThreadPool,
+    ) -> Result<Vec<StringFuture>, String> {
         if self.new_name.is_empty() {
             return Err("Input ended early, --- should have been followed by +++".to_string());
         }
 
         let mut return_me = vec![];
-        if !self.header_rendered {
+        if !self.header_rendered && self.collapsed.is_none() {
             // No hunk ever showed up, e.g. an empty file being added/deleted,
             // or a truncated diff. Fall back to a plain, line-less link.
             return_me.push(StringFuture::from_string(self.highlighted(None)));
-            self.header_rendered = true;
         }
+        self.header_rendered = true;
 
         if let Some(ref mut sub) = self.sub_highlighter {
             return_me.extend(sub.consume_eof(thread_pool)?);
         }
+        return_me.extend(self.collapsed_summary());
+        return_me.extend(self.evil_merge_summary());
 
         Ok(return_me)
     }
-}
 
-impl FileHighlighter {
     /// Create a new LinesHighlighter from a line of input.
     ///
     /// Returns None if this line doesn't start a new LinesHighlighter.
@@ -183,15 +330,147 @@ impl FileHighlighter {
             new_name: String::new(),
             formatter,
             sub_highlighter: None,
-            url: None, // Will be set in consume_line() based on the +++ line
+            link: None,              // Will be set in consume_line() based on the +++ line
+            structured_format: None, // Also set based on the +++ line
+            header_row: None,
+            expecting_header_row: false,
+            collapsed: None,    // Also set based on the +++ line
+            dependencies: None, // Also set based on the +++ line
+            evil_merge: (0, 0),
+            in_conflict: false,
+            held_back: None, // Also set based on the +++ line
+            held_back_lines: 0,
+            prefix_length: 1,
             header_rendered: false,
         };
 
         return Some(highlighter);
     }
 
+    fn start_hunk(&mut self, hunk_header: HunkHeader) -> Result<(), String> {
+        // Merge diffs aren't supported
+        let key_paths = self
+            .structured_format
+            .filter(|_| hunk_header.linecounts.len() == 2)
+            .map(|format| {
+                KeyPathCollector::new(
+                    format,
+                    hunk_header.old_start(),
+                    hunk_header.new_start(),
+                    self.header_row.clone(),
+                )
+            });
+
+        let has_header_row = matches!(
+            self.structured_format,
+            Some(StructuredFormat::Csv | StructuredFormat::Tsv)
+        );
+        if has_header_row && hunk_header.new_start() == 1 {
+            self.expecting_header_row = true;
+        }
+
+        self.prefix_length = hunk_header.linecounts.len() - 1;
+        let mut hunk_highlighter = HunkLinesHighlighter::from_parsed(
+            hunk_header,
+            self.formatter.clone(),
+            &self.link,
+            key_paths,
+        )?;
+        if self.collapsed.is_some() {
+            hunk_highlighter.collapse();
+        }
+        self.sub_highlighter = Some(Box::new(hunk_highlighter));
+        return Ok(());
+    }
+
+    /// Replaces the whole file section if the file is collapsed, see
+    /// `generated::is_generated()`
+    fn collapsed_summary(&self) -> Option<StringFuture> {
+        let (added, removed) = self.collapsed?;
+        let name = if without_timestamp(&self.new_name) == "/dev/null" {
+            without_timestamp(&self.old_name)
+        } else {
+            without_timestamp(&self.new_name)
+        };
+        return Some(StringFuture::from_string(format!(
+            "{BOLD}{name}{NORMAL}{FAINT}: +{added} -{removed} lines, generated file collapsed{NORMAL}\n"
+        )));
+    }
+
+    fn count_evil_merge_line(&mut self, line: &str) {
+        let Some(prefix) = line.get(..self.prefix_length) else {
+            return;
+        };
+        if !is_evil_merge_prefix(prefix) {
+            return;
+        }
+
+        // Unresolved conflicts are in neither parent either, but they are
+        // highlighted as conflicts, not as evil merges
+        let text = &line[prefix.len()..];
+        if text.starts_with("<<<<<<<") {
+            self.in_conflict = true;
+        }
+        if self.in_conflict {
+            if text.starts_with(">>>>>>>") {
+                self.in_conflict = false;
+            }
+            return;
+        }
+
+        if prefix.starts_with('+') {
+            self.evil_merge.0 += 1;
+        } else {
+            self.evil_merge.1 += 1;
+        }
+    }
+
+    /// Ends the file section of merge diffs with lines in neither parent, so
+    /// that those don't go unnoticed
+    fn evil_merge_summary(&self) -> Option<StringFuture> {
+        let (added, removed) = self.evil_merge;
+        if added == 0 && removed == 0 {
+            return None;
+        }
+        return Some(StringFuture::from_string(format!(
+            "{EVIL_MERGE}Evil merge{NORMAL}{FAINT}: +{added} -{removed} lines in neither parent{NORMAL}\n"
+        )));
+    }
+
+    /// The held back file section, with the dependency table inserted right
+    /// after the file header or the collapsed file summary
+    fn release_held_back(&mut self) -> Vec<StringFuture> {
+        let mut released = self.held_back.take().unwrap_or_default();
+        let Some(table) = self.dependency_table() else {
+            return released;
+        };
+
+        let table_position = if self.collapsed.is_some() {
+            // The summary comes last
+            released.len()
+        } else {
+            cmp::min(1, released.len())
+        };
+        released.insert(table_position, table);
+        return released;
+    }
+
+    /// The dependency table, if there were any dependency changes
+    fn dependency_table(&mut self) -> Option<StringFuture> {
+        let dependencies = self.dependencies.take()?;
+        let table = render_dependency_table(&dependencies.changes());
+        if table.is_empty() {
+            return None;
+        }
+        return Some(StringFuture::from_string(table));
+    }
+
     fn highlighted(&self, first_hunk_line: Option<usize>) -> String {
-        let (mut old_tokens, mut new_tokens) = diff(&self.old_name, &self.new_name);
+        // File names are tokenized the same way whatever the file contains
+        let (mut old_tokens, mut new_tokens) = self
+            .formatter
+            .with_tokenizer_profile(Profile::Generic)
+            .diff(&self.old_name, &self.new_name);
 
         // New file
         if self.old_name == "/dev/null" {
@@ -219,7 +498,12 @@ impl FileHighlighter {
             None
         };
 
-        decorate_paths(&mut old_tokens, &mut new_tokens, first_hunk_line);
+        decorate_paths(
+            &mut old_tokens,
+            &mut new_tokens,
+            first_hunk_line,
+            self.formatter.hyperlinker(),
+        );
 
         if let Some(prefix) = new_prefix {
             new_tokens.insert(0, prefix);
@@ -288,6 +572,7 @@ fn hyperlink_tokenized(
     just_path: &mut [StyledToken],
     just_filename: &mut [StyledToken],
     fragment_line: Option<usize>,
+    hyperlinker: &Hyperlinker,
 ) {
     // Convert filename_tokens into a String
     let mut filename = String::new();
@@ -298,11 +583,9 @@ fn hyperlink_tokenized(
         filename.push_str(&token.token);
     }
 
-    if let Some(mut url) = hyperlink_filename(&filename) {
-        if let Some(fragment_line) = fragment_line {
-            url.set_fragment(Some(&fragment_line.to_string()));
-        }
-
+    let url = hyperlink_filename(&filename)
+        .and_then(|url| hyperlinker.file_link(&url).url(fragment_line));
+    if let Some(url) = url {
         // Actually link the tokens
         for token in just_path.iter_mut() {
             token.url = Some(url.clone());
@@ -467,6 +750,7 @@ pub(crate) fn decorate_paths(
     old_tokens: &mut [StyledToken],
     new_tokens: &mut [StyledToken],
     fragment_line: Option<usize>,
+    hyperlinker: &Hyperlinker,
 ) {
     let look_for_git_prefixes = have_git_prefixes(old_tokens, new_tokens);
 
@@ -491,9 +775,19 @@ pub(crate) fn decorate_paths(
     if old_split.just_path == new_split.just_path
         && old_split.just_filename == new_split.just_filename
     {
-        hyperlink_tokenized(old_split.just_path, old_split.just_filename, fragment_line);
+        hyperlink_tokenized(
+            old_split.just_path,
+            old_split.just_filename,
+            fragment_line,
+            hyperlinker,
+        );
     }
-    hyperlink_tokenized(new_split.just_path, new_split.just_filename, fragment_line);
+    hyperlink_tokenized(
+        new_split.just_path,
+        new_split.just_filename,
+        fragment_line,
+        hyperlinker,
+    );
 
     lowlight_dev_null(old_split.just_path, old_split.just_filename);
     lowlight_dev_null(new_split.just_path, new_split.just_filename);
@@ -586,7 +880,8 @@ mod tests {
         assert_eq!(LineAcceptance::AcceptedWantMore, response.line_accepted);
 
         let path = test_me
-            .url
+            .link
+            .and_then(|link| link.url(None))
             .unwrap()
             .to_file_path()
             .expect("Hyperlink should be a file path");
@@ -621,6 +916,95 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_collapse_generated_file() {
+        let thread_pool = ThreadPool::new(1);
+        let mut test_me =
+            FileHighlighter::from_line("--- a/Cargo.lock", FORMATTER.with_collapse_generated(true))
+                .unwrap();
+
+        let mut highlighted = String::new();
+        for line in [
+            "+++ b/Cargo.lock",
+            "@@ -1,2 +1,2 @@",
+            " [[package]]",
+            "-version = \"1.0\"",
+            "+version = \"1.1\"",
+            "@@ -10 +10,2 @@",
+            " x",
+            "+y",
+        ] {
+            let response = test_me.consume_line(line, &thread_pool).unwrap();
+            assert_eq!(LineAcceptance::AcceptedWantMore, response.line_accepted);
+            for mut future in response.highlighted {
+                highlighted.push_str(future.get());
+            }
+        }
+        assert_eq!(highlighted, "");
+
+        let response = test_me
+            .consume_line("diff --git a/x b/x", &thread_pool)
+            .unwrap();
+        assert_eq!(LineAcceptance::RejectedDone, response.line_accepted);
+        for mut future in response.highlighted {
+            highlighted.push_str(future.get());
+        }
+        assert_eq!(
+            highlighted,
+            format!("{BOLD}b/Cargo.lock{NORMAL}{FAINT}: +2 -1 lines, generated file collapsed{NORMAL}\n")
+        );
+    }
+
+    #[test]
+    fn test_long_manifest_not_held_back() {
+        let thread_pool = ThreadPool::new(1);
+        let mut test_me =
+            FileHighlighter::from_line("--- a/Cargo.toml", FORMATTER.clone()).unwrap();
+
+        let context_lines = MAX_HELD_BACK_LINES + 1;
+        let hunk_header = format!("@@ -1,{} +1,{} @@", context_lines + 1, context_lines + 1);
+        let mut lines = vec!["+++ b/Cargo.toml", &hunk_header, " [dependencies]"];
+        lines.extend(std::iter::repeat_n(" # Comment", context_lines - 1));
+        lines.extend(["-serde = \"1.0\"", "+serde = \"1.1\""]);
+
+        // Too long to hold back, so output is streamed
+        let mut streamed = 0;
+        for line in lines {
+            let response = test_me.consume_line(line, &thread_pool).unwrap();
+            assert_eq!(LineAcceptance::AcceptedWantMore, response.line_accepted);
+            streamed += response.highlighted.len();
+        }
+        assert!(streamed > 0);
+
+        // With the table at the end instead
+        let response = test_me
+            .consume_line("diff --git a/x b/x", &thread_pool)
+            .unwrap();
+        assert_eq!(LineAcceptance::RejectedDone, response.line_accepted);
+        let mut highlighted = String::new();
+        for mut future in response.highlighted {
+            highlighted.push_str(future.get());
+        }
+        let (changes, table) = highlighted.split_once("Dependencies:").unwrap();
+        assert!(changes.contains("serde"));
+        assert!(table.contains("upgraded"));
+    }
+
+    #[test]
+    fn test_notebook_cells() {
+        let thread_pool = ThreadPool::new(1);
+        let formatter = FORMATTER.with_notebook_cells(Some(Profile::Python));
+        let mut test_me = FileHighlighter::from_line("--- a.ipynb", formatter.clone()).unwrap();
+        test_me.consume_line("+++ b.ipynb", &thread_pool).unwrap();
+
+        // Rendered cells are Python, not notebook JSON
+        assert_eq!(test_me.structured_format, None);
+        assert_eq!(
+            test_me.formatter,
+            formatter.with_tokenizer_profile(Profile::Python)
+        );
+    }
+
     #[test]
     fn test_brighten_filename() {
         let highlighted = highlight_header_lines("--- a/x/y/z.txt", "+++ b/x/y/z.txt");
@@ -692,7 +1076,7 @@ mod tests {
         let mut row = vec![StyledToken::new("README.md".to_string(), Style::Context)];
 
         // Act: call the function
-        hyperlink_tokenized(&mut [], &mut row, None);
+        hyperlink_tokenized(&mut [], &mut row, None, &Hyperlinker::default());
 
         // Assert: the file:/// URL points to our README.md file
         let url = row[0].url.as_ref().expect("Token should have a URL");
@@ -0,0 +1,206 @@
+use crate::hyperlink::{expand, validate_template};
+use crate::repository::{self, Remote};
+
+/// Links commits, tags and branches to the repository's web UI. Made from
+/// the repository's remote, see `--commit-link-format`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ForgeLinks {
+    /// Like `origin`, only branches of this remote are linked
+    remote_name: String,
+
+    /// URL templates with a `{ref}` placeholder
+    commit_template: String,
+    tag_template: String,
+    branch_template: String,
+}
+
+impl ForgeLinks {
+    /// Links for the repository in the current directory, if it has a remote
+    /// on a forge we know about, or if `template` is set.
+    pub(crate) fn from_current_directory(template: Option<&str>) -> Option<Self> {
+        let cwd = std::env::current_dir().ok()?;
+        let remote = repository::toplevel(&cwd)
+            .as_deref()
+            .and_then(repository::remote);
+        return Self::new(remote, template);
+    }
+
+    /// `template` overrides the forge's own URL patterns. Templates using
+    /// `{host}` or `{repo}` need a remote.
+    pub(crate) fn new(remote: Option<Remote>, template: Option<&str>) -> Option<Self> {
+        if let Some(template) = template {
+            let (remote_name, host, repo) = match remote {
+                Some(remote) => (remote.name, remote.host, remote.path),
+                None if template.contains("{host}") || template.contains("{repo}") => {
+                    return None;
+                }
+                None => Default::default(),
+            };
+            let template = expand(template, &[("host", &host), ("repo", &repo)]);
+            return Some(ForgeLinks {
+                remote_name,
+                commit_template: template.clone(),
+                tag_template: template.clone(),
+                branch_template: template,
+            });
+        }
+
+        let remote = remote?;
+        let base = format!("https://{}/{}", remote.host, remote.path);
+        let host = remote.host.to_lowercase();
+        let (commit, tag, branch) = if host.contains("github") {
+            ("commit", "releases/tag", "tree")
+        } else if host.contains("gitlab") {
+            ("-/commit", "-/tags", "-/tree")
+        } else if host.contains("gitea") || host.contains("forgejo") || host == "codeberg.org" {
+            ("commit", "src/tag", "src/branch")
+        } else if host.contains("bitbucket") {
+            ("commits", "src", "branch")
+        } else {
+            return None;
+        };
+
+        return Some(ForgeLinks {
+            remote_name: remote.name,
+            commit_template: format!("{base}/{commit}/{{ref}}"),
+            tag_template: format!("{base}/{tag}/{{ref}}"),
+            branch_template: format!("{base}/{branch}/{{ref}}"),
+        });
+    }
+
+    pub(crate) fn commit_url(&self, commit: &str) -> Option<url::Url> {
+        return url_for(&self.commit_template, commit);
+    }
+
+    pub(crate) fn tag_url(&self, tag: &str) -> Option<url::Url> {
+        return url_for(&self.tag_template, tag);
+    }
+
+    /// `branch` is a local branch name from a `git log` decoration, like
+    /// `main` or `refs/heads/main`
+    pub(crate) fn branch_url(&self, branch: &str) -> Option<url::Url> {
+        let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+        return url_for(&self.branch_template, branch);
+    }
+
+    /// `branch` is a remote-tracking branch from a `git log` decoration, like
+    /// `origin/main` or `refs/remotes/origin/main`. Only branches of our own
+    /// remote are linked, and `origin/HEAD` isn't.
+    pub(crate) fn remote_branch_url(&self, branch: &str) -> Option<url::Url> {
+        let branch = branch.strip_prefix("refs/remotes/").unwrap_or(branch);
+        let (remote, branch) = branch.split_once('/')?;
+        if remote != self.remote_name || branch == "HEAD" {
+            return None;
+        }
+        return url_for(&self.branch_template, branch);
+    }
+}
+
+/// Validate a `--commit-link-format` template
+pub(crate) fn parse_template(template: &str) -> Result<String, String> {
+    validate_template(
+        template,
+        &[
+            ("host", "example.com"),
+            ("repo", "owner/repo"),
+            ("ref", "main"),
+        ],
+    )?;
+    return Ok(template.to_string());
+}
+
+fn url_for(template: &str, reference: &str) -> Option<url::Url> {
+    return url::Url::parse(&expand(template, &[("ref", reference)])).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    fn remote(host: &str) -> Option<Remote> {
+        return Some(Remote {
+            name: "origin".to_string(),
+            host: host.to_string(),
+            path: "walles/riff".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_github() {
+        let links = ForgeLinks::new(remote("github.com"), None).unwrap();
+        assert_eq!(
+            links.commit_url("62da46c").unwrap().as_str(),
+            "https://github.com/walles/riff/commit/62da46c"
+        );
+        assert_eq!(
+            links.tag_url("2.21.0").unwrap().as_str(),
+            "https://github.com/walles/riff/releases/tag/2.21.0"
+        );
+        assert_eq!(
+            links
+                .remote_branch_url("origin/walles/threaded")
+                .unwrap()
+                .as_str(),
+            "https://github.com/walles/riff/tree/walles/threaded"
+        );
+        assert_eq!(
+            links
+                .remote_branch_url("refs/remotes/origin/main")
+                .unwrap()
+                .as_str(),
+            "https://github.com/walles/riff/tree/main"
+        );
+        assert_eq!(links.remote_branch_url("origin/HEAD"), None);
+        assert_eq!(links.remote_branch_url("upstream/main"), None);
+        assert_eq!(
+            links.branch_url("refs/heads/main").unwrap().as_str(),
+            "https://github.com/walles/riff/tree/main"
+        );
+    }
+
+    #[test]
+    fn test_other_forges() {
+        let commit_url = |host| {
+            return ForgeLinks::new(remote(host), None)
+                .and_then(|links| links.commit_url("62da46c"))
+                .map(String::from);
+        };
+        assert_eq!(
+            commit_url("gitlab.example.com").as_deref(),
+            Some("https://gitlab.example.com/walles/riff/-/commit/62da46c")
+        );
+        assert_eq!(
+            commit_url("codeberg.org").as_deref(),
+            Some("https://codeberg.org/walles/riff/commit/62da46c")
+        );
+        assert_eq!(
+            commit_url("bitbucket.org").as_deref(),
+            Some("https://bitbucket.org/walles/riff/commits/62da46c")
+        );
+        assert_eq!(commit_url("git.example.com"), None);
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let template = parse_template("https://review.example.com/{repo}/+/{ref}").unwrap();
+        let links = ForgeLinks::new(remote("git.example.com"), Some(&template)).unwrap();
+        assert_eq!(
+            links.commit_url("62da46c").unwrap().as_str(),
+            "https://review.example.com/walles/riff/+/62da46c"
+        );
+        assert_eq!(
+            links.remote_branch_url("origin/main").unwrap().as_str(),
+            "https://review.example.com/walles/riff/+/main"
+        );
+
+        // Nothing to put in {repo}
+        assert_eq!(ForgeLinks::new(None, Some(&template)), None);
+        assert!(ForgeLinks::new(None, Some("https://example.com/{ref}")).is_some());
+
+        assert!(parse_template("https://example.com/{commit}").is_err());
+        assert!(parse_template("{ref}").is_err());
+    }
+}
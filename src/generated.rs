@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{env, fs};
+
+use once_cell::sync::Lazy;
+
+use crate::repository;
+
+/// Lock files that nobody reads the diffs of
+const GENERATED_FILE_NAMES: &[&str] = &["Cargo.lock", "package-lock.json", "yarn.lock"];
+
+/// Contents of the `.gitattributes` files we have read so far, by path.
+/// Missing files are empty.
+static GITATTRIBUTES: Lazy<Mutex<HashMap<PathBuf, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Is this a generated, vendored or lock file that should be collapsed into a
+/// one line summary? See `--no-collapse`.
+///
+/// `path` is a file name from a `---` or `+++` line, without any timestamp.
+/// `file` is where that file is on disk, if we found it.
+pub(crate) fn is_generated(path: &str, file: Option<&Path>) -> bool {
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    let basename = path.rsplit('/').next().unwrap_or(path);
+    if GENERATED_FILE_NAMES.contains(&basename) || basename.ends_with(".min.js") {
+        return true;
+    }
+
+    let Some((toplevel, relative_path)) = locate(path, file) else {
+        return false;
+    };
+    return is_generated_in(&toplevel, &relative_path);
+}
+
+/// The repository root the file is in, and the file's path relative to that
+/// root.
+///
+/// Without a `file` on disk, `path` is taken to be relative to the root of the
+/// repository we are in, as in `git diff` output.
+fn locate(path: &str, file: Option<&Path>) -> Option<(PathBuf, String)> {
+    if let Some(file) = file {
+        if let Some(toplevel) = repository::toplevel(file) {
+            let relative_path = file.strip_prefix(&toplevel).ok()?;
+            let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+            return Some((toplevel, relative_path));
+        }
+    }
+
+    let current_dir = env::current_dir().ok()?;
+    let toplevel = repository::toplevel(&current_dir).unwrap_or(current_dir);
+    return Some((toplevel, path.to_string()));
+}
+
+/// Check the `.gitattributes` files of `relative_path`'s directory and all its
+/// parent directories up to `toplevel`. Deeper files override the ones
+/// higher up.
+fn is_generated_in(toplevel: &Path, relative_path: &str) -> bool {
+    let mut directories = vec![""];
+    for (index, _) in relative_path.match_indices('/') {
+        directories.push(&relative_path[..index]);
+    }
+
+    let mut cache = GITATTRIBUTES.lock().unwrap();
+    let mut attributes: HashMap<String, bool> = HashMap::new();
+    for directory in directories {
+        let gitattributes_path = toplevel.join(directory).join(".gitattributes");
+        let gitattributes = cache
+            .entry(gitattributes_path.clone())
+            .or_insert_with(|| fs::read_to_string(&gitattributes_path).unwrap_or_default());
+
+        // Patterns are relative to the directory of the .gitattributes file
+        let path_in_directory = if directory.is_empty() {
+            relative_path
+        } else {
+            &relative_path[directory.len() + 1..]
+        };
+        collect_attributes(gitattributes, path_in_directory, &mut attributes);
+    }
+
+    return has_generated_attributes(&attributes);
+}
+
+/// Is `path` marked as `linguist-generated`, `linguist-vendored`, `-diff` or
+/// `binary` in this `.gitattributes` file contents?
+#[cfg(test)]
+fn is_generated_by_gitattributes(gitattributes: &str, path: &str) -> bool {
+    let mut attributes = HashMap::new();
+    collect_attributes(gitattributes, path, &mut attributes);
+    return has_generated_attributes(&attributes);
+}
+
+fn has_generated_attributes(attributes: &HashMap<String, bool>) -> bool {
+    let is_set = |name: &str| attributes.get(name).copied();
+    return is_set("linguist-generated") == Some(true)
+        || is_set("linguist-vendored") == Some(true)
+        || is_set("diff") == Some(false);
+}
+
+/// Add the attributes set for `path` in this `.gitattributes` file contents to
+/// `attributes`, overriding any values already there
+fn collect_attributes(gitattributes: &str, path: &str, attributes: &mut HashMap<String, bool>) {
+    // Later lines override earlier ones
+    for line in gitattributes.lines() {
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        if pattern.starts_with('#') || !pattern_matches(pattern, path) {
+            continue;
+        }
+
+        for attribute in words {
+            let (name, set) = if let Some(name) = attribute.strip_prefix('-') {
+                (name, false)
+            } else if let Some((name, value)) = attribute.split_once('=') {
+                (name, value != "false")
+            } else {
+                (attribute, true)
+            };
+
+            if name == "binary" {
+                // A macro for "-diff -merge -text"
+                attributes.insert("diff".to_string(), !set);
+                continue;
+            }
+            attributes.insert(name.to_string(), set);
+        }
+    }
+}
+
+/// Does a `.gitattributes` pattern match this path, relative to the
+/// repository root?
+///
+/// Patterns without slashes match file names in any directory, other
+/// patterns match from the root.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    if !pattern.contains('/') {
+        let basename = path.rsplit('/').next().unwrap_or(path);
+        return glob_matches(pattern.as_bytes(), basename.as_bytes());
+    }
+
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    return glob_matches(pattern.as_bytes(), path.as_bytes());
+}
+
+/// `*` and `?` match anything except `/`, `**` matches anything.
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => return text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // Zero or more directories
+            return glob_matches(rest, text)
+                || (0..text.len())
+                    .any(|index| text[index] == b'/' && glob_matches(rest, &text[index + 1..]));
+        }
+        [b'*', b'*', rest @ ..] => {
+            return (0..=text.len()).any(|index| glob_matches(rest, &text[index..]));
+        }
+        [b'*', rest @ ..] => {
+            let segment_length = text.iter().take_while(|c| **c != b'/').count();
+            return (0..=segment_length).any(|index| glob_matches(rest, &text[index..]));
+        }
+        [b'?', rest @ ..] => {
+            return text.first().is_some_and(|c| *c != b'/') && glob_matches(rest, &text[1..]);
+        }
+        [c, rest @ ..] => {
+            return text.first() == Some(c) && glob_matches(rest, &text[1..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_generated() {
+        assert!(is_generated("a/Cargo.lock", None));
+        assert!(is_generated("b/web/package-lock.json", None));
+        assert!(is_generated("yarn.lock", None));
+        assert!(is_generated("b/static/jquery.min.js", None));
+        assert!(!is_generated("b/src/main.rs", None));
+        assert!(!is_generated("b/Cargo.lock.md", None));
+    }
+
+    #[test]
+    fn test_nested_gitattributes() {
+        let toplevel = tempfile::tempdir().unwrap();
+        fs::create_dir_all(toplevel.path().join("src/gen")).unwrap();
+        fs::write(
+            toplevel.path().join(".gitattributes"),
+            "*.pb.go linguist-generated\n",
+        )
+        .unwrap();
+        fs::write(
+            toplevel.path().join("src/.gitattributes"),
+            "gen/** linguist-generated\nkeep.pb.go -linguist-generated\n",
+        )
+        .unwrap();
+
+        assert!(is_generated_in(toplevel.path(), "api/service.pb.go"));
+        assert!(is_generated_in(toplevel.path(), "src/gen/x.rs"));
+        assert!(!is_generated_in(toplevel.path(), "src/keep.pb.go"));
+        assert!(!is_generated_in(toplevel.path(), "gen/x.rs"));
+    }
+
+    #[test]
+    fn test_is_generated_by_gitattributes() {
+        let gitattributes = "\
+# Comment
+*.pb.go linguist-generated=true
+/vendor/** linguist-vendored
+docs/**/*.svg -diff
+assets/*.bin binary
+src/gen/keep.rs -linguist-generated
+src/gen/* linguist-generated
+src/gen/manual.rs linguist-generated=false
+";
+        let is_generated = |path| is_generated_by_gitattributes(gitattributes, path);
+        assert!(is_generated("api/v1/service.pb.go"));
+        assert!(is_generated("vendor/github.com/x/y.go"));
+        assert!(is_generated("docs/logo.svg"));
+        assert!(is_generated("docs/a/b/logo.svg"));
+        assert!(is_generated("assets/font.bin"));
+        assert!(is_generated("src/gen/keep.rs")); // Overridden by a later line
+        assert!(!is_generated("src/gen/manual.rs"));
+        assert!(!is_generated("src/gen/sub/file.rs"));
+        assert!(!is_generated("app/vendor/x.go"));
+        assert!(!is_generated("api/v1/service.go"));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches(b"*.js", b"a.js"));
+        assert!(!glob_matches(b"*.js", b"a/b.js"));
+        assert!(glob_matches(b"a/**", b"a/b/c"));
+        assert!(glob_matches(b"**/c", b"c"));
+        assert!(glob_matches(b"a?c", b"abc"));
+        assert!(!glob_matches(b"a?c", b"a/c"));
+    }
+}
@@ -0,0 +1,160 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::constants::*;
+
+// Handle the lane drawings in front of each line of `git log --graph` output
+
+/// Like `* commit 1234abc`, `| * commit 1234abc`, `* | commit 1234abc` or
+/// `*   commit 1234abc` for merges. With `--oneline` there's no `commit`. The
+/// first group is the graph, which always starts with the leftmost lane.
+static GRAPH_COMMIT_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^((?:\|[|/\\_ ]*)?\*[|/\\_. -]* )(?:commit [0-9a-f]+|[0-9a-f]{7,40} )").unwrap()
+});
+
+/// Same order as `git`'s own default graph colors
+const LANE_COLORS: [&str; 6] = [
+    "\x1b[31m", // Red
+    "\x1b[32m", // Green
+    "\x1b[33m", // Yellow
+    "\x1b[34m", // Blue
+    "\x1b[35m", // Magenta
+    "\x1b[36m", // Cyan
+];
+
+fn is_graph_char(c: char) -> bool {
+    return matches!(c, '|' | '/' | '\\' | '_' | '*' | '.' | '-' | ' ');
+}
+
+/// The graph drawn in front of one commit's lines. `git` pads the graph to the
+/// same width on all lines of a commit, so after the commit line we know how
+/// much to strip.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Graph {
+    width: usize,
+}
+
+impl Graph {
+    /// A graph for the lines following `line`, if it is a `git log --graph`
+    /// commit line
+    pub(crate) fn from_commit_line(line: &str) -> Option<Graph> {
+        if !line.starts_with(['*', '|']) {
+            // Fast path for the common case
+            return None;
+        }
+        let captures = GRAPH_COMMIT_LINE.captures(line)?;
+        return Some(Graph {
+            width: captures.get(1).unwrap().as_str().len(),
+        });
+    }
+
+    /// Split `line` into its graph prefix and the line as it would have been
+    /// without `--graph`. Lines like `|/` between commits are all graph.
+    pub(crate) fn split<'a>(&self, line: &'a str) -> (&'a str, &'a str) {
+        // Trailing whitespace is sometimes trimmed, so the graph can be
+        // shorter than the width, as in a lone "|"
+        let width = self.width.min(line.len());
+        let Some(prefix) = line
+            .get(..width)
+            .filter(|prefix| prefix.chars().all(is_graph_char))
+        else {
+            return ("", line);
+        };
+        let rest = &line[width..];
+
+        // Diff lines start with ' ', '+', '-', '\' or '@', and commit message
+        // lines with four spaces. So this must be more graph.
+        if rest.starts_with(['|', '/', '\\', '_', '*']) && rest.chars().all(is_graph_char) {
+            return (line, "");
+        }
+        return (prefix, rest);
+    }
+}
+
+/// Color each lane of `graph` in its own color
+pub(crate) fn colorize(graph: &str) -> String {
+    if graph.is_empty() {
+        return String::new();
+    }
+
+    let mut colorized = String::with_capacity(graph.len() * 3);
+    for (column, c) in graph.chars().enumerate() {
+        if c == ' ' {
+            colorized.push(c);
+            continue;
+        }
+
+        // Each lane is two columns wide
+        colorized.push_str(LANE_COLORS[(column / 2) % LANE_COLORS.len()]);
+        colorized.push(c);
+    }
+    colorized.push_str(NORMAL);
+    return colorized;
+}
+
+/// Put `prefix` in front of each line of `text`
+pub(crate) fn prefix_lines(text: &str, prefix: &str) -> String {
+    let mut prefixed = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        prefixed.push_str(prefix);
+        prefixed.push_str(line);
+    }
+    return prefixed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_from_commit_line() {
+        assert_eq!(
+            Graph::from_commit_line("* commit 62da46c (HEAD -> main)"),
+            Some(Graph { width: 2 })
+        );
+        assert_eq!(
+            Graph::from_commit_line("| * commit 62da46c"),
+            Some(Graph { width: 4 })
+        );
+        assert_eq!(
+            Graph::from_commit_line("*   commit 62da46c"),
+            Some(Graph { width: 4 })
+        );
+        assert_eq!(
+            Graph::from_commit_line("* | commit 62da46c"),
+            Some(Graph { width: 4 })
+        );
+        assert_eq!(
+            Graph::from_commit_line("| * 62da46c Fix the parser"),
+            Some(Graph { width: 4 })
+        );
+        assert_eq!(Graph::from_commit_line("commit 62da46c"), None);
+        assert_eq!(
+            Graph::from_commit_line("    * 62da46c Fix the parser"),
+            None
+        );
+        assert_eq!(Graph::from_commit_line("| Author: Johan"), None);
+    }
+
+    #[test]
+    fn test_split() {
+        let graph = Graph { width: 4 };
+        assert_eq!(graph.split("| | Author: Johan"), ("| | ", "Author: Johan"));
+        assert_eq!(graph.split("| | -removed"), ("| | ", "-removed"));
+        assert_eq!(graph.split("| |  context"), ("| | ", " context"));
+        assert_eq!(graph.split("| |"), ("| |", ""));
+        assert_eq!(graph.split("| |/"), ("| |/", ""));
+        assert_eq!(graph.split("| | |\\"), ("| | |\\", ""));
+        assert_eq!(graph.split("| | - "), ("| | ", "- "));
+        assert_eq!(graph.split("diff --git"), ("", "diff --git"));
+    }
+
+    #[test]
+    fn test_colorize() {
+        assert_eq!(colorize(""), "");
+        assert_eq!(colorize("| * "), format!("\x1b[31m| \x1b[32m* {NORMAL}"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+use std::time::Instant;
+
+use similar::algorithms::{myers, Capture, Compact, DiffHook, NoFinishHook, Replace};
+use similar::DiffOp;
+
+/// Elements occurring more often than this in the old sequence are never used
+/// as anchors. If that's all we have, we let Myers do the job instead.
+///
+/// Same number as JGit uses.
+const MAX_ANCHOR_OCCURRENCES: usize = 64;
+
+/// Histogram diff, like `git diff --histogram`.
+///
+/// Like Patience diff, but rather than anchoring on elements that are unique
+/// in both sequences, we anchor on the least common elements of the old
+/// sequence. This handles sequences with few unique elements better, which
+/// tokenized source code usually is.
+///
+/// Returns the same kind of ops as `similar::capture_diff_slices_deadline()`.
+pub(crate) fn capture_diff_slices_deadline<T: Hash + Eq + Ord>(
+    old: &[T],
+    new: &[T],
+    deadline: Option<Instant>,
+) -> Vec<DiffOp> {
+    let mut d = Compact::new(Replace::new(Capture::new()), old, new);
+    diff(&mut d, old, new, deadline).unwrap();
+    d.finish().unwrap();
+    return d.into_inner().into_inner().into_ops();
+}
+
+/// Work left to do, see `diff()`
+enum Task {
+    Diff(Range<usize>, Range<usize>),
+    Equal(usize, usize, usize),
+}
+
+fn diff<T: Hash + Eq, D: DiffHook>(
+    d: &mut D,
+    old: &[T],
+    new: &[T],
+    deadline: Option<Instant>,
+) -> Result<(), D::Error> {
+    // Built once for the whole diff, subranges look up their part of it
+    let mut old_positions: HashMap<&T, Vec<usize>> = HashMap::new();
+    for (old_index, element) in old.iter().enumerate() {
+        old_positions.entry(element).or_default().push(old_index);
+    }
+
+    // Tasks are popped off the end, so anything that should be reported to
+    // the hook in order must be pushed in reverse. We do this without
+    // recursion since token sequences can get really long.
+    let mut tasks = vec![Task::Diff(0..old.len(), 0..new.len())];
+    while let Some(task) = tasks.pop() {
+        let (old_range, new_range) = match task {
+            Task::Equal(old_index, new_index, len) => {
+                d.equal(old_index, new_index, len)?;
+                continue;
+            }
+            Task::Diff(old_range, new_range) => (old_range, new_range),
+        };
+
+        if old_range.is_empty() && new_range.is_empty() {
+            continue;
+        }
+        if old_range.is_empty() {
+            d.insert(old_range.start, new_range.start, new_range.len())?;
+            continue;
+        }
+        if new_range.is_empty() {
+            d.delete(old_range.start, old_range.len(), new_range.start)?;
+            continue;
+        }
+
+        let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        let anchor = if out_of_time {
+            None
+        } else {
+            find_anchor(
+                old,
+                old_range.clone(),
+                new,
+                new_range.clone(),
+                &old_positions,
+            )
+        };
+
+        let Some((old_index, new_index, len)) = anchor else {
+            // Myers will fall back to an approximation if we're out of time
+            myers::diff_deadline(
+                &mut NoFinishHook::new(&mut *d),
+                old,
+                old_range,
+                new,
+                new_range,
+                deadline,
+            )?;
+            continue;
+        };
+
+        tasks.push(Task::Diff(
+            old_index + len..old_range.end,
+            new_index + len..new_range.end,
+        ));
+        tasks.push(Task::Equal(old_index, new_index, len));
+        tasks.push(Task::Diff(
+            old_range.start..old_index,
+            new_range.start..new_index,
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Find the longest common region containing the least common element of
+/// the old range.
+///
+/// `old_positions` lists the indices of each element in all of `old`, in
+/// increasing order.
+///
+/// Returns `(old_index, new_index, len)` of that region, or `None` if there
+/// is no usable anchor.
+fn find_anchor<T: Hash + Eq>(
+    old: &[T],
+    old_range: Range<usize>,
+    new: &[T],
+    new_range: Range<usize>,
+    old_positions: &HashMap<&T, Vec<usize>>,
+) -> Option<(usize, usize, usize)> {
+    // (occurrence count, old_index, new_index, len)
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut new_index = new_range.start;
+    while new_index < new_range.end {
+        let Some(all_positions) = old_positions.get(&new[new_index]) else {
+            new_index += 1;
+            continue;
+        };
+        let first = all_positions.partition_point(|index| *index < old_range.start);
+        let last = all_positions.partition_point(|index| *index < old_range.end);
+        let positions = &all_positions[first..last];
+        if positions.is_empty() || positions.len() > MAX_ANCHOR_OCCURRENCES {
+            new_index += 1;
+            continue;
+        }
+
+        let mut next_new_index = new_index + 1;
+        for &old_index in positions {
+            // Extend the match as far as possible in both directions
+            let mut start = 0;
+            while old_index - start > old_range.start
+                && new_index - start > new_range.start
+                && old[old_index - start - 1] == new[new_index - start - 1]
+            {
+                start += 1;
+            }
+            let mut end = 1;
+            while old_index + end < old_range.end
+                && new_index + end < new_range.end
+                && old[old_index + end] == new[new_index + end]
+            {
+                end += 1;
+            }
+
+            let len = start + end;
+            let count = positions.len();
+            let is_better = match best {
+                None => true,
+                Some((best_count, _, _, best_len)) => {
+                    count < best_count || (count == best_count && len > best_len)
+                }
+            };
+            if is_better {
+                best = Some((count, old_index - start, new_index - start, len));
+            }
+            next_new_index = next_new_index.max(new_index + end);
+        }
+
+        new_index = next_new_index;
+    }
+
+    return best.map(|(_, old_index, new_index, len)| (old_index, new_index, len));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_identical() {
+        let tokens = ["a", "b", "c"];
+        assert_eq!(
+            capture_diff_slices_deadline(&tokens, &tokens, None),
+            [DiffOp::Equal {
+                old_index: 0,
+                new_index: 0,
+                len: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn test_anchor_on_rare_token() {
+        // The `{` / `}` tokens are all over the place, `x` is the one to
+        // anchor on
+        let old = ["{", "}", "{", "x", "}"];
+        let new = ["{", "x", "}", "{", "}"];
+        assert_eq!(
+            capture_diff_slices_deadline(&old, &new, None),
+            [
+                DiffOp::Delete {
+                    old_index: 0,
+                    old_len: 2,
+                    new_index: 0
+                },
+                DiffOp::Equal {
+                    old_index: 2,
+                    new_index: 0,
+                    len: 3
+                },
+                DiffOp::Insert {
+                    old_index: 5,
+                    new_index: 3,
+                    new_len: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nothing_in_common() {
+        assert_eq!(
+            capture_diff_slices_deadline(&["a", "b"], &["c"], None),
+            [DiffOp::Replace {
+                old_index: 0,
+                old_len: 2,
+                new_index: 0,
+                new_len: 1
+            }]
+        );
+    }
+}
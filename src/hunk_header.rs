@@ -1,4 +1,5 @@
 use crate::constants::*;
+use crate::hyperlink::FileLink;
 
 /// Result of parsing a hunk header: <https://en.wikipedia.org/wiki/Diff#Unified_format>
 ///
@@ -26,12 +27,9 @@ pub(crate) struct HunkHeader {
 
 pub(crate) const HUNK_HEADER: &str = "\x1b[36m"; // Cyan
 
-fn hyperlink(string: &str, url: &Option<url::Url>, line_number: usize) -> String {
-    if let Some(url) = url {
-        return format!(
-            "\x1b]8;;{}#{}\x1b\\{}\x1b]8;;\x1b\\",
-            url, line_number, string
-        );
+fn hyperlink(string: &str, link: &Option<FileLink>, line_number: usize) -> String {
+    if let Some(url) = link.as_ref().and_then(|link| link.url(Some(line_number))) {
+        return format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, string);
     }
 
     return string.to_string();
@@ -120,6 +118,16 @@ impl HunkHeader {
         })
     }
 
+    /// One-based start line of the first old section
+    pub fn old_start(&self) -> usize {
+        return self.starts[0];
+    }
+
+    /// One-based start line of the new section
+    pub fn new_start(&self) -> usize {
+        return *self.starts.last().unwrap();
+    }
+
     /// The first modified line in the new file, i.e. the line a click on this
     /// hunk's title should land on.
     pub fn first_modified_line(&self) -> Result<usize, String> {
@@ -135,7 +143,7 @@ impl HunkHeader {
     }
 
     /// Render into an ANSI highlighted string, not ending in a newline.
-    pub fn render(&self, url: &Option<url::Url>) -> Result<String, String> {
+    pub fn render(&self, link: &Option<FileLink>) -> Result<String, String> {
         let mut rendered = String::new();
         rendered.push_str(HUNK_HEADER);
         rendered.push_str(&self.ats);
@@ -159,7 +167,7 @@ impl HunkHeader {
         if let Some(title) = &self.title {
             rendered.push(' ');
             rendered.push_str(BOLD);
-            rendered.push_str(&hyperlink(title, url, self.first_modified_line()?));
+            rendered.push_str(&hyperlink(title, link, self.first_modified_line()?));
         }
 
         rendered.push_str(NORMAL);
@@ -4,11 +4,18 @@ use crate::conflicts_highlighter::ConflictsHighlighter;
 use crate::constants::NORMAL;
 use crate::constants::NO_EOF_NEWLINE_COLOR;
 use crate::hunk_header::HunkHeader;
+use crate::hyperlink::{FileLink, LineLinks};
+use crate::key_path::{render_key_paths, KeyPathCollector, StructuredFormat};
 use crate::lines_highlighter::{LineAcceptance, LinesHighlighter, Response};
+use crate::notebook::{HunkPart, NotebookHunk};
 use crate::plusminus_lines_highlighter::PlusMinusLinesHighlighter;
 use crate::refiner::Formatter;
 use crate::string_future::StringFuture;
 
+/// Larger hunks than this don't get any key paths in their hunk headers, see
+/// `HunkLinesHighlighter::key_paths`
+const MAX_KEY_PATHS_HUNK_LINES: usize = 1000;
+
 #[derive(Debug)]
 pub(crate) struct HunkLinesHighlighter {
     lines_highlighter: Option<Box<dyn LinesHighlighter>>,
@@ -23,16 +30,92 @@ pub(crate) struct HunkLinesHighlighter {
     remaining_line_counts: Vec<usize>,
 
     formatter: Formatter,
+
+    /// For JSON, YAML, CSV and TSV files. If set, we hold back all output until the
+    /// hunk is done, so that we can put the changed key paths in the hunk
+    /// header.
+    ///
+    /// Holding back output breaks streaming, so this is only done for hunks of
+    /// at most `MAX_KEY_PATHS_HUNK_LINES` lines.
+    key_paths: Option<KeyPathCollector>,
+
+    /// Output held back while collecting key paths
+    held_back: Vec<StringFuture>,
+
+    /// For Jupyter notebooks. If set, we collect all lines of the hunk
+    /// before highlighting them, so that output and metadata changes can be
+    /// summarized.
+    notebook_hunk: Option<NotebookHunk>,
+
+    /// Set for generated files, see `collapse()`
+    collapsed: bool,
+
+    /// Set with `--hyperlink-format`, for linking each line
+    line_links: Option<LineLinks>,
 }
 
 impl LinesHighlighter for HunkLinesHighlighter {
     fn consume_line(&mut self, line: &str, thread_pool: &ThreadPool) -> Result<Response, String> {
+        if self.collapsed {
+            return self.consume_collapsed_line(line);
+        }
+        if self.notebook_hunk.is_some() {
+            return self.consume_notebook_line(line, thread_pool);
+        }
+
+        let mut response = self.consume_line_unbuffered(line, thread_pool)?;
+        let Some(key_paths) = &mut self.key_paths else {
+            return Ok(response);
+        };
+
+        if response.line_accepted != LineAcceptance::RejectedDone {
+            key_paths.consume_line(line);
+        }
+        self.held_back.append(&mut response.highlighted);
+        if response.line_accepted == LineAcceptance::RejectedDone {
+            response.highlighted = self.release_held_back();
+        }
+        return Ok(response);
+    }
+
+    fn consume_eof(&mut self, thread_pool: &ThreadPool) -> Result<Vec<StringFuture>, String> {
+        if self.collapsed || self.notebook_hunk.is_some() {
+            if self.more_lines_expected() {
+                return Err(format!(
+                    "Still expecting more lines, but got EOF: {}",
+                    self.describe_remaining_line_counts()
+                ));
+            }
+            if self.collapsed {
+                return Ok(vec![]);
+            }
+            return self.release_notebook_hunk(thread_pool);
+        }
+
+        let mut result = self.consume_eof_unbuffered(thread_pool)?;
+        if self.key_paths.is_none() {
+            return Ok(result);
+        }
+
+        self.held_back.append(&mut result);
+        return Ok(self.release_held_back());
+    }
+}
+
+impl HunkLinesHighlighter {
+    fn consume_line_unbuffered(
+        &mut self,
+        line: &str,
+        thread_pool: &ThreadPool,
+    ) -> Result<Response, String> {
         let mut return_me = vec![];
 
-        // Always start by rendering the hunk header
-        if let Some(hunk_header) = &self.hunk_header {
-            return_me.push(StringFuture::from_string(hunk_header.to_string() + "\n"));
-            self.hunk_header = None;
+        // Always start by rendering the hunk header, unless we're collecting
+        // key paths for it
+        if self.key_paths.is_none() {
+            if let Some(hunk_header) = self.hunk_header.take() {
+                return_me.push(StringFuture::from_string(hunk_header + "\n"));
+            }
         }
 
         let prefix_length = self.remaining_line_counts.len() - 1;
@@ -44,7 +127,7 @@ impl LinesHighlighter for HunkLinesHighlighter {
         };
 
         if line.starts_with('\\') {
-            return_me.append(&mut self.consume_line_internal(line, thread_pool)?);
+            return_me.append(&mut self.highlight_line(line, thread_pool)?);
             return Ok(Response {
                 line_accepted: LineAcceptance::AcceptedWantMore,
                 highlighted: return_me,
@@ -53,8 +136,8 @@ impl LinesHighlighter for HunkLinesHighlighter {
 
         if !self.more_lines_expected() {
             if let Some(ref mut lines_highlighter) = self.lines_highlighter {
-                let mut result = lines_highlighter.consume_eof(thread_pool)?;
-                return_me.append(&mut result);
+                let result = lines_highlighter.consume_eof(thread_pool)?;
+                return_me.append(&mut self.linked(result));
             }
 
             return Ok(Response {
@@ -66,14 +149,17 @@ impl LinesHighlighter for HunkLinesHighlighter {
         self.decrease_remaining_line_counts(prefix)?;
 
         // It wasn't a nnaeof line, and we're still expecting more lines.
-        return_me.append(&mut self.consume_line_internal(line, thread_pool)?);
+        return_me.append(&mut self.highlight_line(line, thread_pool)?);
         return Ok(Response {
             line_accepted: LineAcceptance::AcceptedWantMore,
             highlighted: return_me,
         });
     }
 
-    fn consume_eof(&mut self, thread_pool: &ThreadPool) -> Result<Vec<StringFuture>, String> {
+    fn consume_eof_unbuffered(
+        &mut self,
+        thread_pool: &ThreadPool,
+    ) -> Result<Vec<StringFuture>, String> {
         if self.more_lines_expected() {
             return Err(format!(
                 "Still expecting more lines, but got EOF: {}",
@@ -81,24 +167,132 @@ impl LinesHighlighter for HunkLinesHighlighter {
             ));
         }
 
-        return self.drain(thread_pool);
+        let drained = self.drain(thread_pool)?;
+        return Ok(self.linked(drained));
+    }
+
+    /// Return the hunk header with the changed key paths in it, followed by
+    /// everything we held back while collecting those key paths.
+    fn release_held_back(&mut self) -> Vec<StringFuture> {
+        let mut released = Vec::with_capacity(self.held_back.len() + 1);
+        if let Some(hunk_header) = self.hunk_header.take() {
+            let key_paths = self
+                .key_paths
+                .as_ref()
+                .map(KeyPathCollector::changed_paths)
+                .unwrap_or_default();
+            released.push(StringFuture::from_string(
+                hunk_header + &render_key_paths(&key_paths) + "\n",
+            ));
+        }
+        released.append(&mut self.held_back);
+        return released;
+    }
+
+    /// Count lines without highlighting them, for files that are summarized
+    /// rather than shown. The caller counts the added and removed lines.
+    pub(crate) fn collapse(&mut self) {
+        self.collapsed = true;
+    }
+
+    fn consume_collapsed_line(&mut self, line: &str) -> Result<Response, String> {
+        if !line.starts_with('\\') {
+            if !self.more_lines_expected() {
+                return Ok(Response {
+                    line_accepted: LineAcceptance::RejectedDone,
+                    highlighted: vec![],
+                });
+            }
+            self.decrease_remaining_line_counts(&self.prefix_of(line))?;
+        }
+
+        return Ok(Response {
+            line_accepted: LineAcceptance::AcceptedWantMore,
+            highlighted: vec![],
+        });
+    }
+
+    /// The `-`, `+` or ` ` prefix of a line, one character per version
+    /// being compared
+    fn prefix_of(&self, line: &str) -> String {
+        let prefix_length = self.remaining_line_counts.len() - 1;
+        return match line.get(..prefix_length) {
+            Some(prefix) => prefix.to_string(),
+            None => " ".repeat(prefix_length),
+        };
+    }
+
+    fn consume_notebook_line(
+        &mut self,
+        line: &str,
+        thread_pool: &ThreadPool,
+    ) -> Result<Response, String> {
+        if !line.starts_with('\\') {
+            if !self.more_lines_expected() {
+                return Ok(Response {
+                    line_accepted: LineAcceptance::RejectedDone,
+                    highlighted: self.release_notebook_hunk(thread_pool)?,
+                });
+            }
+
+            self.decrease_remaining_line_counts(&self.prefix_of(line))?;
+        }
+
+        if let Some(key_paths) = &mut self.key_paths {
+            key_paths.consume_line(line);
+        }
+        self.notebook_hunk.as_mut().unwrap().push(line);
+        return Ok(Response {
+            line_accepted: LineAcceptance::AcceptedWantMore,
+            highlighted: vec![],
+        });
+    }
+
+    /// Highlight a complete notebook hunk, with output and metadata changes
+    /// summarized
+    fn release_notebook_hunk(
+        &mut self,
+        thread_pool: &ThreadPool,
+    ) -> Result<Vec<StringFuture>, String> {
+        // Starts with the hunk header
+        let mut released = self.release_held_back();
+
+        let notebook_hunk = self.notebook_hunk.take().unwrap();
+        for part in notebook_hunk.summarize() {
+            match part {
+                HunkPart::Line(line) => {
+                    released.append(&mut self.consume_line_internal(&line, thread_pool)?);
+                }
+                HunkPart::Summary(summary) => {
+                    released.append(&mut self.drain(thread_pool)?);
+                    released.push(StringFuture::from_string(summary + "\n"));
+                }
+            }
+        }
+        released.append(&mut self.drain(thread_pool)?);
+
+        return Ok(released);
     }
-}
 
-impl HunkLinesHighlighter {
     /// Create a new LinesHighlighter from a line of input.
     ///
     /// Returns None if this line doesn't start a new LinesHighlighter.
+    #[cfg(test)]
     pub(crate) fn from_line(
         line: &str,
         formatter: Formatter,
-        file_url: &Option<url::Url>,
+        file_link: &Option<FileLink>,
     ) -> Result<Option<Self>, String>
     where
         Self: Sized,
     {
         if let Some(hunk_header) = HunkHeader::parse(line) {
-            return Ok(Some(Self::from_parsed(hunk_header, formatter, file_url)?));
+            return Ok(Some(Self::from_parsed(
+                hunk_header,
+                formatter,
+                file_link,
+                None,
+            )?));
         }
 
         return Ok(None);
@@ -106,20 +300,76 @@ impl HunkLinesHighlighter {
 
     /// Like `from_line()`, but for a hunk header the caller has already
     /// parsed, so the same line doesn't get parsed twice.
+    ///
+    /// If `key_paths` is set, the hunk header will list the key paths of the
+    /// changed lines. For notebooks, output and metadata changes will also
+    /// be summarized.
     pub(crate) fn from_parsed(
         hunk_header: HunkHeader,
         formatter: Formatter,
-        file_url: &Option<url::Url>,
+        file_link: &Option<FileLink>,
+        key_paths: Option<KeyPathCollector>,
     ) -> Result<Self, String> {
+        let notebook_hunk = key_paths
+            .as_ref()
+            .filter(|key_paths| key_paths.format() == StructuredFormat::Notebook)
+            .map(|_| NotebookHunk::new(hunk_header.old_start(), hunk_header.new_start()));
+
+        // Notebook hunks must be collected to be summarized, but other hunks
+        // are only held back for their key paths if they are small enough
+        let hunk_lines = hunk_header.linecounts.iter().sum::<usize>();
+        let key_paths =
+            key_paths.filter(|_| notebook_hunk.is_some() || hunk_lines <= MAX_KEY_PATHS_HUNK_LINES);
+
+        // Notebook summaries replace lines, and collapsed reindentations
+        // merge them, so those lines can't be linked one by one
+        let line_links = file_link
+            .as_ref()
+            .filter(|file_link| file_link.links_lines() && notebook_hunk.is_none())
+            .filter(|_| !formatter.collapse_reindented())
+            .map(|file_link| LineLinks::new(file_link.clone(), hunk_header.new_start()));
+
         return Ok(HunkLinesHighlighter {
-            hunk_header: Some(hunk_header.render(file_url)?),
+            hunk_header: Some(hunk_header.render(file_link)?),
             remaining_line_counts: hunk_header.linecounts.clone(),
             initial_line_counts: hunk_header.linecounts,
             lines_highlighter: None,
             formatter,
+            key_paths,
+            held_back: Vec::new(),
+            notebook_hunk,
+            collapsed: false,
+            line_links,
         });
     }
 
+    /// Like `consume_line_internal()`, but links the highlighted lines if
+    /// `--hyperlink-format` is set
+    fn highlight_line(
+        &mut self,
+        line: &str,
+        thread_pool: &ThreadPool,
+    ) -> Result<Vec<StringFuture>, String> {
+        let prefix_length = self.remaining_line_counts.len() - 1;
+        if let Some(line_links) = &mut self.line_links {
+            line_links.push(line, prefix_length);
+        }
+
+        let highlighted = self.consume_line_internal(line, thread_pool)?;
+        return Ok(self.linked(highlighted));
+    }
+
+    /// Link the lines of some highlighted hunk lines, see `LineLinks`
+    fn linked(&self, highlighted: Vec<StringFuture>) -> Vec<StringFuture> {
+        let Some(line_links) = &self.line_links else {
+            return highlighted;
+        };
+        return highlighted
+            .into_iter()
+            .map(|future| line_links.link(future))
+            .collect();
+    }
+
     fn consume_line_internal(
         &mut self,
         line: &str,
@@ -152,7 +402,8 @@ impl HunkLinesHighlighter {
         }
 
         if prefix_length == 2 {
-            if let Some(highlighter) = ConflictsHighlighter::from_line(line) {
+            if let Some(highlighter) = ConflictsHighlighter::from_line(line, self.formatter.clone())
+            {
                 self.lines_highlighter = Some(Box::new(highlighter));
                 return Ok(return_me);
             }
@@ -290,6 +541,7 @@ impl HunkLinesHighlighter {
 
 #[cfg(test)]
 mod tests {
+    use crate::key_path::StructuredFormat;
     use crate::refiner::tests::FORMATTER;
     use crate::{line_collector::NO_EOF_NEWLINE_MARKER_HOLDER, lines_highlighter::LineAcceptance};
 
@@ -342,6 +594,57 @@ mod tests {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_key_paths_in_hunk_header() {
+        let thread_pool = ThreadPool::new(1);
+
+        let mut test_me = HunkLinesHighlighter::from_parsed(
+            HunkHeader::parse("@@ -1,2 +1,2 @@").unwrap(),
+            FORMATTER.clone(),
+            &None,
+            Some(KeyPathCollector::new(StructuredFormat::Yaml, 1, 1, None)),
+        )
+        .unwrap();
+
+        // Everything is held back until the hunk is done
+        for line in [" spec:", "-  replicas: 1", "+  replicas: 2"] {
+            let result = test_me.consume_line(line, &thread_pool).unwrap();
+            assert_eq!(result.line_accepted, LineAcceptance::AcceptedWantMore);
+            assert!(result.highlighted.is_empty());
+        }
+
+        let mut result = test_me.consume_line("@@ -9 +9 @@", &thread_pool).unwrap();
+        assert_eq!(result.line_accepted, LineAcceptance::RejectedDone);
+        assert_eq!(result.highlighted.len(), 3);
+        assert_eq!(
+            result.highlighted[0].get(),
+            "\u{1b}[36m@@ -1,2 +1,2 @@\u{1b}[0m \u{1b}[2m(spec.replicas)\u{1b}[0m\n"
+        );
+        assert_eq!(result.highlighted[1].get(), " spec:\n");
+    }
+
+    #[test]
+    fn test_large_hunk_not_held_back() {
+        let thread_pool = ThreadPool::new(1);
+
+        let mut test_me = HunkLinesHighlighter::from_parsed(
+            HunkHeader::parse("@@ -1,2000 +1,2000 @@").unwrap(),
+            FORMATTER.clone(),
+            &None,
+            Some(KeyPathCollector::new(StructuredFormat::Yaml, 1, 1, None)),
+        )
+        .unwrap();
+
+        // Too large for collecting key paths, so output is streamed
+        let mut result = test_me.consume_line(" spec:", &thread_pool).unwrap();
+        assert_eq!(result.line_accepted, LineAcceptance::AcceptedWantMore);
+        assert_eq!(result.highlighted.len(), 2);
+        assert_eq!(
+            result.highlighted[0].get(),
+            "\u{1b}[36m@@ -1,2000 +1,2000 @@\u{1b}[0m\n"
+        );
+    }
+
     #[test]
     fn test_decrease_remaining_line_count() {
         let mut test_me =
@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::forge::ForgeLinks;
+use crate::issue_links::IssueLink;
+use crate::refiner::is_annotation_line;
+use crate::repository;
+use crate::string_future::StringFuture;
+
+/// Makes the URLs we put in hyperlinks. Files are linked using
+/// `--hyperlink-format`, commits using `--commit-link-format` and issue
+/// references using `--issue-link`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Hyperlinker {
+    /// Like `vscode://file/{path}:{line}`. If not set, we link to `file://`
+    /// URLs with the line number as the fragment.
+    template: Option<String>,
+
+    /// The commit we're showing the diff of, for `{commit}`
+    commit: Option<String>,
+
+    /// Links to the repository's web UI, for commit lines
+    forge_links: Option<ForgeLinks>,
+
+    /// For issue references in commit messages
+    issue_links: Vec<IssueLink>,
+}
+
+impl Hyperlinker {
+    /// Parse a `--hyperlink-format` template
+    pub(crate) fn from_template(template: &str) -> Result<Self, String> {
+        validate_template(
+            template,
+            &[
+                ("path", "/src/main.rs"),
+                ("relpath", "src/main.rs"),
+                ("line", "1"),
+                ("repo", "owner/repo"),
+                ("commit", "HEAD"),
+            ],
+        )?;
+
+        return Ok(Hyperlinker {
+            template: Some(template.to_string()),
+            ..Hyperlinker::default()
+        });
+    }
+
+    pub(crate) fn with_commit(&self, commit: &str) -> Self {
+        return Hyperlinker {
+            commit: Some(commit.to_string()),
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_forge_links(&self, forge_links: Option<ForgeLinks>) -> Self {
+        return Hyperlinker {
+            forge_links,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn forge_links(&self) -> Option<&ForgeLinks> {
+        return self.forge_links.as_ref();
+    }
+
+    pub(crate) fn with_issue_links(&self, issue_links: Vec<IssueLink>) -> Self {
+        return Hyperlinker {
+            issue_links,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn issue_links(&self) -> &[IssueLink] {
+        return &self.issue_links;
+    }
+
+    /// `file_url` is a `file://` URL to a file that exists, see
+    /// `file_highlighter::hyperlink_filename()`.
+    pub(crate) fn file_link(&self, file_url: &url::Url) -> FileLink {
+        let Some(template) = &self.template else {
+            return FileLink {
+                file_url: file_url.clone(),
+                template: None,
+            };
+        };
+        let Ok(path) = file_url.to_file_path() else {
+            return FileLink {
+                file_url: file_url.clone(),
+                template: None,
+            };
+        };
+
+        let toplevel = repository::toplevel(&path);
+        let relpath = toplevel
+            .as_deref()
+            .and_then(|toplevel| path.strip_prefix(toplevel).ok())
+            .unwrap_or(&path);
+        let repo = if template.contains("{repo}") {
+            toplevel
+                .as_deref()
+                .and_then(repository::remote)
+                .map(|remote| remote.path)
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        // Everything but the line number is known now
+        let template = expand(
+            template,
+            &[
+                ("path", &url_encoded_path(&path)),
+                ("relpath", &url_encoded_path(relpath)),
+                ("repo", &repo),
+                ("commit", self.commit.as_deref().unwrap_or("HEAD")),
+            ],
+        );
+        return FileLink {
+            file_url: file_url.clone(),
+            template: Some(template),
+        };
+    }
+}
+
+/// Links to one file, see `Hyperlinker::file_link()`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FileLink {
+    file_url: url::Url,
+
+    /// `--hyperlink-format` template with only `{line}` left to expand
+    template: Option<String>,
+}
+
+impl FileLink {
+    /// URL to this file, opening it at `line` if set
+    pub(crate) fn url(&self, line: Option<usize>) -> Option<url::Url> {
+        let Some(template) = &self.template else {
+            let mut url = self.file_url.clone();
+            if let Some(line) = line {
+                url.set_fragment(Some(&line.to_string()));
+            }
+            return Some(url);
+        };
+
+        let line = line.unwrap_or(1).to_string();
+        return url::Url::parse(&expand(template, &[("line", &line)])).ok();
+    }
+
+    /// Individual diff lines are only linked with `--hyperlink-format`, since
+    /// `file://` URLs can't point at lines in most terminals
+    pub(crate) fn links_lines(&self) -> bool {
+        return self.template.is_some();
+    }
+}
+
+/// Links the lines of one hunk to where they are in the new version of the
+/// file. Links are collected as lines are consumed, and applied to the
+/// highlighted lines when those are printed, in the same order.
+#[derive(Debug)]
+pub(crate) struct LineLinks {
+    file_link: FileLink,
+
+    /// Line number of the next line in the new version of the file
+    next_line: usize,
+
+    /// Links of lines that have been consumed but not printed yet
+    pending: Arc<Mutex<VecDeque<Option<url::Url>>>>,
+}
+
+impl LineLinks {
+    pub(crate) fn new(file_link: FileLink, new_start: usize) -> Self {
+        return LineLinks {
+            file_link,
+            next_line: new_start,
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        };
+    }
+
+    /// `prefix_length` is the length of the `-`, `+` or ` ` prefix of each
+    /// hunk line, one character per version being compared.
+    pub(crate) fn push(&mut self, line: &str, prefix_length: usize) {
+        let url = if line.starts_with('\\') {
+            // "\ No newline at end of file" is not a line of the file
+            None
+        } else {
+            // Removed lines link to where they were removed from
+            let url = self.file_link.url(Some(self.next_line));
+            let prefix = line.get(..prefix_length).unwrap_or_default();
+            if prefix.contains('+') || prefix.chars().all(|c| c == ' ') {
+                self.next_line += 1;
+            }
+            url
+        };
+        self.pending.lock().unwrap().push_back(url);
+    }
+
+    /// Link each line of `highlighted` to the next pending link. Annotation
+    /// lines from the refiner aren't lines of the file, and don't get links.
+    pub(crate) fn link(&self, highlighted: StringFuture) -> StringFuture {
+        let pending = Arc::clone(&self.pending);
+        return highlighted.map(move |text| {
+            let mut pending = pending.lock().unwrap();
+            let mut linked = String::with_capacity(text.len());
+            for line in text.split_inclusive('\n') {
+                if is_annotation_line(line) {
+                    linked.push_str(line);
+                    continue;
+                }
+                let Some(url) = pending.pop_front().flatten() else {
+                    linked.push_str(line);
+                    continue;
+                };
+                match line.strip_suffix('\n') {
+                    Some(line) => linked.push_str(&(hyperlink(&url, line) + "\n")),
+                    None => linked.push_str(&hyperlink(&url, line)),
+                }
+            }
+            return linked;
+        });
+    }
+}
+
+/// `text` as an OSC 8 terminal hyperlink to `url`
+pub(crate) fn hyperlink(url: &url::Url, text: &str) -> String {
+    return format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\");
+}
+
+/// Check that `template` only uses the placeholders in `examples`, and that
+/// it becomes a URL when they are replaced with their example values
+pub(crate) fn validate_template(template: &str, examples: &[(&str, &str)]) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            return Err(format!("Unterminated {{ in: {template}"));
+        };
+        let placeholder = &rest[start + 1..start + length];
+        if !examples.iter().any(|(name, _)| *name == placeholder) {
+            let names = examples.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            return Err(format!(
+                "Unknown placeholder {{{placeholder}}}, expected one of {{{}}}",
+                names.join("}, {")
+            ));
+        }
+        rest = &rest[start + length + 1..];
+    }
+
+    if let Err(error) = url::Url::parse(&expand(template, examples)) {
+        return Err(format!("Not a URL template, {error}: {template}"));
+    }
+    return Ok(());
+}
+
+pub(crate) fn expand(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = template.to_string();
+    for (placeholder, value) in values {
+        expanded = expanded.replace(&format!("{{{placeholder}}}"), value);
+    }
+    return expanded;
+}
+
+/// With forward slashes, so that it works in URLs on Windows as well, and
+/// with characters like `#`, `?` and `%` percent-encoded
+fn url_encoded_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~:".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    return encoded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    fn readme_url() -> url::Url {
+        return url::Url::from_file_path(std::fs::canonicalize("README.md").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_from_template() {
+        assert!(Hyperlinker::from_template("vscode://file/{path}:{line}").is_ok());
+        assert!(Hyperlinker::from_template("idea://open?file={path}&line={line}").is_ok());
+        assert!(Hyperlinker::from_template(
+            "https://git.example.com/{repo}/blob/{commit}/{relpath}#L{line}"
+        )
+        .is_ok());
+
+        assert!(Hyperlinker::from_template("vscode://file/{file}:{line}").is_err());
+        assert!(Hyperlinker::from_template("vscode://file/{path").is_err());
+        assert!(Hyperlinker::from_template("{path}:{line}").is_err());
+    }
+
+    #[test]
+    fn test_default_file_link() {
+        let link = Hyperlinker::default().file_link(&readme_url());
+        assert!(!link.links_lines());
+
+        let mut expected = readme_url();
+        expected.set_fragment(Some("12"));
+        assert_eq!(link.url(Some(12)), Some(expected));
+        assert_eq!(link.url(None), Some(readme_url()));
+    }
+
+    #[test]
+    fn test_templated_file_link() {
+        let readme = std::fs::canonicalize("README.md").unwrap();
+        let hyperlinker = Hyperlinker::from_template("vscode://file/{path}:{line}").unwrap();
+        let link = hyperlinker.file_link(&readme_url());
+        assert!(link.links_lines());
+        assert_eq!(
+            link.url(Some(12)).unwrap().as_str(),
+            format!("vscode://file/{}:12", readme.to_string_lossy())
+        );
+
+        let hyperlinker =
+            Hyperlinker::from_template("https://example.com/{commit}/{relpath}#L{line}")
+                .unwrap()
+                .with_commit("abc123");
+        assert_eq!(
+            hyperlinker
+                .file_link(&readme_url())
+                .url(None)
+                .unwrap()
+                .as_str(),
+            "https://example.com/abc123/README.md#L1"
+        );
+    }
+
+    #[test]
+    fn test_url_encoded_path() {
+        assert_eq!(
+            url_encoded_path(Path::new("/src/a b/c#1?x=50%.rs")),
+            "/src/a%20b/c%231%3Fx%3D50%25.rs"
+        );
+        assert_eq!(
+            url_encoded_path(Path::new("C:/src/åäö.rs")),
+            "C:/src/%C3%A5%C3%A4%C3%B6.rs"
+        );
+    }
+
+    #[test]
+    fn test_line_links() {
+        let hyperlinker = Hyperlinker::from_template("vscode://file/{relpath}:{line}").unwrap();
+        let mut line_links = LineLinks::new(hyperlinker.file_link(&readme_url()), 10);
+        for line in [
+            " context",
+            "-removed",
+            "+added",
+            "\\ No newline at end of file",
+        ] {
+            line_links.push(line, 1);
+        }
+
+        let link = |line: usize, text: &str| {
+            format!("\x1b]8;;vscode://file/README.md:{line}\x1b\\{text}\x1b]8;;\x1b\\\n")
+        };
+        let mut first = line_links.link(StringFuture::from_string(" context\n".to_string()));
+        let annotation = crate::refiner::annotation_line("reindented") + "\n";
+        let mut rest = line_links.link(StringFuture::from_string(
+            "-removed\n+added\n".to_string() + &annotation + "\\ No newline at end of file\n",
+        ));
+        assert_eq!(first.get(), link(10, " context"));
+        assert_eq!(
+            rest.get(),
+            link(11, "-removed")
+                + &link(11, "+added")
+                + &annotation
+                + "\\ No newline at end of file\n"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::constants::*;
+use crate::hyperlink::{expand, hyperlink, validate_template};
+
+/// Links issue references like `#123` or `PROJ-123` in commit messages to
+/// the issue tracker, see `--issue-link`.
+#[derive(Clone)]
+pub(crate) struct IssueLink {
+    regex: Regex,
+
+    /// URL template with an `{id}` placeholder
+    template: String,
+}
+
+impl IssueLink {
+    /// For `--issue-link`: Parse `REGEX=TEMPLATE`. Since URL templates are
+    /// more likely than regexes to contain `=`, we split on the first one.
+    pub(crate) fn from_spec(spec: &str) -> Result<IssueLink, String> {
+        let Some((pattern, template)) = spec.split_once('=') else {
+            return Err(format!("Expected REGEX=TEMPLATE, got: {spec}"));
+        };
+        if pattern.is_empty() {
+            return Err("Empty regex".to_string());
+        }
+        let regex = Regex::new(pattern).map_err(|error| error.to_string())?;
+        validate_template(template, &[("id", "123")])?;
+
+        return Ok(IssueLink {
+            regex,
+            template: template.to_string(),
+        });
+    }
+
+    /// `{id}` is the first capture group, or the whole match if the regex has
+    /// no groups. That way both `#(\d+)` and `[A-Z]+-\d+` do the right thing.
+    fn url(&self, captures: &regex::Captures) -> Option<url::Url> {
+        let id = captures.get(1).or(captures.get(0))?.as_str();
+        return url::Url::parse(&expand(&self.template, &[("id", id)])).ok();
+    }
+}
+
+impl PartialEq for IssueLink {
+    fn eq(&self, other: &Self) -> bool {
+        return self.regex.as_str() == other.regex.as_str() && self.template == other.template;
+    }
+}
+
+impl fmt::Debug for IssueLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}={}", self.regex.as_str(), self.template);
+    }
+}
+
+/// Link and highlight all issue references in `line`. Where references
+/// overlap, the one starting first wins, then the longest one, then the one
+/// whose pattern was given first.
+pub(crate) fn link_issues(line: &str, issue_links: &[IssueLink]) -> String {
+    let mut matches: Vec<(usize, usize, url::Url)> = Vec::new();
+    for issue_link in issue_links {
+        for captures in issue_link.regex.captures_iter(line) {
+            let whole = captures.get(0).unwrap();
+            if whole.start() == whole.end() {
+                continue;
+            }
+            if let Some(url) = issue_link.url(&captures) {
+                matches.push((whole.start(), whole.end(), url));
+            }
+        }
+    }
+    matches.sort_by_key(|(start, end, _)| (*start, usize::MAX - end));
+
+    let mut linked = String::with_capacity(line.len());
+    let mut position = 0;
+    for (start, end, url) in matches {
+        if start < position {
+            // Overlaps an earlier match
+            continue;
+        }
+        linked.push_str(&line[position..start]);
+        linked.push_str(&format!(
+            "{CYAN}{UNDERLINE}{}{NO_UNDERLINE}{DEFAULT_COLOR}",
+            hyperlink(&url, &line[start..end])
+        ));
+        position = end;
+    }
+    linked.push_str(&line[position..]);
+    return linked;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    fn linked(url: &str, text: &str) -> String {
+        return format!(
+            "{CYAN}{UNDERLINE}{}{NO_UNDERLINE}{DEFAULT_COLOR}",
+            hyperlink(&url::Url::parse(url).unwrap(), text)
+        );
+    }
+
+    #[test]
+    fn test_from_spec() {
+        assert!(IssueLink::from_spec(r"#(\d+)=https://github.com/walles/riff/issues/{id}").is_ok());
+        assert!(IssueLink::from_spec(r"[A-Z]+-\d+=https://jira.example.com/browse/{id}").is_ok());
+        assert!(IssueLink::from_spec(r"GH-(\d+)=https://example.com/show?id={id}").is_ok());
+
+        assert!(IssueLink::from_spec(r"#(\d+)").is_err());
+        assert!(IssueLink::from_spec(r"=https://example.com/{id}").is_err());
+        assert!(IssueLink::from_spec(r"#(\d+=https://example.com/{id}").is_err());
+        assert!(IssueLink::from_spec(r"#(\d+)=https://example.com/{issue}").is_err());
+    }
+
+    #[test]
+    fn test_link_issues() {
+        let issue_links = vec![
+            IssueLink::from_spec(r"#(\d+)=https://github.com/walles/riff/issues/{id}").unwrap(),
+            IssueLink::from_spec(r"\bGH-(\d+)\b=https://github.com/walles/riff/issues/{id}")
+                .unwrap(),
+            IssueLink::from_spec(r"\b[A-Z]+-\d+\b=https://jira.example.com/browse/{id}").unwrap(),
+        ];
+
+        assert_eq!(
+            link_issues("    Fix crash, see #123 and PROJ-45", &issue_links),
+            format!(
+                "    Fix crash, see {} and {}",
+                linked("https://github.com/walles/riff/issues/123", "#123"),
+                linked("https://jira.example.com/browse/PROJ-45", "PROJ-45"),
+            )
+        );
+
+        // GH-7 matches both the GH and the Jira pattern, GH was given first
+        assert_eq!(
+            link_issues("    Fixes GH-7", &issue_links),
+            format!(
+                "    Fixes {}",
+                linked("https://github.com/walles/riff/issues/7", "GH-7")
+            )
+        );
+
+        assert_eq!(
+            link_issues("    Nothing to see here", &issue_links),
+            "    Nothing to see here"
+        );
+    }
+}
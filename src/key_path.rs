@@ -0,0 +1,790 @@
+use crate::constants::{FAINT, NORMAL};
+use crate::notebook::summarized_path;
+use crate::tokenizer::split_fields;
+
+/// Show at most this many key paths per hunk header
+const MAX_RENDERED_KEY_PATHS: usize = 3;
+
+/// Formats we can figure out key paths for, like `spec.containers[0].image`.
+/// For CSV and TSV files, the key paths are the names of the changed columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StructuredFormat {
+    Json,
+    Yaml,
+
+    /// Jupyter notebooks are JSON, see `notebook.rs` for what else we do
+    /// with them
+    Notebook,
+
+    Csv,
+    Tsv,
+}
+
+impl StructuredFormat {
+    pub(crate) fn from_filename(filename: &str) -> Option<StructuredFormat> {
+        let extension = filename.rsplit_once('.')?.1.to_ascii_lowercase();
+        return match extension.as_str() {
+            "json" => Some(StructuredFormat::Json),
+            "ipynb" => Some(StructuredFormat::Notebook),
+            "yaml" | "yml" => Some(StructuredFormat::Yaml),
+            "csv" => Some(StructuredFormat::Csv),
+            "tsv" | "tab" => Some(StructuredFormat::Tsv),
+            _ => None,
+        };
+    }
+}
+
+/// Collects the lines of one hunk, to figure out the key paths of the changed
+/// lines when the hunk is done.
+///
+/// Since a hunk only shows a few lines of context, we don't always know the
+/// whole path. Paths that don't start at the document root are prefixed by
+/// `…`.
+#[derive(Debug)]
+pub(crate) struct KeyPathCollector {
+    format: StructuredFormat,
+
+    /// Do the old / new lines start at the first line of the file?
+    old_from_start: bool,
+    new_from_start: bool,
+
+    /// Context and `-` lines, with true for the `-` lines
+    old_lines: Vec<(String, bool)>,
+
+    /// Context and `+` lines, with true for the `+` lines
+    new_lines: Vec<(String, bool)>,
+
+    /// For CSV and TSV files, the first line of the file if we have seen it
+    header_row: Option<String>,
+}
+
+impl KeyPathCollector {
+    /// `header_row` is used for naming CSV and TSV columns, unless this hunk
+    /// starts at the first line of the file.
+    pub(crate) fn new(
+        format: StructuredFormat,
+        old_start: usize,
+        new_start: usize,
+        header_row: Option<String>,
+    ) -> Self {
+        return KeyPathCollector {
+            format,
+            old_from_start: old_start <= 1,
+            new_from_start: new_start <= 1,
+            old_lines: Vec::new(),
+            new_lines: Vec::new(),
+            header_row,
+        };
+    }
+
+    pub(crate) fn format(&self) -> StructuredFormat {
+        return self.format;
+    }
+
+    /// Add one line from a two-version hunk, starting with its `-`, `+` or
+    /// ` ` prefix.
+    pub(crate) fn consume_line(&mut self, line: &str) {
+        let mut chars = line.chars();
+        let prefix = chars.next();
+        let text = chars.as_str().to_string();
+        match prefix {
+            Some('-') => self.old_lines.push((text, true)),
+            Some('+') => self.new_lines.push((text, true)),
+            Some(' ') => {
+                self.old_lines.push((text.clone(), false));
+                self.new_lines.push((text, false));
+            }
+            _ => { /* Probably a "\ No newline at end of file" line */ }
+        }
+    }
+
+    /// Key paths of all changed lines, without paths inside of other paths
+    /// in the list, in order of appearance.
+    pub(crate) fn changed_paths(&self) -> Vec<String> {
+        match self.format {
+            StructuredFormat::Csv => return self.changed_columns(','),
+            StructuredFormat::Tsv => return self.changed_columns('\t'),
+            StructuredFormat::Json | StructuredFormat::Yaml | StructuredFormat::Notebook => {
+                /* Handled below */
+            }
+        }
+
+        let mut paths: Vec<String> = Vec::new();
+        for (lines, from_start) in [
+            (&self.old_lines, self.old_from_start),
+            (&self.new_lines, self.new_from_start),
+        ] {
+            let texts = lines
+                .iter()
+                .map(|(text, _)| text.as_str())
+                .collect::<Vec<_>>();
+            let line_paths = match self.format {
+                StructuredFormat::Json | StructuredFormat::Notebook => {
+                    json_line_paths(&texts, from_start)
+                }
+                StructuredFormat::Yaml => yaml_line_paths(&texts, from_start),
+                StructuredFormat::Csv | StructuredFormat::Tsv => unreachable!(),
+            };
+            for ((_, changed), path) in lines.iter().zip(line_paths) {
+                if !*changed || path.is_empty() || paths.contains(&path) {
+                    continue;
+                }
+                if self.format == StructuredFormat::Notebook && summarized_path(&path).is_some() {
+                    // Already summarized in the hunk itself
+                    continue;
+                }
+                paths.push(path);
+            }
+        }
+
+        let outermost_paths = paths
+            .iter()
+            .filter(|path| {
+                !paths
+                    .iter()
+                    .any(|other| other != *path && is_inside(path, other))
+            })
+            .cloned()
+            .collect();
+        return outermost_paths;
+    }
+
+    /// Names of the columns where paired `-` and `+` lines differ, in column
+    /// order. Lines are paired by position within each block of changes.
+    ///
+    /// Added and removed rows don't change any particular column, so unpaired
+    /// lines are ignored.
+    fn changed_columns(&self, separator: char) -> Vec<String> {
+        let old_blocks = changed_blocks(&self.old_lines);
+        let new_blocks = changed_blocks(&self.new_lines);
+
+        let mut columns: Vec<usize> = Vec::new();
+        for (position, old_block) in &old_blocks {
+            let Some((_, new_block)) = new_blocks.iter().find(|(p, _)| p == position) else {
+                continue;
+            };
+            for (old_line, new_line) in old_block.iter().zip(new_block) {
+                let old_fields = split_fields(old_line, separator);
+                let new_fields = split_fields(new_line, separator);
+                for column in 0..old_fields.len().max(new_fields.len()) {
+                    if old_fields.get(column) != new_fields.get(column)
+                        && !columns.contains(&column)
+                    {
+                        columns.push(column);
+                    }
+                }
+            }
+        }
+        columns.sort_unstable();
+
+        // The first line of the file is the header row
+        let header_row = if self.new_from_start {
+            self.new_lines.first().map(|(text, _)| text.as_str())
+        } else if self.old_from_start {
+            self.old_lines.first().map(|(text, _)| text.as_str())
+        } else {
+            self.header_row.as_deref()
+        };
+        let names = header_row
+            .map(|header_row| split_fields(header_row, separator))
+            .unwrap_or_default();
+
+        return columns
+            .into_iter()
+            .map(|column| {
+                let name = names
+                    .get(column)
+                    .map_or("", |name| name.trim().trim_matches('"'));
+                if name.is_empty() {
+                    return format!("column {}", column + 1);
+                }
+                return name.to_string();
+            })
+            .collect();
+    }
+}
+
+/// Group the changed lines by how many context lines came before them.
+/// Blocks with the same number of context lines before them are the old and
+/// new versions of the same change.
+fn changed_blocks(lines: &[(String, bool)]) -> Vec<(usize, Vec<&str>)> {
+    let mut blocks: Vec<(usize, Vec<&str>)> = Vec::new();
+    let mut context_count = 0;
+    for (text, changed) in lines {
+        if !*changed {
+            context_count += 1;
+            continue;
+        }
+        match blocks.last_mut() {
+            Some((position, block)) if *position == context_count => block.push(text),
+            _ => blocks.push((context_count, vec![text])),
+        }
+    }
+    return blocks;
+}
+
+/// Key paths of each line in a two-version JSON hunk. The lines start with
+/// their `-`, `+` or ` ` prefixes. Context lines get their new paths, and
+/// other lines get empty paths.
+pub(crate) fn json_hunk_line_paths(
+    lines: &[String],
+    old_from_start: bool,
+    new_from_start: bool,
+) -> Vec<String> {
+    let side_paths = |prefix: char, from_start: bool| {
+        let texts = lines
+            .iter()
+            .filter(|line| line.starts_with([prefix, ' ']) || line.is_empty())
+            .map(|line| line.get(1..).unwrap_or_default())
+            .collect::<Vec<_>>();
+        return json_line_paths(&texts, from_start).into_iter();
+    };
+    let mut old_paths = side_paths('-', old_from_start);
+    let mut new_paths = side_paths('+', new_from_start);
+
+    return lines
+        .iter()
+        .map(|line| match line.chars().next() {
+            Some('-') => old_paths.next(),
+            Some('+') => new_paths.next(),
+            Some(' ') | None => {
+                old_paths.next();
+                new_paths.next()
+            }
+            _ => None,
+        })
+        .map(Option::unwrap_or_default)
+        .collect();
+}
+
+/// Is `path` somewhere inside of `container`?
+fn is_inside(path: &str, container: &str) -> bool {
+    let Some(rest) = path.strip_prefix(container) else {
+        return false;
+    };
+    return rest.starts_with('.') || rest.starts_with('[');
+}
+
+/// Render key paths for appending to a hunk header, starting with a space
+pub(crate) fn render_key_paths(paths: &[String]) -> String {
+    if paths.is_empty() {
+        return String::new();
+    }
+
+    let mut rendered = paths
+        .iter()
+        .take(MAX_RENDERED_KEY_PATHS)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if paths.len() > MAX_RENDERED_KEY_PATHS {
+        rendered.push_str(", …");
+    }
+    return format!(" {FAINT}({rendered}){NORMAL}");
+}
+
+/// One step in a key path
+#[derive(Debug, Clone)]
+enum Segment {
+    /// An object key
+    Key(String),
+
+    /// An array index
+    Index(usize),
+
+    /// Something we didn't see the start of
+    Unknown,
+}
+
+fn join_path<'a>(segments: impl Iterator<Item = &'a Segment>) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Key(key) => {
+                if !path.is_empty() && !path.ends_with('…') {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Segment::Index(index) => path.push_str(&format!("[{index}]")),
+            Segment::Unknown => {
+                if path.is_empty() {
+                    path.push('…');
+                } else {
+                    path.push_str("[?]");
+                }
+            }
+        }
+    }
+    return path;
+}
+
+#[derive(Debug)]
+struct JsonFrame {
+    /// How we got into this container from its parent. None for the root.
+    segment: Option<Segment>,
+
+    is_array: bool,
+
+    /// Index of the current array element
+    index: usize,
+
+    /// Key of the current object member
+    key: Option<String>,
+}
+
+impl JsonFrame {
+    fn unknown() -> Self {
+        return JsonFrame {
+            segment: Some(Segment::Unknown),
+            is_array: false,
+            index: 0,
+            key: None,
+        };
+    }
+
+    /// The segment for the current member of this container
+    fn member_segment(&self) -> Segment {
+        if self.is_array {
+            return Segment::Index(self.index);
+        }
+        return match &self.key {
+            Some(key) => Segment::Key(key.clone()),
+            None => Segment::Unknown,
+        };
+    }
+}
+
+fn json_path(stack: &[JsonFrame], member: Option<Segment>) -> String {
+    let segments = stack
+        .iter()
+        .filter_map(|frame| frame.segment.clone())
+        .chain(member)
+        .collect::<Vec<_>>();
+    return join_path(segments.iter());
+}
+
+/// Figure out the key path of each line in a piece of JSON.
+///
+/// This is a heuristic working line by line, made for pretty printed JSON.
+/// Lines get the path of the first member they mention.
+fn json_line_paths(lines: &[&str], from_start: bool) -> Vec<String> {
+    let mut stack: Vec<JsonFrame> = Vec::new();
+    if !from_start {
+        stack.push(JsonFrame::unknown());
+    }
+
+    let mut paths = Vec::with_capacity(lines.len());
+    for line in lines {
+        let mut line_path: Option<String> = None;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    let mut string = String::new();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => {
+                                if let Some(escaped) = chars.next() {
+                                    string.push(escaped);
+                                }
+                            }
+                            '"' => break,
+                            _ => string.push(c),
+                        }
+                    }
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
+                    }
+
+                    if chars.peek() == Some(&':') {
+                        chars.next();
+                        if let Some(frame) = stack.last_mut() {
+                            frame.is_array = false;
+                            frame.key = Some(string);
+                        }
+                    }
+                    if line_path.is_none() {
+                        let member = stack.last().map(JsonFrame::member_segment);
+                        line_path = Some(json_path(&stack, member));
+                    }
+                }
+                '{' | '[' => {
+                    let segment = stack.last().map(JsonFrame::member_segment);
+                    if line_path.is_none() {
+                        line_path = Some(json_path(&stack, segment.clone()));
+                    }
+                    stack.push(JsonFrame {
+                        segment,
+                        is_array: c == '[',
+                        index: 0,
+                        key: None,
+                    });
+                }
+                '}' | ']' => {
+                    if line_path.is_none() {
+                        line_path = Some(json_path(&stack, None));
+                    }
+                    stack.pop();
+                    if stack.is_empty() {
+                        // We didn't see the start of this container
+                        stack.push(JsonFrame::unknown());
+                    }
+                }
+                ',' => {
+                    if let Some(frame) = stack.last_mut() {
+                        frame.index += 1;
+                        frame.key = None;
+                    }
+                }
+                _ if c.is_whitespace() => {}
+                _ => {
+                    // Some other value, like a number or `true`
+                    if line_path.is_none() {
+                        let member = stack.last().map(JsonFrame::member_segment);
+                        line_path = Some(json_path(&stack, member));
+                    }
+                }
+            }
+        }
+
+        paths.push(line_path.unwrap_or_default());
+    }
+
+    return paths;
+}
+
+#[derive(Debug)]
+struct YamlEntry {
+    indent: usize,
+    segment: Segment,
+    is_item: bool,
+}
+
+/// Figure out the key path of each line in a piece of YAML, based on
+/// indentation.
+fn yaml_line_paths(lines: &[&str], from_start: bool) -> Vec<String> {
+    let mut stack: Vec<YamlEntry> = Vec::new();
+
+    // Lines indented deeper than this are part of a `|` or `>` block
+    let mut block_indent: Option<usize> = None;
+
+    let path_of = |stack: &[YamlEntry]| {
+        let root_seen = from_start || stack.first().is_some_and(|entry| entry.indent == 0);
+        let unknown_root = if root_seen {
+            None
+        } else {
+            Some(Segment::Unknown)
+        };
+        let segments = unknown_root
+            .into_iter()
+            .chain(stack.iter().map(|entry| entry.segment.clone()))
+            .collect::<Vec<_>>();
+        return join_path(segments.iter());
+    };
+
+    let mut paths = Vec::with_capacity(lines.len());
+    for line in lines {
+        let content = line.trim_start();
+        let mut indent = line.len() - content.len();
+        let mut content = content.trim_end();
+
+        if let Some(block) = block_indent {
+            if content.is_empty() || indent > block {
+                paths.push(path_of(&stack));
+                continue;
+            }
+            block_indent = None;
+        }
+
+        if content.is_empty() || content.starts_with('#') {
+            paths.push(String::new());
+            continue;
+        }
+        if content == "---" || content == "..." {
+            // New document
+            stack.clear();
+            paths.push(String::new());
+            continue;
+        }
+
+        loop {
+            if content == "-" || content.starts_with("- ") {
+                let mut previous_index = None;
+                while let Some(top) = stack.last() {
+                    if top.indent < indent || (top.indent == indent && !top.is_item) {
+                        break;
+                    }
+                    if top.indent == indent {
+                        if let Segment::Index(index) = top.segment {
+                            previous_index = Some(index);
+                        }
+                        if let Segment::Unknown = top.segment {
+                            previous_index = None;
+                        }
+                    }
+                    stack.pop();
+                }
+
+                let segment = match previous_index {
+                    Some(index) => Segment::Index(index + 1),
+                    None if from_start || !stack.is_empty() => Segment::Index(0),
+                    None => Segment::Unknown,
+                };
+                stack.push(YamlEntry {
+                    indent,
+                    segment,
+                    is_item: true,
+                });
+
+                let rest = content[1..].trim_start();
+                indent += content.len() - rest.len();
+                content = rest;
+                if content.is_empty() {
+                    break;
+                }
+                continue;
+            }
+
+            if let Some((key, value)) = split_yaml_key(content) {
+                while stack.last().is_some_and(|top| top.indent >= indent) {
+                    stack.pop();
+                }
+                stack.push(YamlEntry {
+                    indent,
+                    segment: Segment::Key(key),
+                    is_item: false,
+                });
+
+                if value.starts_with('|') || value.starts_with('>') {
+                    block_indent = Some(indent);
+                }
+            }
+            break;
+        }
+
+        paths.push(path_of(&stack));
+    }
+
+    return paths;
+}
+
+/// Split `key: value` into its key and value parts. Returns `None` if this
+/// isn't a key.
+fn split_yaml_key(content: &str) -> Option<(String, &str)> {
+    if let Some(quote) = content.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = content[1..].find(quote)? + 1;
+        let rest = content[end + 1..].trim_start();
+        let value = rest.strip_prefix(':')?;
+        if !value.is_empty() && !value.starts_with(' ') {
+            return None;
+        }
+        return Some((content[1..end].to_string(), value.trim_start()));
+    }
+
+    let colon = content
+        .match_indices(':')
+        .map(|(index, _)| index)
+        .find(|index| {
+            let after = &content[index + 1..];
+            after.is_empty() || after.starts_with(' ')
+        })?;
+    let key = content[..colon].trim_end();
+    if key.is_empty() || key.contains(" #") || key.starts_with(['{', '[']) {
+        return None;
+    }
+    return Some((key.to_string(), content[colon + 1..].trim_start()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_from_filename() {
+        assert_eq!(
+            StructuredFormat::from_filename("b/package.json"),
+            Some(StructuredFormat::Json)
+        );
+        assert_eq!(
+            StructuredFormat::from_filename("deploy.YML"),
+            Some(StructuredFormat::Yaml)
+        );
+        assert_eq!(
+            StructuredFormat::from_filename("fixtures/users.csv"),
+            Some(StructuredFormat::Csv)
+        );
+        assert_eq!(StructuredFormat::from_filename("main.rs"), None);
+        assert_eq!(StructuredFormat::from_filename("Makefile"), None);
+    }
+
+    #[test]
+    fn test_json_line_paths() {
+        let lines = [
+            "{",
+            "  \"spec\": {",
+            "    \"containers\": [",
+            "      {",
+            "        \"image\": \"nginx\",",
+            "        \"ports\": [80, 443]",
+            "      },",
+            "      {",
+            "        \"image\": \"redis\"",
+            "      }",
+            "    ]",
+            "  }",
+            "}",
+        ];
+        assert_eq!(
+            json_line_paths(&lines, true),
+            [
+                "",
+                "spec",
+                "spec.containers",
+                "spec.containers[0]",
+                "spec.containers[0].image",
+                "spec.containers[0].ports",
+                "spec.containers[0]",
+                "spec.containers[1]",
+                "spec.containers[1].image",
+                "spec.containers[1]",
+                "spec.containers",
+                "spec",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_line_paths_partial() {
+        let lines = [
+            "    \"name\": \"x\",",
+            "    \"version\": \"1.0\"",
+            "  },",
+            "  \"b\": 5",
+        ];
+        assert_eq!(
+            json_line_paths(&lines, false),
+            ["…name", "…version", "…", "…b"]
+        );
+    }
+
+    #[test]
+    fn test_yaml_line_paths() {
+        let lines = [
+            "spec:",
+            "  containers:",
+            "  - name: web",
+            "    image: nginx",
+            "    args:",
+            "      - a",
+            "      - b",
+            "  - name: cache",
+            "    script: |",
+            "      echo: hello",
+            "    image: redis",
+            "",
+            "# Comment",
+            "kind: Pod",
+        ];
+        assert_eq!(
+            yaml_line_paths(&lines, true),
+            [
+                "spec",
+                "spec.containers",
+                "spec.containers[0].name",
+                "spec.containers[0].image",
+                "spec.containers[0].args",
+                "spec.containers[0].args[0]",
+                "spec.containers[0].args[1]",
+                "spec.containers[1].name",
+                "spec.containers[1].script",
+                "spec.containers[1].script",
+                "spec.containers[1].image",
+                "",
+                "",
+                "kind",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yaml_line_paths_partial() {
+        let lines = [
+            "    image: nginx",
+            "  - name: cache",
+            "metadata:",
+            "  name: x",
+        ];
+        assert_eq!(
+            yaml_line_paths(&lines, false),
+            ["…image", "…[?].name", "metadata", "metadata.name"]
+        );
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let mut collector = KeyPathCollector::new(StructuredFormat::Yaml, 1, 1, None);
+        for line in [
+            " spec:",
+            "   replicas: 1",
+            "-  image: nginx:1.0",
+            "+  image: nginx:1.1",
+            "+  resources:",
+            "+    cpu: 1",
+        ] {
+            collector.consume_line(line);
+        }
+        assert_eq!(collector.changed_paths(), ["spec.image", "spec.resources"]);
+    }
+
+    #[test]
+    fn test_changed_columns() {
+        let mut collector = KeyPathCollector::new(StructuredFormat::Csv, 1, 1, None);
+        for line in [
+            " id,name,\"unit price\",qty",
+            "-1,apple,5,10",
+            "-2,pear,7,3",
+            "+1,apple,6,10",
+            "+2,pear,7,4",
+            "+3,plum,1,1",
+            " 4,kiwi,2,2",
+            "-5,fig,1,1",
+        ] {
+            collector.consume_line(line);
+        }
+        assert_eq!(collector.changed_paths(), ["unit price", "qty"]);
+    }
+
+    #[test]
+    fn test_changed_columns_later_hunk() {
+        // Header row from an earlier hunk, one extra column in the new line
+        let mut collector =
+            KeyPathCollector::new(StructuredFormat::Tsv, 10, 10, Some("a\tb".to_string()));
+        for line in ["-x\ty", "+x\tz\tw"] {
+            collector.consume_line(line);
+        }
+        assert_eq!(collector.changed_paths(), ["b", "column 3"]);
+    }
+
+    #[test]
+    fn test_render_key_paths() {
+        assert_eq!(render_key_paths(&[]), "");
+        assert_eq!(
+            render_key_paths(&["a".to_string(), "b".to_string()]),
+            format!(" {FAINT}(a, b){NORMAL}")
+        );
+        assert_eq!(
+            render_key_paths(&[
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+                "d".to_string()
+            ]),
+            format!(" {FAINT}(a, b, c, …){NORMAL}")
+        );
+    }
+}
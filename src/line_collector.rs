@@ -1,9 +1,14 @@
 use crate::ansi::without_ansi_escape_codes;
-use crate::commit_line::format_commit_line;
+use crate::commit_line::{format_commit_line, format_oneline_commit_line, is_oneline_commit_line};
+use crate::commit_subject::{format_subject, lint_note};
 use crate::conflicts_highlighter::ConflictsHighlighter;
 use crate::file_highlighter::FileHighlighter;
+use crate::graph::{colorize, prefix_lines, Graph};
 use crate::io::ErrorKind;
+use crate::issue_links::link_issues;
 use crate::lines_highlighter::{LineAcceptance, LinesHighlighter};
+use crate::log_header::HeaderField;
+use crate::range_diff_highlighter::RangeDiffHighlighter;
 use crate::refiner::Formatter;
 use crate::rename_highlighter::RenameHighlighter;
 use once_cell::sync::Lazy;
@@ -12,10 +17,21 @@ use std::process::{self, exit};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{constants::*, string_future::StringFuture};
 use threadpool::ThreadPool;
 
+/// What `LineCollector` does besides refining, see `Formatter` for that
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct CollectorOptions {
+    /// Show how long ago commits were made, see `--relative-dates`
+    pub(crate) relative_dates: bool,
+
+    /// Note style problems in commit messages, see `--lint-commit-messages`
+    pub(crate) lint_commit_messages: bool,
+}
+
 static STATIC_HEADER_PREFIXES: Lazy<Vec<(&'static str, &'static str)>> = Lazy::new(|| {
     vec![
         ("diff ", FAINT),
@@ -94,6 +110,37 @@ pub(crate) struct LineCollector {
     /// onwards will come with highlighted backgrounds, based on this value.
     diff_seen: bool,
 
+    /// Set on `commit` lines, cleared when the commit's diff starts. In
+    /// between are the commit header, and the commit message indented by four
+    /// spaces.
+    in_commit_message: bool,
+
+    /// Number of commit message lines seen since the last `commit` line. The
+    /// first one is the subject.
+    commit_message_lines: usize,
+
+    /// Set once we have seen a `commit` line or a `git log --oneline` line,
+    /// see `in_log()`
+    log_seen: bool,
+
+    /// Commit message lines that look like trailers, plus any blank lines
+    /// after them. Only the last paragraph of a message has trailers, so these
+    /// are held back until we know whether their paragraph is the last one.
+    held_trailers: Vec<HeldMessageLine>,
+
+    /// Set after a commit message line that isn't a trailer, until the next
+    /// blank line. Trailer lookalikes in such paragraphs are just text.
+    in_prose_paragraph: bool,
+
+    /// Set on the first `git log --graph` commit line
+    graph: Option<Graph>,
+
+    /// Colorized graph in front of the current line, empty without `--graph`
+    graph_prefix: String,
+
+    /// Colorized graph in front of the lines of the current lines highlighter
+    highlighter_graph_prefix: String,
+
     consumer_thread: Option<JoinHandle<()>>,
     thread_pool: ThreadPool,
 
@@ -104,11 +151,13 @@ pub(crate) struct LineCollector {
     print_queue_putter: SyncSender<StringFuture>,
 
     formatter: Formatter,
+    options: CollectorOptions,
 }
 
 impl Drop for LineCollector {
     fn drop(&mut self) {
         // Flush outstanding lines
+        self.release_held_trailers(true);
         self.drain_plain();
 
         if let Some(lines_highlighter) = self.lines_highlighter.as_mut() {
@@ -120,7 +169,7 @@ impl Drop for LineCollector {
             }
 
             for highlight in result.unwrap() {
-                self.print_queue_putter.send(highlight).unwrap();
+                self.send_highlighted(highlight);
             }
         }
 
@@ -141,6 +190,7 @@ impl LineCollector {
         output: W,
         color: bool,
         formatter: Formatter,
+        options: CollectorOptions,
     ) -> LineCollector {
         // This is how many entries we can look ahead. An "entry" in this case
         // being either a plain text section or an oldnew section.
@@ -180,12 +230,21 @@ impl LineCollector {
             lines_highlighter: None,
             plain_text: String::from(""),
             diff_seen: false,
+            in_commit_message: false,
+            commit_message_lines: 0,
+            log_seen: false,
+            held_trailers: Vec::new(),
+            in_prose_paragraph: false,
+            graph: None,
+            graph_prefix: String::new(),
+            highlighter_graph_prefix: String::new(),
 
             consumer_thread: Some(consumer),
             thread_pool: ThreadPool::new(num_cpus::get()),
             print_queue_putter: queue_putter,
 
             formatter,
+            options,
         };
     }
 
@@ -202,15 +261,29 @@ impl LineCollector {
         self.plain_text.clear();
     }
 
+    /// Enqueue output from the lines highlighter, with the graph in front of
+    /// each line when highlighting `git log --graph` output
+    fn send_highlighted(&self, highlight: StringFuture) {
+        let highlight = if self.highlighter_graph_prefix.is_empty() {
+            highlight
+        } else {
+            let prefix = self.highlighter_graph_prefix.clone();
+            highlight.map(move |text| prefix_lines(&text, &prefix))
+        };
+        self.print_queue_putter.send(highlight).unwrap();
+    }
+
     fn consume_plain_line(&mut self, line: &str) {
-        assert!(self.lines_highlighter.is_none());
-        self.plain_text.push_str(line);
+        self.consume_plain_linepart(line);
         self.plain_text.push('\n');
     }
 
     /// Like consume_plain_line(), but without outputting any trailing linefeed.
     fn consume_plain_linepart(&mut self, linepart: &str) {
         assert!(self.lines_highlighter.is_none());
+        if self.plain_text.is_empty() || self.plain_text.ends_with('\n') {
+            self.plain_text.push_str(&self.graph_prefix);
+        }
         self.plain_text.push_str(linepart);
     }
 
@@ -259,6 +332,35 @@ impl LineCollector {
             String::from_utf8_lossy(raw_line).to_string()
         };
 
+        // Logs can be colored even if the first line wasn't, like with
+        // `git log --oneline --graph --color`. We restyle those ourselves.
+        // Lines inside of diffs never start logs.
+        let line = if !strip_incoming_formatting && line.contains('\x1b') {
+            let stripped = without_ansi_escape_codes(raw_line);
+            let stripped = String::from_utf8_lossy(&stripped).to_string();
+            if self.graph.is_some()
+                || (self.lines_highlighter.is_none()
+                    && (Graph::from_commit_line(&stripped).is_some()
+                        || is_oneline_commit_line(&stripped, self.in_log())))
+            {
+                stripped
+            } else {
+                line
+            }
+        } else {
+            line
+        };
+
+        // With `git log --graph`, highlight what's after the graph as usual
+        let full_line = line;
+        let mut line = self.split_graph(&full_line);
+
+        let in_message = self.in_commit_message && line.starts_with("    ");
+        if !in_message {
+            // The commit message is done, so its last paragraph was the last
+            self.release_held_trailers(true);
+        }
+
         if line.starts_with('\\') {
             {
                 // Store the "\ No newline at end of file" string however it is
@@ -282,7 +384,7 @@ impl LineCollector {
 
             let response = result.unwrap();
             for highlight in response.highlighted {
-                self.print_queue_putter.send(highlight).unwrap();
+                self.send_highlighted(highlight);
             }
 
             match response.line_accepted {
@@ -299,28 +401,53 @@ impl LineCollector {
             }
         }
 
+        // Only lines outside of diffs and commit messages can be commit lines
+        // starting new graphs
+        if let Some(graph) = Graph::from_commit_line(&full_line).filter(|_| !in_message) {
+            self.graph = Some(graph);
+            line = self.split_graph(&full_line);
+        }
+
         if let Some(file_highlighter) = FileHighlighter::from_line(&line, self.formatter.clone()) {
             self.drain_plain();
+            self.in_commit_message = false;
+            self.highlighter_graph_prefix = self.graph_prefix.clone();
             self.lines_highlighter = Some(Box::new(file_highlighter));
             return Ok(());
         }
 
-        if let Some(conflicts_highlighter) = ConflictsHighlighter::from_line(&line) {
+        if let Some(conflicts_highlighter) =
+            ConflictsHighlighter::from_line(&line, self.formatter.clone())
+        {
             // We get here if the input is not a diff, but some random file
             // containing merge conflict markers.
             self.drain_plain();
+            self.highlighter_graph_prefix = self.graph_prefix.clone();
             self.lines_highlighter = Some(Box::new(conflicts_highlighter));
             return Ok(());
         }
 
-        if let Some(rename_highlighter) = RenameHighlighter::from_line(&line) {
+        if let Some(rename_highlighter) =
+            RenameHighlighter::from_line(&line, self.formatter.clone())
+        {
             self.drain_plain();
+            self.highlighter_graph_prefix = self.graph_prefix.clone();
             self.lines_highlighter = Some(Box::new(rename_highlighter));
             return Ok(());
         }
 
+        if let Some(range_diff_highlighter) =
+            RangeDiffHighlighter::from_line(&line, self.formatter.clone())
+        {
+            self.drain_plain();
+            self.highlighter_graph_prefix = self.graph_prefix.clone();
+            self.lines_highlighter = Some(Box::new(range_diff_highlighter));
+            return Ok(());
+        }
+
         if line.starts_with("diff") {
             self.diff_seen = true;
+            self.in_commit_message = false;
         }
 
         if let Some(fixed_highlight) = get_fixed_highlight(&line) {
@@ -331,10 +458,36 @@ impl LineCollector {
         }
 
         if line.starts_with("commit") {
-            self.consume_plain_line(&format_commit_line(&line, self.diff_seen));
+            let commit = line.split_whitespace().nth(1);
+            if let Some(commit) = commit.filter(|word| word.chars().all(|c| c.is_ascii_hexdigit()))
+            {
+                // For the {commit} placeholder in --hyperlink-format
+                let hyperlinker = self.formatter.hyperlinker().with_commit(commit);
+                self.formatter = self.formatter.with_hyperlinker(hyperlinker);
+            }
+            self.consume_plain_line(&format_commit_line(
+                &line,
+                self.diff_seen,
+                self.formatter.hyperlinker().forge_links(),
+            ));
+            self.in_commit_message = true;
+            self.commit_message_lines = 0;
+            self.log_seen = true;
             return Ok(());
         }
 
+        if is_oneline_commit_line(&line, self.in_log()) {
+            if let Some(oneline) = format_oneline_commit_line(
+                &line,
+                self.formatter.hyperlinker(),
+                self.options.lint_commit_messages,
+            ) {
+                self.consume_plain_line(&oneline);
+                self.log_seen = true;
+                return Ok(());
+            }
+        }
+
         if line.starts_with('\\') {
             // "\ No newline at end of file"
             self.consume_plain_line(&format!("{NO_EOF_NEWLINE_COLOR}{line}{NORMAL}"));
@@ -346,7 +499,139 @@ impl LineCollector {
             return Ok(());
         }
 
+        if self.in_commit_message && line.starts_with("    ") {
+            self.commit_message_lines += 1;
+            self.consume_commit_message_line(&line);
+            return Ok(());
+        }
+
+        if self.in_commit_message {
+            if let Some(field) = HeaderField::from_header_line(&line) {
+                let now = if self.options.relative_dates {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|now| now.as_secs() as i64)
+                } else {
+                    None
+                };
+                let rendered = field.render(self.formatter.hyperlinker(), now);
+                self.consume_plain_line(&rendered);
+                return Ok(());
+            }
+        }
+
+        if self.graph.is_some() {
+            // raw_line starts with the graph
+            self.consume_plain_line(&line);
+            return Ok(());
+        }
+
         self.consume_plain_line(String::from_utf8_lossy(raw_line).as_ref());
         return Ok(());
     }
+
+    /// Are we highlighting `git log` output? Plain lines that look like
+    /// `--oneline` commit lines are only taken to be ones if we are.
+    fn in_log(&self) -> bool {
+        return self.log_seen || self.graph.is_some();
+    }
+
+    /// `line` without any `git log --graph` graph in front of it. The graph
+    /// goes into `self.graph_prefix`.
+    fn split_graph(&mut self, line: &str) -> String {
+        let Some(graph) = &self.graph else {
+            return line.to_string();
+        };
+        let (prefix, rest) = graph.split(line);
+        self.graph_prefix = colorize(prefix);
+        return rest.to_string();
+    }
+
+    /// `line` is indented by four spaces, and `self.commit_message_lines`
+    /// says which message line it is
+    fn consume_commit_message_line(&mut self, line: &str) {
+        let held = HeldMessageLine {
+            graph_prefix: self.graph_prefix.clone(),
+            line: line.to_string(),
+            number: self.commit_message_lines,
+        };
+        if held.number == 1 {
+            self.consume_message_line(&held, false);
+            return;
+        }
+
+        if line.trim().is_empty() {
+            self.in_prose_paragraph = false;
+            if self.held_trailers.is_empty() {
+                self.consume_message_line(&held, false);
+            } else {
+                self.held_trailers.push(held);
+            }
+            return;
+        }
+
+        let new_paragraph = self
+            .held_trailers
+            .last()
+            .is_some_and(|last| last.line.trim().is_empty());
+        if new_paragraph {
+            // The held back paragraph wasn't the last one
+            self.release_held_trailers(false);
+        }
+
+        if !self.in_prose_paragraph && HeaderField::from_message_line(line).is_some() {
+            self.held_trailers.push(held);
+            return;
+        }
+
+        // Trailers must be in a paragraph of their own
+        self.release_held_trailers(false);
+        self.in_prose_paragraph = true;
+        self.consume_message_line(&held, false);
+    }
+
+    /// Print the held back trailer lookalikes, as trailers if `as_trailers` is
+    /// set, otherwise as any other message lines
+    fn release_held_trailers(&mut self, as_trailers: bool) {
+        let held_trailers = std::mem::take(&mut self.held_trailers);
+        let graph_prefix = std::mem::take(&mut self.graph_prefix);
+        for held in &held_trailers {
+            self.graph_prefix.clone_from(&held.graph_prefix);
+            self.consume_message_line(held, as_trailers);
+        }
+        self.graph_prefix = graph_prefix;
+    }
+
+    fn consume_message_line(&mut self, held: &HeldMessageLine, as_trailer: bool) {
+        let hyperlinker = self.formatter.hyperlinker();
+        let lint = self.options.lint_commit_messages;
+        let line = held.line.as_str();
+        if held.number == 1 {
+            let rendered = format_subject(line, hyperlinker.issue_links(), lint);
+            self.consume_plain_line(&rendered);
+            return;
+        }
+
+        let trailer = Some(line)
+            .filter(|_| as_trailer)
+            .and_then(HeaderField::from_message_line);
+        let mut rendered = match trailer {
+            Some(trailer) => trailer.render(hyperlinker, None),
+            None => link_issues(line, hyperlinker.issue_links()),
+        };
+        if lint && held.number == 2 && !line.trim().is_empty() {
+            rendered += &lint_note(&["no blank line after the subject".to_string()]);
+        }
+        self.consume_plain_line(&rendered);
+    }
+}
+
+/// A commit message line, see `LineCollector::held_trailers`
+struct HeldMessageLine {
+    graph_prefix: String,
+    line: String,
+
+    /// Which message line this is, 1 for the subject
+    number: usize,
 }
@@ -0,0 +1,386 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::constants::*;
+use crate::hyperlink::{hyperlink, Hyperlinker};
+use crate::issue_links::link_issues;
+
+// Highlight the lines following a "commit" line in `git log` output
+
+/// Like git's trailers, but with at least one dash in the key so that we don't
+/// mistake "Note: ..." for one.
+static TRAILER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^( +[A-Za-z0-9]+(?:-[A-Za-z0-9]+)+: +)(\S.*)$").unwrap());
+
+/// One field of a `git log` commit header, or a trailer at the end of a commit
+/// message.
+///
+/// Each `label` is everything up to the value, like `"Date:   "` or
+/// `"    Signed-off-by: "`, so that rendering keeps the original alignment.
+#[derive(Debug, PartialEq)]
+pub(crate) enum HeaderField<'a> {
+    /// `Author: Name <email>`, `Commit: ...` or `    Co-authored-by: ...`
+    Person {
+        label: &'a str,
+        name: &'a str,
+        email: Option<&'a str>,
+    },
+
+    /// `Date: ...`, `AuthorDate: ...` or `CommitDate: ...`
+    Date { label: &'a str, date: &'a str },
+
+    /// `Merge: 1234abc 5678def`
+    Merge {
+        label: &'a str,
+        parents: Vec<&'a str>,
+    },
+
+    /// Any other trailer, like `    Change-Id: I1234abc`
+    Trailer { label: &'a str, value: &'a str },
+}
+
+impl<'a> HeaderField<'a> {
+    /// Parse a header line, the non-indented lines between the `commit` line
+    /// and the commit message.
+    pub(crate) fn from_header_line(line: &'a str) -> Option<Self> {
+        let (key, rest) = line.split_once(':')?;
+        let (label, value) = line.split_at(line.len() - rest.trim_start().len());
+        if value.is_empty() {
+            return None;
+        }
+
+        return match key {
+            "Author" | "Commit" => Some(Self::person(label, value)),
+            "Date" | "AuthorDate" | "CommitDate" => Some(HeaderField::Date { label, date: value }),
+            "Merge" => Some(HeaderField::Merge {
+                label,
+                parents: value.split_whitespace().collect(),
+            }),
+            _ => None,
+        };
+    }
+
+    /// Parse a commit message line, returns `None` unless it is a trailer
+    pub(crate) fn from_message_line(line: &'a str) -> Option<Self> {
+        let captures = TRAILER.captures(line)?;
+        let label = captures.get(1).unwrap().as_str();
+        let value = captures.get(2).unwrap().as_str();
+        if value.ends_with('>') && value.contains(" <") {
+            return Some(Self::person(label, value));
+        }
+        return Some(HeaderField::Trailer { label, value });
+    }
+
+    /// `value` is like `Name <email>`
+    fn person(label: &'a str, value: &'a str) -> Self {
+        let Some((name, email)) = value
+            .strip_suffix('>')
+            .and_then(|value| value.rsplit_once(" <"))
+        else {
+            return HeaderField::Person {
+                label,
+                name: value,
+                email: None,
+            };
+        };
+        return HeaderField::Person {
+            label,
+            name,
+            email: Some(email),
+        };
+    }
+
+    /// If `now` is set, in seconds since the epoch, dates get a relative time
+    /// like "(3 days ago)" after them.
+    pub(crate) fn render(&self, hyperlinker: &Hyperlinker, now: Option<i64>) -> String {
+        let label = match self {
+            HeaderField::Person { label, .. }
+            | HeaderField::Date { label, .. }
+            | HeaderField::Merge { label, .. }
+            | HeaderField::Trailer { label, .. } => label,
+        };
+        let label = format!("{FAINT}{label}{NORMAL_INTENSITY}");
+
+        return match self {
+            HeaderField::Person { name, email, .. } => match email {
+                Some(email) => {
+                    format!("{label}{BOLD}{name}{NORMAL_INTENSITY} {FAINT}<{email}>{NORMAL}")
+                }
+                None => format!("{label}{BOLD}{name}{NORMAL}"),
+            },
+            HeaderField::Date { date, .. } => {
+                let relative = now
+                    .zip(parse_date(date))
+                    .and_then(|(now, timestamp)| relative_date(now - timestamp));
+                match relative {
+                    Some(relative) => format!("{label}{date} {FAINT}({relative}){NORMAL}"),
+                    None => format!("{label}{date}{NORMAL}"),
+                }
+            }
+            HeaderField::Merge { parents, .. } => {
+                let parents = parents
+                    .iter()
+                    .map(|parent| {
+                        match hyperlinker
+                            .forge_links()
+                            .and_then(|links| links.commit_url(parent))
+                        {
+                            Some(url) => hyperlink(&url, parent),
+                            None => parent.to_string(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                format!("{label}{YELLOW}{}{NORMAL}", parents.join(" "))
+            }
+            HeaderField::Trailer { value, .. } => {
+                format!(
+                    "{label}{}{NORMAL}",
+                    link_issues(value, hyperlinker.issue_links())
+                )
+            }
+        };
+    }
+}
+
+/// Seconds since the epoch for the date formats `git log` prints by default
+/// and with `--date=iso`, `--date=iso-strict` and `--date=rfc`. Returns `None`
+/// for anything else, like `--date=relative`.
+fn parse_date(date: &str) -> Option<i64> {
+    let words = date.split_whitespace().collect::<Vec<_>>();
+    return match words.as_slice() {
+        // Mon, 16 Oct 2023 14:03:05 +0200
+        [weekday, day, month, year, time, zone] if weekday.ends_with(',') => {
+            timestamp(year, month_number(month)?, day, time, zone)
+        }
+
+        // Mon Oct 16 14:03:05 2023 +0200
+        [_weekday, month, day, time, year, zone] => {
+            timestamp(year, month_number(month)?, day, time, zone)
+        }
+
+        // 2023-10-16 14:03:05 +0200
+        [date, time, zone] => {
+            let mut parts = date.splitn(3, '-');
+            let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+            timestamp(year, month.parse().ok()?, day, time, zone)
+        }
+
+        // 2023-10-16T14:03:05+02:00
+        [iso] => {
+            let (date, time_and_zone) = iso.split_once('T')?;
+            let zone_start = time_and_zone.find(['+', '-', 'Z'])?;
+            let (time, zone) = time_and_zone.split_at(zone_start);
+            let mut parts = date.splitn(3, '-');
+            let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+            timestamp(year, month.parse().ok()?, day, time, zone)
+        }
+
+        _ => None,
+    };
+}
+
+fn month_number(month: &str) -> Option<i64> {
+    let months = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let index = months.iter().position(|candidate| *candidate == month)?;
+    return Some(index as i64 + 1);
+}
+
+/// `time` is like `14:03:05`, `zone` like `+0200`, `+02:00` or `Z`
+fn timestamp(year: &str, month: i64, day: &str, time: &str, zone: &str) -> Option<i64> {
+    let year: i64 = year.parse().ok()?;
+    let day: i64 = day.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut hms = time.splitn(3, ':');
+    let hours: i64 = hms.next()?.parse().ok()?;
+    let minutes: i64 = hms.next()?.parse().ok()?;
+    let seconds: i64 = hms.next()?.parse().ok()?;
+
+    let offset = if zone == "Z" {
+        0
+    } else {
+        let sign = match zone.get(..1)? {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        let digits = zone[1..].replace(':', "");
+        if digits.len() != 4 {
+            return None;
+        }
+        let zone_hours: i64 = digits[..2].parse().ok()?;
+        let zone_minutes: i64 = digits[2..].parse().ok()?;
+        sign * (zone_hours * 3600 + zone_minutes * 60)
+    };
+
+    let local = days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+    return Some(local - offset);
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar, from
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// Rounded like `git log --date=relative` does it. Returns `None` for dates
+/// in the future.
+fn relative_date(seconds_ago: i64) -> Option<String> {
+    if seconds_ago < 0 {
+        return None;
+    }
+    if seconds_ago < 90 {
+        return Some(ago(seconds_ago, "second"));
+    }
+
+    let minutes = (seconds_ago + 30) / 60;
+    if minutes < 90 {
+        return Some(ago(minutes, "minute"));
+    }
+
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return Some(ago(hours, "hour"));
+    }
+
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return Some(ago(days, "day"));
+    }
+    if days < 70 {
+        return Some(ago((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return Some(ago((days + 15) / 30, "month"));
+    }
+    return Some(ago((days + 183) / 365, "year"));
+}
+
+fn ago(count: i64, unit: &str) -> String {
+    if count == 1 {
+        return format!("1 {unit} ago");
+    }
+    return format!("{count} {unit}s ago");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_from_header_line() {
+        assert_eq!(
+            HeaderField::from_header_line("Author: Johan Walles <johan.walles@gmail.com>"),
+            Some(HeaderField::Person {
+                label: "Author: ",
+                name: "Johan Walles",
+                email: Some("johan.walles@gmail.com"),
+            })
+        );
+        assert_eq!(
+            HeaderField::from_header_line("CommitDate: Mon Oct 24 00:32:27 2022 +0200"),
+            Some(HeaderField::Date {
+                label: "CommitDate: ",
+                date: "Mon Oct 24 00:32:27 2022 +0200",
+            })
+        );
+        assert_eq!(
+            HeaderField::from_header_line("Merge: 234d1fe fb7d21c"),
+            Some(HeaderField::Merge {
+                label: "Merge: ",
+                parents: vec!["234d1fe", "fb7d21c"],
+            })
+        );
+        assert_eq!(HeaderField::from_header_line("Author:"), None);
+        assert_eq!(HeaderField::from_header_line("Subject: Hello"), None);
+    }
+
+    #[test]
+    fn test_from_message_line() {
+        assert_eq!(
+            HeaderField::from_message_line("    Co-authored-by: Jane Doe <jane@example.com>"),
+            Some(HeaderField::Person {
+                label: "    Co-authored-by: ",
+                name: "Jane Doe",
+                email: Some("jane@example.com"),
+            })
+        );
+        assert_eq!(
+            HeaderField::from_message_line("    Change-Id: I1234abc"),
+            Some(HeaderField::Trailer {
+                label: "    Change-Id: ",
+                value: "I1234abc",
+            })
+        );
+        assert_eq!(
+            HeaderField::from_message_line("    Note: this is not a trailer"),
+            None
+        );
+        assert_eq!(HeaderField::from_message_line("    Fix the thing"), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let date = HeaderField::from_header_line("Date:   Mon Oct 24 00:32:27 2022 +0200").unwrap();
+        assert_eq!(
+            date.render(&Hyperlinker::default(), None),
+            format!("{FAINT}Date:   {NORMAL_INTENSITY}Mon Oct 24 00:32:27 2022 +0200{NORMAL}")
+        );
+
+        let three_days_later = parse_date("Mon Oct 24 00:32:27 2022 +0200").unwrap() + 3 * 86400;
+        assert_eq!(
+            date.render(&Hyperlinker::default(), Some(three_days_later)),
+            format!(
+                "{FAINT}Date:   {NORMAL_INTENSITY}Mon Oct 24 00:32:27 2022 +0200 {FAINT}(3 days ago){NORMAL}"
+            )
+        );
+
+        let author =
+            HeaderField::from_header_line("Author: Johan Walles <johan.walles@gmail.com>").unwrap();
+        assert_eq!(
+            author.render(&Hyperlinker::default(), None),
+            format!(
+                "{FAINT}Author: {NORMAL_INTENSITY}{BOLD}Johan Walles{NORMAL_INTENSITY} {FAINT}<johan.walles@gmail.com>{NORMAL}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_date() {
+        // date -d 2022-10-23T22:32:27Z +%s
+        let expected = Some(1666564347);
+        assert_eq!(parse_date("Mon Oct 24 00:32:27 2022 +0200"), expected);
+        assert_eq!(parse_date("Mon, 24 Oct 2022 00:32:27 +0200"), expected);
+        assert_eq!(parse_date("2022-10-24 00:32:27 +0200"), expected);
+        assert_eq!(parse_date("2022-10-24T00:32:27+02:00"), expected);
+        assert_eq!(parse_date("2022-10-23T22:32:27Z"), expected);
+        assert_eq!(parse_date("Sun Oct 23 17:32:27 2022 -0500"), expected);
+
+        assert_eq!(parse_date("3 days ago"), None);
+        assert_eq!(parse_date("Mon Foo 24 00:32:27 2022 +0200"), None);
+    }
+
+    #[test]
+    fn test_relative_date() {
+        assert_eq!(relative_date(-5), None);
+        assert_eq!(relative_date(1).as_deref(), Some("1 second ago"));
+        assert_eq!(relative_date(45 * 60).as_deref(), Some("45 minutes ago"));
+        assert_eq!(relative_date(5 * 3600).as_deref(), Some("5 hours ago"));
+        assert_eq!(relative_date(3 * 86400).as_deref(), Some("3 days ago"));
+        assert_eq!(relative_date(30 * 86400).as_deref(), Some("4 weeks ago"));
+        assert_eq!(relative_date(100 * 86400).as_deref(), Some("3 months ago"));
+        assert_eq!(relative_date(800 * 86400).as_deref(), Some("2 years ago"));
+    }
+}
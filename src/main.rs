@@ -6,34 +6,58 @@
 #![deny(warnings)]
 
 use backtrace::Backtrace;
+use canonicalize::CanonicalCopies;
 use clap::CommandFactory;
 use clap::Parser;
 use clap::ValueEnum;
+use forge::ForgeLinks;
 use git_version::git_version;
-use line_collector::LineCollector;
+use hyperlink::Hyperlinker;
+use issue_links::IssueLink;
+use key_path::StructuredFormat;
+use line_collector::{CollectorOptions, LineCollector};
 use logging::init_logger;
+use normalizer::Normalizer;
+use refiner::DiffOptions;
 use refiner::Formatter;
+use refiner::RefineAlgorithm;
 use std::io::{self, IsTerminal};
 use std::panic;
 use std::path::{self, PathBuf};
 use std::process::exit;
 use std::process::{Command, Stdio};
 use std::str;
-use std::{env, fs::File};
+use std::time::Duration;
+use std::{env, fs, fs::File};
 
 mod ansi;
+mod canonicalize;
 mod commit_line;
+mod commit_subject;
 mod conflicts_highlighter;
 mod constants;
+mod dependencies;
 mod file_highlighter;
+mod forge;
+mod generated;
+mod graph;
+mod histogram_diff;
 mod hunk_header;
 mod hunk_highlighter;
+mod hyperlink;
+mod issue_links;
+mod key_path;
 mod line_collector;
 mod lines_highlighter;
+mod log_header;
 mod logging;
+mod normalizer;
+mod notebook;
 mod plusminus_lines_highlighter;
+mod range_diff_highlighter;
 mod refiner;
 mod rename_highlighter;
+mod repository;
 mod string_future;
 mod token_collector;
 mod tokenizer;
@@ -96,6 +120,11 @@ struct Options {
     #[arg(long, short('w'), conflicts_with_all = ["ignore_space_change"])]
     ignore_all_space: bool,
 
+    /// Pretty print JSON and YAML files with sorted keys before comparing them.
+    /// Jupyter notebooks are always compared cell by cell.
+    #[arg(long, requires("fd2"))]
+    canonicalize: bool,
+
     /// Don't page the result
     #[arg(long)]
     no_pager: bool,
@@ -111,6 +140,76 @@ struct Options {
     #[arg(long)]
     color: Option<ColorOption>,
 
+    /// How long to spend refining each block of changed lines before falling
+    /// back to simpler highlighting
+    #[arg(long, value_name = "MILLISECONDS", default_value = "500")]
+    refine_budget: u64,
+
+    /// Diff algorithm to use when highlighting changed line parts
+    #[arg(long, value_name = "ALGORITHM", default_value = "patience")]
+    refine_algorithm: RefineAlgorithm,
+
+    /// Also highlight short unchanged line parts between two changes
+    #[arg(long)]
+    semantic_cleanup: bool,
+
+    /// Show lines that only changed indentation as context lines
+    #[arg(long)]
+    collapse_reindented: bool,
+
+    /// Don't highlight differences inside of regex matches, like timestamps.
+    /// Can be given multiple times.
+    #[arg(long, short('I'), value_name = "REGEX", value_parser = Normalizer::from_ignore_pattern)]
+    ignore_matching: Vec<Normalizer>,
+
+    /// Replace regex matches before highlighting differences. Can be given
+    /// multiple times.
+    #[arg(long, value_name = "REGEX=REPLACEMENT", value_parser = Normalizer::from_normalize_spec)]
+    normalize: Vec<Normalizer>,
+
+    /// Pad CSV and TSV cells so that the columns of - and + lines line up
+    #[arg(long)]
+    align_columns: bool,
+
+    /// Show generated files and lock files in full, rather than as one line
+    /// summaries
+    #[arg(long)]
+    no_collapse: bool,
+
+    /// Link file names, hunk headers and lines using this URL template, like
+    /// `vscode://file/{path}:{line}`. Placeholders are {path}, {relpath},
+    /// {line}, {repo} and {commit}.
+    #[arg(long, value_name = "TEMPLATE", value_parser = Hyperlinker::from_template)]
+    hyperlink_format: Option<Hyperlinker>,
+
+    /// Link commits, tags and branches in `git log` output using this URL
+    /// template, like `https://review.example.com/{repo}/+/{ref}`.
+    /// Placeholders are {host}, {repo} and {ref}. GitHub, GitLab, Gitea and
+    /// Bitbucket remotes are linked without this.
+    #[arg(long, value_name = "TEMPLATE", value_parser = forge::parse_template)]
+    commit_link_format: Option<String>,
+
+    /// Link issue references in commit messages, like
+    /// `#(\d+)=https://github.com/owner/repo/issues/{id}`. {id} is the first
+    /// capture group, or the whole match. Can be given multiple times.
+    #[arg(long, value_name = "REGEX=TEMPLATE", value_parser = IssueLink::from_spec)]
+    issue_link: Vec<IssueLink>,
+
+    /// In `git log` output, show how long ago each date was, like "(3 days
+    /// ago)"
+    #[arg(long)]
+    relative_dates: bool,
+
+    /// In `git log` output, note commit subjects longer than 72 characters or
+    /// ending with a period, and subjects not followed by a blank line
+    #[arg(long)]
+    lint_commit_messages: bool,
+
+    /// In merge diffs, color changed tokens by which parent they came from.
+    /// Each parent's color is the prefix color of its `-` lines.
+    #[arg(long)]
+    refine_per_parent: bool,
+
     #[arg(long, hide(true))]
     please_panic: bool,
 }
@@ -161,8 +260,9 @@ fn highlight_diff_or_exit<W: io::Write + Send + 'static>(
     output: W,
     color: bool,
     formatter: Formatter,
+    collector_options: CollectorOptions,
 ) {
-    if let Err(message) = highlight_diff(input, output, color, formatter) {
+    if let Err(message) = highlight_diff(input, output, color, formatter, collector_options) {
         eprintln!("{message}");
         exit(1);
     }
@@ -175,8 +275,9 @@ fn highlight_diff<W: io::Write + Send + 'static>(
     output: W,
     color: bool,
     formatter: Formatter,
+    collector_options: CollectorOptions,
 ) -> Result<(), String> {
-    let mut line_collector = LineCollector::new(output, color, formatter);
+    let mut line_collector = LineCollector::new(output, color, formatter, collector_options);
 
     // Read input line by line, using from_utf8_lossy() to convert lines into
     // strings while handling invalid UTF-8 without crashing
@@ -244,6 +345,7 @@ fn try_pager(
     pager_space_separated: &str,
     color: bool,
     formatter: Formatter,
+    collector_options: CollectorOptions,
 ) -> bool {
     if pager_space_separated.is_empty() {
         return false;
@@ -277,7 +379,7 @@ fn try_pager(
         Ok(mut pager) => {
             let pager_stdin = pager.stdin.unwrap();
             pager.stdin = None;
-            highlight_diff_or_exit(input, pager_stdin, color, formatter);
+            highlight_diff_or_exit(input, pager_stdin, color, formatter, collector_options);
 
             // FIXME: Report pager exit status if non-zero, together with
             // contents of pager stderr as well if possible.
@@ -326,20 +428,32 @@ fn panic_handler<T: std::fmt::Debug>(panic_info: &T) {
 }
 
 /// Highlight the given stream, paging if stdout is a terminal
-fn highlight_stream(input: &mut dyn io::Read, no_pager: bool, color: bool, formatter: Formatter) {
+fn highlight_stream(
+    input: &mut dyn io::Read,
+    no_pager: bool,
+    color: bool,
+    formatter: Formatter,
+    collector_options: CollectorOptions,
+) {
     if !io::stdout().is_terminal() {
         // We're being piped, just do stdin -> stdout
-        highlight_diff_or_exit(input, io::stdout(), color, formatter);
+        highlight_diff_or_exit(input, io::stdout(), color, formatter, collector_options);
         return;
     }
 
     if no_pager {
-        highlight_diff_or_exit(input, io::stdout(), color, formatter);
+        highlight_diff_or_exit(input, io::stdout(), color, formatter, collector_options);
         return;
     }
 
     if let Ok(pager_value) = env::var("PAGER") {
-        if try_pager(input, &pager_value, color, formatter.clone()) {
+        if try_pager(
+            input,
+            &pager_value,
+            color,
+            formatter.clone(),
+            collector_options,
+        ) {
             return;
         }
 
@@ -347,21 +461,27 @@ fn highlight_stream(input: &mut dyn io::Read, no_pager: bool, color: bool, forma
         // doesn't exist.
     }
 
-    if try_pager(input, "moor", color, formatter.clone()) {
+    if try_pager(input, "moor", color, formatter.clone(), collector_options) {
         return;
     }
 
     // Old name for moor: https://github.com/walles/moor/pull/305
-    if try_pager(input, "moar", color, formatter.clone()) {
+    if try_pager(input, "moar", color, formatter.clone(), collector_options) {
         return;
     }
 
-    if try_pager(input, "less", color, formatter.clone()) {
+    if try_pager(input, "less", color, formatter.clone(), collector_options) {
         return;
     }
 
     // No pager found, wth?
-    highlight_diff_or_exit(input, io::stdout(), color, formatter.clone());
+    highlight_diff_or_exit(
+        input,
+        io::stdout(),
+        color,
+        formatter.clone(),
+        collector_options,
+    );
 }
 
 /// `Not found`, `File`, `Directory` or `Not file not dir`
@@ -393,17 +513,21 @@ fn ensure_listable(path: &path::Path) {
 }
 
 /// Run the `diff` binary on the two paths and highlight the output
+#[allow(clippy::too_many_arguments)]
 fn exec_diff_highlight(
     path1: &str,
     path2: &str,
     ignore_space_change: bool,
     ignore_all_space: bool,
+    canonicalize: bool,
     no_pager: bool,
     color: bool,
     formatter: Formatter,
+    collector_options: CollectorOptions,
 ) {
     let path1 = path::Path::new(path1);
     let path2 = path::Path::new(path2);
+
     let both_paths_are_non_dirs = !path1.is_dir() && !path2.is_dir();
     let both_paths_are_dirs = path1.is_dir() && path2.is_dir();
 
@@ -422,9 +546,55 @@ fn exec_diff_highlight(
         ensure_listable(path2);
     }
 
+    // Notebooks are compared cell by cell, see `notebook::render_notebook()`
+    let is_notebook = |path: &path::Path| {
+        return StructuredFormat::from_filename(&path.to_string_lossy())
+            == Some(StructuredFormat::Notebook);
+    };
+    let notebooks = both_paths_are_non_dirs && is_notebook(path1) && is_notebook(path2);
+
+    // Diff canonical copies of the files instead of the files themselves.
+    // Removes the copies when dropped, so it must live until we're done
+    // diffing.
+    let canonical_copies = if canonicalize || notebooks {
+        if !both_paths_are_non_dirs {
+            eprintln!("ERROR: --canonicalize can only compare files, not directories");
+            exit(1);
+        }
+        match CanonicalCopies::new(path1, path2) {
+            Ok(copies) => Some(copies),
+            Err(message) => {
+                eprintln!("ERROR: {message}");
+                exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Notebook cells are highlighted in the notebook's language rather than
+    // as notebook JSON
+    let formatter = if notebooks {
+        let text = fs::read_to_string(path2).unwrap_or_default();
+        formatter.with_notebook_cells(Some(notebook::language_profile(&text)))
+    } else {
+        formatter
+    };
+
     // Run "diff -ur file1 file2"
     let mut command: &mut Command = &mut Command::new("diff");
 
+    let (path1, path2) = match &canonical_copies {
+        Some(copies) => {
+            // Show the original file names, not the names of the copies
+            command = command
+                .arg(format!("--label={}", path1.to_string_lossy()))
+                .arg(format!("--label={}", path2.to_string_lossy()));
+            (copies.path1.as_path(), copies.path2.as_path())
+        }
+        None => (path1, path2),
+    };
+
     if ignore_space_change {
         command = command.arg("-b");
     }
@@ -452,7 +622,7 @@ fn exec_diff_highlight(
     }
 
     let diff_stdout = diff_subprocess.stdout.as_mut().unwrap();
-    highlight_stream(diff_stdout, no_pager, color, formatter);
+    highlight_stream(diff_stdout, no_pager, color, formatter, collector_options);
 
     let diff_result = diff_subprocess.wait().unwrap();
     let diff_exit_code = diff_result.code().unwrap_or(2);
@@ -460,6 +630,7 @@ fn exec_diff_highlight(
         // diff exit code was neither 0 (comparees identical) or 1 (differences
         // found), this means trouble.
         eprintln!("Exit code {diff_exit_code}: {pretty_command}");
+        drop(canonical_copies);
         exit(diff_exit_code);
     }
 }
@@ -516,6 +687,35 @@ fn main() {
         UnchangedStyle::RedGreen => Formatter::default(),
         UnchangedStyle::Yellow => Formatter::yellow(),
     };
+    let formatter = formatter.with_refine_budget(Duration::from_millis(options.refine_budget));
+    let formatter = formatter.with_diff_options(DiffOptions {
+        algorithm: options.refine_algorithm,
+        semantic_cleanup: options.semantic_cleanup,
+        ..DiffOptions::default()
+    });
+    let formatter = formatter.with_collapse_reindented(options.collapse_reindented);
+    let formatter = formatter.with_normalizers(
+        options
+            .ignore_matching
+            .into_iter()
+            .chain(options.normalize)
+            .collect(),
+    );
+    let formatter = formatter.with_align_columns(options.align_columns);
+    let formatter = formatter.with_collapse_generated(!options.no_collapse);
+    let formatter = formatter.with_refine_per_parent(options.refine_per_parent);
+    let hyperlinker = options
+        .hyperlink_format
+        .unwrap_or_default()
+        .with_forge_links(ForgeLinks::from_current_directory(
+            options.commit_link_format.as_deref(),
+        ))
+        .with_issue_links(options.issue_link);
+    let formatter = formatter.with_hyperlinker(hyperlinker);
+    let collector_options = CollectorOptions {
+        relative_dates: options.relative_dates,
+        lint_commit_messages: options.lint_commit_messages,
+    };
 
     if let (Some(file1), Some(file2)) = (options.fd1, options.fd2) {
         // "riff file1 file2"
@@ -524,12 +724,14 @@ fn main() {
             &file2,
             options.ignore_space_change,
             options.ignore_all_space,
+            options.canonicalize,
             options.no_pager,
             options
                 .color
                 .unwrap_or(ColorOption::Auto)
                 .bool_or(io::stdout().is_terminal()),
             formatter,
+            collector_options,
         );
         return;
     }
@@ -556,6 +758,7 @@ fn main() {
                 .unwrap_or(ColorOption::Auto)
                 .bool_or(io::stdout().is_terminal()),
             formatter,
+            collector_options,
         );
         return;
     }
@@ -578,6 +781,7 @@ fn main() {
             .unwrap_or(ColorOption::Auto)
             .bool_or(io::stdout().is_terminal()),
         formatter,
+        collector_options,
     );
 
     let logs = logger.get_logs();
@@ -630,6 +834,7 @@ mod tests {
             file.reopen().unwrap(),
             true,
             Formatter::default(),
+            CollectorOptions::default(),
         ) {
             panic!("{}", error);
         }
@@ -799,13 +1004,22 @@ mod tests {
         input_file: &PathBuf,
         expected_output_file: &PathBuf,
     ) -> Option<ExampleFailure> {
+        // Refining must not depend on how fast this machine is, or the
+        // output would differ between runs
+        let formatter = Formatter::default().with_refine_budget(Duration::from_secs(3600));
+
+        // Examples named collapsed-* test collapsing generated files
+        let file_name = input_file.file_name().unwrap().to_str().unwrap();
+        let formatter = formatter.with_collapse_generated(file_name.starts_with("collapsed-"));
+
         // Run highlighting on the file into a memory buffer
         let file = tempfile::NamedTempFile::new().unwrap();
         if let Err(error) = highlight_diff(
             &mut fs::File::open(input_file).unwrap(),
             file.reopen().unwrap(),
             true,
-            Formatter::default(),
+            formatter.clone(),
+            CollectorOptions::default(),
         ) {
             return Some(ExampleFailure {
                 diagnostics: format!("Highlighting failed: {error}"),
@@ -833,7 +1047,8 @@ mod tests {
             &mut fs::File::open(input_file).unwrap(),
             file.reopen().unwrap(),
             false,
-            Formatter::default(),
+            formatter,
+            CollectorOptions::default(),
         )
         .unwrap();
 
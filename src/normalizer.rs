@@ -0,0 +1,231 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use regex::Regex;
+
+/// Rewrites parts of lines before refining them, see `--ignore-matching` and
+/// `--normalize`.
+///
+/// Things like timestamps and UUIDs that always differ can be normalized to
+/// something constant, and then they won't be highlighted as changes.
+#[derive(Clone)]
+pub(crate) struct Normalizer {
+    regex: Regex,
+    replacement: String,
+}
+
+impl Normalizer {
+    /// For `--ignore-matching`: All matches are considered equal
+    pub(crate) fn from_ignore_pattern(pattern: &str) -> Result<Normalizer, String> {
+        return Normalizer::new(pattern, "");
+    }
+
+    /// For `--normalize`: Parse `REGEX=REPLACEMENT`. Since the regex is more
+    /// likely than the replacement to contain `=`, we split on the last one.
+    pub(crate) fn from_normalize_spec(spec: &str) -> Result<Normalizer, String> {
+        let Some((pattern, replacement)) = spec.rsplit_once('=') else {
+            return Err(format!("Expected REGEX=REPLACEMENT, got: {spec}"));
+        };
+        return Normalizer::new(pattern, replacement);
+    }
+
+    fn new(pattern: &str, replacement: &str) -> Result<Normalizer, String> {
+        if pattern.is_empty() {
+            return Err("Empty regex".to_string());
+        }
+        let regex = Regex::new(pattern).map_err(|error| error.to_string())?;
+        return Ok(Normalizer {
+            regex,
+            replacement: replacement.to_string(),
+        });
+    }
+}
+
+impl PartialEq for Normalizer {
+    fn eq(&self, other: &Self) -> bool {
+        return self.regex.as_str() == other.regex.as_str()
+            && self.replacement == other.replacement;
+    }
+}
+
+impl fmt::Debug for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}={}", self.regex.as_str(), self.replacement);
+    }
+}
+
+/// Merge all tokens touched by any normalizer match into one token per match,
+/// and compute a diff key for each resulting token.
+///
+/// `tokens` must be `text` split into consecutive pieces, as done by
+/// `tokenizer::tokenize()`.
+///
+/// Returns the (possibly merged) tokens plus one key per token. Diffing the
+/// keys rather than the tokens makes matches compare equal after
+/// normalization.
+pub(crate) fn normalize_tokens<'a>(
+    text: &'a str,
+    tokens: Vec<&'a str>,
+    normalizers: &[Normalizer],
+) -> (Vec<&'a str>, Vec<Cow<'a, str>>) {
+    // Byte ranges of all matches, sorted and with overlaps merged. Matches
+    // are clipped at newlines, since a lot of code looks for "\n" tokens.
+    let mut spans: Vec<(usize, usize)> = normalizers
+        .iter()
+        .flat_map(|normalizer| normalizer.regex.find_iter(text))
+        .flat_map(|found| split_at_newlines(text, found.start(), found.end()))
+        .filter(|(start, end)| start < end)
+        .collect();
+    spans.sort_unstable();
+    let mut merged_spans: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged_spans.last_mut() {
+            Some((_, last_end)) if start < *last_end => *last_end = (*last_end).max(end),
+            _ => merged_spans.push((start, end)),
+        }
+    }
+    if merged_spans.is_empty() {
+        let keys = tokens.iter().map(|token| Cow::Borrowed(*token)).collect();
+        return (tokens, keys);
+    }
+
+    let mut normalized_tokens = Vec::with_capacity(tokens.len());
+    let mut keys = Vec::with_capacity(tokens.len());
+    let mut spans = merged_spans.into_iter().peekable();
+    let mut token_start = 0;
+    let mut merge_start: Option<usize> = None;
+    for token in tokens {
+        let token_end = token_start + token.len();
+
+        // Skip spans ending before this token
+        while spans
+            .peek()
+            .is_some_and(|(_, span_end)| *span_end <= token_start)
+        {
+            spans.next();
+        }
+
+        let in_span = spans
+            .peek()
+            .is_some_and(|(span_start, _)| *span_start < token_end);
+        if in_span && merge_start.is_none() {
+            merge_start = Some(token_start);
+        }
+
+        let span_continues = spans
+            .peek()
+            .is_some_and(|(span_start, span_end)| *span_start < token_end && *span_end > token_end);
+        if let Some(start) = merge_start {
+            if !span_continues {
+                let merged = &text[start..token_end];
+                normalized_tokens.push(merged);
+                keys.push(normalize(merged, normalizers));
+                merge_start = None;
+            }
+        } else {
+            normalized_tokens.push(token);
+            keys.push(Cow::Borrowed(token));
+        }
+
+        token_start = token_end;
+    }
+
+    return (normalized_tokens, keys);
+}
+
+/// Split the `start..end` byte range of `text` into the ranges between its
+/// newlines
+fn split_at_newlines(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut range_start = start;
+    for (index, _) in text[start..end].match_indices('\n') {
+        ranges.push((range_start, start + index));
+        range_start = start + index + 1;
+    }
+    ranges.push((range_start, end));
+    return ranges;
+}
+
+/// `line` without its trailing newline, and with all normalizer matches
+/// replaced. For comparing whole lines rather than tokens.
+pub(crate) fn normalize_line<'a>(line: &'a str, normalizers: &[Normalizer]) -> Cow<'a, str> {
+    return normalize(line.strip_suffix('\n').unwrap_or(line), normalizers);
+}
+
+fn normalize<'a>(text: &'a str, normalizers: &[Normalizer]) -> Cow<'a, str> {
+    let mut normalized = Cow::Borrowed(text);
+    for normalizer in normalizers {
+        if let Cow::Owned(replaced) = normalizer
+            .regex
+            .replace_all(&normalized, normalizer.replacement.as_str())
+        {
+            normalized = Cow::Owned(replaced);
+        }
+    }
+    return normalized;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{tokenize, Profile};
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_from_normalize_spec() {
+        let normalizer = Normalizer::from_normalize_spec("0x[0-9a-f]+=ADDR").unwrap();
+        assert_eq!(format!("{normalizer:?}"), "0x[0-9a-f]+=ADDR");
+
+        // Split on the last =
+        let normalizer = Normalizer::from_normalize_spec("a=b=c").unwrap();
+        assert_eq!(format!("{normalizer:?}"), "a=b=c");
+
+        assert!(Normalizer::from_normalize_spec("no equals sign").is_err());
+        assert!(Normalizer::from_normalize_spec("(=x").is_err());
+        assert!(Normalizer::from_normalize_spec("=x").is_err());
+    }
+
+    #[test]
+    fn test_normalize_tokens() {
+        let normalizers = [Normalizer::from_normalize_spec("0x[0-9a-f]+=ADDR").unwrap()];
+        let text = "at 0x7ffe12 in main\n";
+        let (tokens, keys) = normalize_tokens(text, tokenize(text, Profile::Generic), &normalizers);
+        assert_eq!(
+            tokens,
+            ["at", " ", "0x7ffe12", " ", "in", " ", "main", "\n"]
+        );
+        assert_eq!(keys, ["at", " ", "ADDR", " ", "in", " ", "main", "\n"]);
+    }
+
+    #[test]
+    fn test_normalize_tokens_partial_token_match() {
+        // The match starts and ends inside of tokens, so those tokens get
+        // merged in with the match
+        let normalizers = [Normalizer::from_ignore_pattern("[0-9]:[0-9]").unwrap()];
+        let text = "a12:34b c\n";
+        let (tokens, keys) = normalize_tokens(text, tokenize(text, Profile::Generic), &normalizers);
+        assert_eq!(tokens, ["a12:34b", " ", "c", "\n"]);
+        assert_eq!(keys, ["a14b", " ", "c", "\n"]);
+    }
+
+    #[test]
+    fn test_normalize_tokens_across_newlines() {
+        // The match is clipped at the newline, which stays a token of its own
+        let normalizers = [Normalizer::from_ignore_pattern(r"\s+").unwrap()];
+        let text = "a \n b\n";
+        let (tokens, keys) = normalize_tokens(text, tokenize(text, Profile::Generic), &normalizers);
+        assert_eq!(tokens, ["a", " ", "\n", " ", "b", "\n"]);
+        assert_eq!(keys, ["a", "", "\n", "", "b", "\n"]);
+    }
+
+    #[test]
+    fn test_normalize_tokens_no_matches() {
+        let normalizers = [Normalizer::from_ignore_pattern("x").unwrap()];
+        let text = "a b\n";
+        let (tokens, keys) = normalize_tokens(text, tokenize(text, Profile::Generic), &normalizers);
+        assert_eq!(tokens, ["a", " ", "b", "\n"]);
+        assert_eq!(keys, ["a", " ", "b", "\n"]);
+    }
+}
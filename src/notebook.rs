@@ -0,0 +1,414 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use serde_json::Value;
+
+use crate::constants::{FAINT, NORMAL};
+use crate::key_path::json_hunk_line_paths;
+use crate::tokenizer::Profile;
+
+/// Cell members that we summarize rather than show
+const SUMMARIZED_KEYS: &[&str] = &["outputs", "metadata", "execution_count"];
+
+/// Keys only found inside of cell outputs. If a hunk starts in the middle of
+/// some output, these tell us where we are.
+const OUTPUT_KEYS: &[&str] = &[
+    "output_type",
+    "data",
+    "text",
+    "name",
+    "traceback",
+    "ename",
+    "evalue",
+];
+
+/// Render a Jupyter notebook as a list of cells for diffing.
+///
+/// Each cell gets a `# %% [cell_type]` header followed by its source. Outputs
+/// and metadata are summarized into one line each, with a fingerprint that
+/// changes if they do. Execution counts change on every run and are left out.
+pub(crate) fn render_notebook(text: &str) -> Result<String, String> {
+    let notebook: Value = serde_json::from_str(text).map_err(|error| error.to_string())?;
+    let Some(cells) = notebook.get("cells").and_then(Value::as_array) else {
+        return Err("Not a Jupyter notebook, no cells found".to_string());
+    };
+
+    let mut rendered = String::new();
+    for cell in cells {
+        let cell_type = cell
+            .get("cell_type")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+        rendered.push_str(&format!("# %% [{cell_type}]\n"));
+
+        let source = multiline_text(cell.get("source"));
+        rendered.push_str(&source);
+        if !source.is_empty() && !source.ends_with('\n') {
+            rendered.push('\n');
+        }
+
+        if let Some(outputs) = cell.get("outputs").and_then(Value::as_array) {
+            if !outputs.is_empty() {
+                rendered.push_str(&format!("# outputs: {}\n", summarize_outputs(outputs)));
+            }
+        }
+
+        if let Some(metadata) = non_empty_object(cell.get("metadata")) {
+            rendered.push_str(&format!("# metadata: {}\n", fingerprint(metadata)));
+        }
+    }
+
+    if let Some(metadata) = non_empty_object(notebook.get("metadata")) {
+        rendered.push_str(&format!("# notebook metadata: {}\n", fingerprint(metadata)));
+    }
+
+    return Ok(rendered);
+}
+
+/// Tokenizer profile for the code cells of a Jupyter notebook, based on the
+/// language in the notebook's metadata
+pub(crate) fn language_profile(text: &str) -> Profile {
+    let Ok(notebook) = serde_json::from_str::<Value>(text) else {
+        return Profile::Generic;
+    };
+    let metadata = notebook.get("metadata");
+
+    let language_info = metadata.and_then(|metadata| metadata.get("language_info"));
+    if let Some(extension) = language_info
+        .and_then(|language_info| language_info.get("file_extension"))
+        .and_then(Value::as_str)
+    {
+        return Profile::from_filename(&format!("cell{extension}"));
+    }
+
+    let language = language_info
+        .and_then(|language_info| language_info.get("name"))
+        .or_else(|| {
+            metadata
+                .and_then(|metadata| metadata.get("kernelspec"))
+                .and_then(|kernelspec| kernelspec.get("language"))
+        })
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    return match language.to_ascii_lowercase().as_str() {
+        "python" => Profile::Python,
+        "rust" => Profile::Rust,
+        "bash" | "sh" => Profile::Shell,
+        _ => Profile::Generic,
+    };
+}
+
+/// Notebook texts are either strings or arrays of lines
+fn multiline_text(value: Option<&Value>) -> String {
+    return match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    };
+}
+
+fn non_empty_object(value: Option<&Value>) -> Option<&Value> {
+    return value.filter(|value| value.as_object().is_some_and(|object| !object.is_empty()));
+}
+
+/// Like `stream, image/png (1a2b3c4d)`
+fn summarize_outputs(outputs: &[Value]) -> String {
+    let mut kinds: Vec<&str> = Vec::new();
+    let mut hashed_outputs: Vec<Value> = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let output_type = output
+            .get("output_type")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+        let output_kinds: Vec<&str> = match output.get("data").and_then(Value::as_object) {
+            // MIME types, like "text/plain"
+            Some(data) => data.keys().map(String::as_str).collect(),
+            None => vec![output_type],
+        };
+        for kind in output_kinds {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+
+        // Re-running a cell changes its execution count even if the output
+        // stays the same
+        let mut hashed_output = output.clone();
+        if let Some(object) = hashed_output.as_object_mut() {
+            object.remove("execution_count");
+        }
+        hashed_outputs.push(hashed_output);
+    }
+
+    return format!(
+        "{} ({})",
+        kinds.join(", "),
+        fingerprint(&Value::Array(hashed_outputs))
+    );
+}
+
+/// Short hash of a JSON value, for telling whether it changed
+fn fingerprint(value: &Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.to_string().hash(&mut hasher);
+    return format!("{:08x}", hasher.finish() as u32);
+}
+
+/// Part of a notebook hunk, see `summarize_hunk()`
+#[derive(Debug, PartialEq)]
+pub(crate) enum HunkPart {
+    /// A line to highlight like any other
+    Line(String),
+
+    /// A one line summary of changed output or metadata lines
+    Summary(String),
+}
+
+/// Collects the lines of a notebook hunk, so that they can be summarized when
+/// the hunk is done
+#[derive(Debug)]
+pub(crate) struct NotebookHunk {
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<String>,
+}
+
+impl NotebookHunk {
+    pub(crate) fn new(old_start: usize, new_start: usize) -> Self {
+        return NotebookHunk {
+            old_start,
+            new_start,
+            lines: Vec::new(),
+        };
+    }
+
+    pub(crate) fn push(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+    }
+
+    pub(crate) fn summarize(self) -> Vec<HunkPart> {
+        return summarize_hunk(self.lines, self.old_start, self.new_start);
+    }
+}
+
+/// Replace runs of changed lines inside of cell outputs and metadata with
+/// one line summaries.
+///
+/// `lines` are the lines of one two-version hunk, starting with their `-`,
+/// `+` or ` ` prefixes.
+fn summarize_hunk(lines: Vec<String>, old_start: usize, new_start: usize) -> Vec<HunkPart> {
+    let paths = json_hunk_line_paths(&lines, old_start <= 1, new_start <= 1);
+
+    let mut parts: Vec<HunkPart> = Vec::with_capacity(lines.len());
+
+    // Path, removed line count and added line count of the current run of
+    // summarized lines
+    let mut run: Option<(String, usize, usize)> = None;
+    for (line, path) in lines.into_iter().zip(paths) {
+        let summarized_path = if line.starts_with(['-', '+']) {
+            summarized_path(&path)
+        } else {
+            None
+        };
+
+        if let Some(summarized_path) = summarized_path {
+            if run
+                .as_ref()
+                .is_some_and(|(run_path, _, _)| run_path != summarized_path)
+            {
+                parts.push(render_summary(run.take().unwrap()));
+            }
+            let (_, removed, added) =
+                run.get_or_insert_with(|| (summarized_path.to_string(), 0, 0));
+            if line.starts_with('-') {
+                *removed += 1;
+            } else {
+                *added += 1;
+            }
+            continue;
+        }
+
+        if line.starts_with('\\') && run.is_some() {
+            // "\ No newline at end of file" for a line we just summarized
+            continue;
+        }
+
+        if let Some(run) = run.take() {
+            parts.push(render_summary(run));
+        }
+        parts.push(HunkPart::Line(line));
+    }
+    if let Some(run) = run {
+        parts.push(render_summary(run));
+    }
+
+    return parts;
+}
+
+fn render_summary((path, removed, added): (String, usize, usize)) -> HunkPart {
+    let counts = match (removed, added) {
+        (0, added) => format!("+{added}"),
+        (removed, 0) => format!("-{removed}"),
+        (removed, added) => format!("-{removed} +{added}"),
+    };
+    return HunkPart::Summary(format!("{FAINT}~ {path} changed ({counts} lines){NORMAL}"));
+}
+
+/// If `path` points into some cell's outputs or metadata, return the path to
+/// those outputs or that metadata.
+pub(crate) fn summarized_path(path: &str) -> Option<&str> {
+    let mut segment_start = 0;
+    for (index, character) in path.char_indices().chain([(path.len(), '.')]) {
+        if character != '.' && character != '[' {
+            continue;
+        }
+
+        let segment = path[segment_start..index].trim_start_matches('…');
+        if SUMMARIZED_KEYS.contains(&segment) {
+            return Some(&path[..index]);
+        }
+        if OUTPUT_KEYS.contains(&segment) || segment.contains('/') {
+            // We're in some output, but didn't see where it started. MIME
+            // types like "image/png" are output data keys.
+            return Some("…outputs");
+        }
+
+        segment_start = index + character.len_utf8();
+    }
+
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    const NOTEBOOK: &str = r##"{
+ "cells": [
+  {
+   "cell_type": "markdown",
+   "metadata": {},
+   "source": ["# Title\n", "Some text"]
+  },
+  {
+   "cell_type": "code",
+   "execution_count": 3,
+   "metadata": {"scrolled": true},
+   "outputs": [
+    {"name": "stdout", "output_type": "stream", "text": ["hello\n"]},
+    {"data": {"image/png": "iVBORw0KGgo=", "text/plain": ["<Figure>"]},
+     "execution_count": 3, "metadata": {}, "output_type": "execute_result"}
+   ],
+   "source": "print('hello')\nplot()"
+  }
+ ],
+ "metadata": {},
+ "nbformat": 4,
+ "nbformat_minor": 5
+}"##;
+
+    #[test]
+    fn test_render_notebook() {
+        let rendered = render_notebook(NOTEBOOK).unwrap();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(
+            &lines[..6],
+            [
+                "# %% [markdown]",
+                "# Title",
+                "Some text",
+                "# %% [code]",
+                "print('hello')",
+                "plot()",
+            ]
+        );
+        assert!(lines[6].starts_with("# outputs: stream, image/png, text/plain ("));
+        assert!(lines[7].starts_with("# metadata: "));
+        assert_eq!(lines.len(), 8);
+
+        // Execution counts don't matter
+        let rerun = NOTEBOOK.replace("\"execution_count\": 3", "\"execution_count\": 4");
+        assert_eq!(render_notebook(&rerun).unwrap(), rendered);
+
+        assert!(render_notebook("{}").is_err());
+    }
+
+    #[test]
+    fn test_language_profile() {
+        assert_eq!(
+            language_profile(r#"{"metadata": {"language_info": {"file_extension": ".py"}}}"#),
+            Profile::Python
+        );
+        assert_eq!(
+            language_profile(r#"{"metadata": {"kernelspec": {"language": "rust"}}}"#),
+            Profile::Rust
+        );
+        assert_eq!(
+            language_profile(r#"{"metadata": {"language_info": {"name": "R"}}}"#),
+            Profile::Generic
+        );
+        assert_eq!(language_profile("{}"), Profile::Generic);
+    }
+
+    #[test]
+    fn test_summarized_path() {
+        assert_eq!(
+            summarized_path("cells[1].outputs[0].text[0]"),
+            Some("cells[1].outputs")
+        );
+        assert_eq!(
+            summarized_path("cells[1].metadata.scrolled"),
+            Some("cells[1].metadata")
+        );
+        assert_eq!(
+            summarized_path("…execution_count"),
+            Some("…execution_count")
+        );
+        assert_eq!(summarized_path("…data.image/png"), Some("…outputs"));
+        assert_eq!(summarized_path("cells[1].source[0]"), None);
+        assert_eq!(summarized_path("…"), None);
+    }
+
+    #[test]
+    fn test_summarize_hunk() {
+        let lines = [
+            "   {",
+            "    \"cell_type\": \"code\",",
+            "-   \"execution_count\": 3,",
+            "+   \"execution_count\": 4,",
+            "    \"metadata\": {},",
+            "    \"outputs\": [",
+            "     {",
+            "      \"data\": {",
+            "-      \"image/png\": \"iVBORw0KGgo=\"",
+            "+      \"image/png\": \"iVBORw0KGgp=\"",
+            "      }",
+            "     }",
+            "    ],",
+            "    \"source\": [",
+            "-    \"plot()\"",
+            "+    \"plot(x)\"",
+        ]
+        .map(String::from)
+        .to_vec();
+        let parts = summarize_hunk(lines, 10, 10);
+        assert_eq!(
+            parts[2],
+            HunkPart::Summary(format!(
+                "{FAINT}~ …[?].execution_count changed (-1 +1 lines){NORMAL}"
+            ))
+        );
+        assert_eq!(
+            parts[7],
+            HunkPart::Summary(format!(
+                "{FAINT}~ …[?].outputs changed (-1 +1 lines){NORMAL}"
+            ))
+        );
+        assert_eq!(parts[12], HunkPart::Line("-    \"plot()\"".to_string()));
+        assert_eq!(parts[13], HunkPart::Line("+    \"plot(x)\"".to_string()));
+        assert_eq!(parts.len(), 14);
+    }
+}
@@ -1,5 +1,7 @@
 use threadpool::ThreadPool;
 
+use crate::ansi::without_ansi_escape_codes;
+use crate::constants::{EVIL_MERGE, NORMAL};
 use crate::lines_highlighter::{LineAcceptance, LinesHighlighter, Response};
 use crate::refiner::Formatter;
 use crate::string_future::StringFuture;
@@ -209,6 +211,7 @@ impl PlusMinusLinesHighlighter {
         let texts = self.texts.clone();
         let prefixes = self.prefixes.clone();
         let formatter = self.formatter.clone();
+        let prefix_length = self.prefix_length;
 
         self.texts.clear();
         self.prefixes.clear();
@@ -216,11 +219,10 @@ impl PlusMinusLinesHighlighter {
         let return_me = StringFuture::from_function(
             move || {
                 let mut result = String::new();
-                for line in formatter.format(
-                    &prefixes.iter().map(String::as_str).collect::<Vec<&str>>(),
-                    &texts.iter().map(String::as_str).collect::<Vec<&str>>(),
-                ) {
-                    result.push_str(&line);
+                let prefixes = prefixes.iter().map(String::as_str).collect::<Vec<&str>>();
+                let texts = texts.iter().map(String::as_str).collect::<Vec<&str>>();
+                for line in formatter.format(&prefixes, &texts) {
+                    result.push_str(&highlight_evil_merge(&line, prefix_length));
                     result.push('\n');
                 }
 
@@ -233,6 +235,44 @@ impl PlusMinusLinesHighlighter {
     }
 }
 
+/// In merge diffs, `++` lines are in neither parent, and `--` lines were
+/// removed from both. Either way, they come from the merge itself rather than
+/// from any of the merged branches. This is also known as an "evil merge".
+pub(crate) fn is_evil_merge_prefix(prefix: &str) -> bool {
+    return prefix.len() >= 2
+        && (prefix.chars().all(|c| c == '+') || prefix.chars().all(|c| c == '-'));
+}
+
+/// If `line` is an evil merge line, give its prefix a loud style of its own.
+/// `line` is a highlighted line, starting with its prefix style.
+fn highlight_evil_merge(line: &str, prefix_length: usize) -> String {
+    if prefix_length < 2 {
+        return line.to_string();
+    }
+    let plain = without_ansi_escape_codes(line.as_bytes());
+    let is_evil = plain
+        .get(..prefix_length)
+        .and_then(|prefix| std::str::from_utf8(prefix).ok())
+        .is_some_and(is_evil_merge_prefix);
+    if !is_evil {
+        return line.to_string();
+    }
+
+    // Keep the escape codes in front of the prefix, they style the rest of
+    // the line as well
+    let mut prefix_start = 0;
+    while line[prefix_start..].starts_with("\x1b[") {
+        let Some(length) = line[prefix_start + 2..].find(|c: char| c.is_ascii_alphabetic()) else {
+            return line.to_string();
+        };
+        prefix_start += 2 + length + 1;
+    }
+    let leading_escapes = &line[..prefix_start];
+    let prefix = &line[prefix_start..prefix_start + prefix_length];
+    let rest = &line[prefix_start + prefix_length..];
+    return format!("{EVIL_MERGE}{prefix}{NORMAL}{leading_escapes}{rest}");
+}
+
 #[cfg(test)]
 mod tests {
     use crate::lines_highlighter::LinesHighlighter;
@@ -243,6 +283,38 @@ mod tests {
     };
     use threadpool::ThreadPool;
 
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_is_evil_merge_prefix() {
+        use crate::plusminus_lines_highlighter::is_evil_merge_prefix;
+        assert!(is_evil_merge_prefix("++"));
+        assert!(is_evil_merge_prefix("---"));
+        assert!(!is_evil_merge_prefix("+"));
+        assert!(!is_evil_merge_prefix("+-"));
+        assert!(!is_evil_merge_prefix(" +"));
+    }
+
+    #[test]
+    fn test_highlight_evil_merge() {
+        use crate::constants::{EVIL_MERGE, GREEN, INVERSE_VIDEO, NORMAL};
+        use crate::plusminus_lines_highlighter::highlight_evil_merge;
+
+        let evil = format!("{GREEN}++{INVERSE_VIDEO}->{NORMAL}");
+        assert_eq!(
+            highlight_evil_merge(&evil, 2),
+            format!("{EVIL_MERGE}++{NORMAL}{GREEN}{INVERSE_VIDEO}->{NORMAL}")
+        );
+
+        let not_evil = format!("{GREEN} +added{NORMAL}");
+        assert_eq!(highlight_evil_merge(&not_evil, 2), not_evil);
+
+        // Not a merge diff, just a line starting with a plus
+        let two_pluses = format!("{GREEN}++added{NORMAL}");
+        assert_eq!(highlight_evil_merge(&two_pluses, 1), two_pluses);
+    }
+
     // `\ No newline at end of file` test, based on
     // `testdata/add-remove-trailing-newline.diff`.
     #[test]
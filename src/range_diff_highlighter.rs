@@ -0,0 +1,314 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use threadpool::ThreadPool;
+
+use crate::constants::*;
+use crate::graph::prefix_lines;
+use crate::hunk_header::HUNK_HEADER;
+use crate::hyperlink::{hyperlink, Hyperlinker};
+use crate::lines_highlighter::{LineAcceptance, LinesHighlighter, Response};
+use crate::plusminus_lines_highlighter::PlusMinusLinesHighlighter;
+use crate::refiner::Formatter;
+use crate::string_future::StringFuture;
+
+// Highlight `git range-diff` output, which looks like this:
+//
+//   1:  421035d ! 1:  aa9252a Greet the world
+//       @@ main.rs
+//        @@
+//         fn main() {
+//        -    println!("Hello");
+//       -+    println!("Hello, world");
+//       ++    println!("Hello, world!");
+//        +    greet("Johan");
+//         }
+//   2:  eed4c12 = 2:  d679104 Add greeting function
+//
+// The indented lines are a diff of two diffs. The first column is the outer
+// diff, comparing the old and the new version of the commit. The second
+// column is the inner diff, the commit itself.
+
+/// Like `1:  421035d ! 1:  aa9252a Greet the world`. Commits only on one side
+/// are numbered `-` and have `-------` for a hash.
+static PAIR_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^( *(?:\d+|-): +(?:[0-9a-f]{4,40}|-+)) ([=!<>]) ( *(?:\d+|-): +(?:[0-9a-f]{4,40}|-+)) (.*)$")
+        .unwrap()
+});
+
+const INDENTATION: &str = "    ";
+
+pub(crate) struct RangeDiffHighlighter {
+    /// The highlighted pair header, emitted together with the next line
+    header: Option<String>,
+
+    /// Used when spawning sub highlighters
+    formatter: Formatter,
+
+    /// Refines the current block of changed lines, on the outer or the inner
+    /// diff level depending on `inner`
+    sub_highlighter: Option<PlusMinusLinesHighlighter>,
+
+    /// True if `sub_highlighter` refines inner diff changes. Those lines are
+    /// passed on without their unchanged outer column, so that they are
+    /// refined as a plain one parent diff.
+    inner: bool,
+}
+
+impl LinesHighlighter for RangeDiffHighlighter {
+    fn consume_line(&mut self, line: &str, thread_pool: &ThreadPool) -> Result<Response, String> {
+        let mut highlights: Vec<StringFuture> = Vec::new();
+        if let Some(header) = self.header.take() {
+            highlights.push(StringFuture::from_string(header));
+        }
+
+        let Some(body) = body_of(line) else {
+            // Not part of this commit pair, we're done
+            if let Some(mut sub_highlighter) = self.sub_highlighter.take() {
+                highlights.extend(self.indented(sub_highlighter.consume_eof(thread_pool)?));
+            }
+            return Ok(Response {
+                line_accepted: LineAcceptance::RejectedDone,
+                highlighted: highlights,
+            });
+        };
+
+        let change = changed_level(body);
+        if let Some(sub_highlighter) = self.sub_highlighter.as_mut() {
+            if change == Some(self.inner) {
+                let response =
+                    sub_highlighter.consume_line(sub_line(body, self.inner), thread_pool)?;
+                highlights.extend(self.indented(response.highlighted));
+                if response.line_accepted != LineAcceptance::RejectedDone {
+                    if response.line_accepted == LineAcceptance::AcceptedDone {
+                        self.sub_highlighter = None;
+                    }
+                    return Ok(Response {
+                        line_accepted: LineAcceptance::AcceptedWantMore,
+                        highlighted: highlights,
+                    });
+                }
+            } else {
+                let highlighted = sub_highlighter.consume_eof(thread_pool)?;
+                highlights.extend(self.indented(highlighted));
+            }
+            self.sub_highlighter = None;
+        }
+
+        if let Some(inner) = change {
+            self.sub_highlighter = PlusMinusLinesHighlighter::from_line(
+                sub_line(body, inner),
+                1,
+                self.formatter.clone(),
+            );
+            self.inner = inner;
+        } else {
+            highlights.push(StringFuture::from_string(format!(
+                "{INDENTATION}{}\n",
+                highlight_body_line(body)
+            )));
+        }
+
+        return Ok(Response {
+            line_accepted: LineAcceptance::AcceptedWantMore,
+            highlighted: highlights,
+        });
+    }
+
+    fn consume_eof(&mut self, thread_pool: &ThreadPool) -> Result<Vec<StringFuture>, String> {
+        let mut highlights: Vec<StringFuture> = Vec::new();
+        if let Some(header) = self.header.take() {
+            highlights.push(StringFuture::from_string(header));
+        }
+        if let Some(mut sub_highlighter) = self.sub_highlighter.take() {
+            highlights.extend(self.indented(sub_highlighter.consume_eof(thread_pool)?));
+        }
+        return Ok(highlights);
+    }
+}
+
+impl RangeDiffHighlighter {
+    /// Returns None if this line isn't a `git range-diff` commit pair header
+    pub(crate) fn from_line(line: &str, formatter: Formatter) -> Option<Self> {
+        let header = highlight_pair_header(line, formatter.hyperlinker())?;
+        return Some(RangeDiffHighlighter {
+            header: Some(header + "\n"),
+            formatter,
+            sub_highlighter: None,
+            inner: false,
+        });
+    }
+
+    /// Indent sub highlighter output, putting back the unchanged outer column
+    /// of inner diff lines
+    fn indented(&self, highlights: Vec<StringFuture>) -> Vec<StringFuture> {
+        let prefix = if self.inner {
+            format!("{INDENTATION} ")
+        } else {
+            INDENTATION.to_string()
+        };
+        return highlights
+            .into_iter()
+            .map(|highlight| {
+                let prefix = prefix.clone();
+                highlight.map(move |text| prefix_lines(&text, &prefix))
+            })
+            .collect();
+    }
+}
+
+/// The line without its indentation, or `None` if it isn't indented
+fn body_of(line: &str) -> Option<&str> {
+    if let Some(body) = line.strip_prefix(INDENTATION) {
+        return Some(body);
+    }
+    if line.trim().is_empty() {
+        // Trailing whitespace may have been trimmed
+        return Some("");
+    }
+    return None;
+}
+
+/// `Some(false)` for changed outer lines, `Some(true)` for unchanged outer
+/// lines with changed inner lines, `None` for unchanged lines.
+fn changed_level(body: &str) -> Option<bool> {
+    if body.starts_with(['-', '+']) {
+        return Some(false);
+    }
+    if body.starts_with(' ') && body[1..].starts_with(['-', '+']) {
+        return Some(true);
+    }
+    return None;
+}
+
+/// Outer changes are refined with their outer prefix, inner changes with their
+/// inner prefix only
+fn sub_line(body: &str, inner: bool) -> &str {
+    if inner {
+        return &body[1..];
+    }
+    return body;
+}
+
+/// Style the pair header by whether the commit is unchanged (`=`), changed
+/// (`!`), only in the old range (`<`) or only in the new range (`>`)
+fn highlight_pair_header(line: &str, hyperlinker: &Hyperlinker) -> Option<String> {
+    let captures = PAIR_HEADER.captures(line)?;
+    let old = captures.get(1).unwrap().as_str();
+    let status = captures.get(2).unwrap().as_str();
+    let new = captures.get(3).unwrap().as_str();
+    let subject = captures.get(4).unwrap().as_str();
+
+    let (old_style, status_style, new_style, subject_style) = match status {
+        "!" => (OLD, format!("{BOLD}{YELLOW}"), GREEN, BOLD),
+        "<" => (OLD, format!("{BOLD}{RED}"), FAINT, OLD),
+        ">" => (FAINT, format!("{BOLD}{GREEN}"), GREEN, GREEN),
+        _ => (FAINT, FAINT.to_string(), FAINT, FAINT),
+    };
+
+    return Some(format!(
+        "{old_style}{}{NORMAL} {status_style}{status}{NORMAL} {new_style}{}{NORMAL} {subject_style}{subject}{NORMAL}",
+        link_hash(old, hyperlinker),
+        link_hash(new, hyperlinker),
+    ));
+}
+
+/// Link the hash in `1:  421035d`
+fn link_hash(side: &str, hyperlinker: &Hyperlinker) -> String {
+    let Some((number, hash)) = side.rsplit_once(' ') else {
+        return side.to_string();
+    };
+    if hash.starts_with('-') {
+        // Only in the other range
+        return side.to_string();
+    }
+    let url = hyperlinker
+        .forge_links()
+        .and_then(|links| links.commit_url(hash));
+    return match url {
+        Some(url) => format!("{number} {}", hyperlink(&url, hash)),
+        None => side.to_string(),
+    };
+}
+
+/// Highlight an unindented body line that isn't part of any change
+fn highlight_body_line(body: &str) -> String {
+    if let Some(title) = body.strip_prefix("@@ ") {
+        // Like `@@ main.rs` or `@@ Metadata`
+        return format!("{HUNK_HEADER}@@ {BOLD}{title}{NORMAL}");
+    }
+    if body.starts_with(" @@") {
+        return format!("{HUNK_HEADER}{body}{NORMAL}");
+    }
+
+    // Like `## Commit message ##` or `## main.rs ##`
+    let section = body.trim_start();
+    if section.starts_with("## ") && section.ends_with(" ##") {
+        return format!("{BOLD}{body}{NORMAL}");
+    }
+
+    return body.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_highlight_pair_header() {
+        let hyperlinker = Hyperlinker::default();
+        assert_eq!(
+            highlight_pair_header("1:  421035d ! 1:  aa9252a Greet the world", &hyperlinker),
+            Some(format!(
+                "{OLD}1:  421035d{NORMAL} {BOLD}{YELLOW}!{NORMAL} {GREEN}1:  aa9252a{NORMAL} {BOLD}Greet the world{NORMAL}"
+            ))
+        );
+        assert_eq!(
+            highlight_pair_header("-:  ------- > 3:  68c50eb Add shouting", &hyperlinker),
+            Some(format!(
+                "{FAINT}-:  -------{NORMAL} {BOLD}{GREEN}>{NORMAL} {GREEN}3:  68c50eb{NORMAL} {GREEN}Add shouting{NORMAL}"
+            ))
+        );
+        assert_eq!(
+            highlight_pair_header("10:  1fa8dba = 10:  b4346d1 Add README", &hyperlinker),
+            Some(format!(
+                "{FAINT}10:  1fa8dba{NORMAL} {FAINT}={NORMAL} {FAINT}10:  b4346d1{NORMAL} {FAINT}Add README{NORMAL}"
+            ))
+        );
+        assert_eq!(
+            highlight_pair_header("1: Not a range-diff", &hyperlinker),
+            None
+        );
+    }
+
+    #[test]
+    fn test_changed_level() {
+        assert_eq!(changed_level("-+    old"), Some(false));
+        assert_eq!(changed_level("++    new"), Some(false));
+        assert_eq!(changed_level(" -    old"), Some(true));
+        assert_eq!(changed_level("  context"), None);
+        assert_eq!(changed_level("@@ main.rs"), None);
+        assert_eq!(changed_level(""), None);
+    }
+
+    #[test]
+    fn test_sub_line() {
+        assert_eq!(sub_line("-+    old", false), "-+    old");
+        assert_eq!(sub_line(" -    old", true), "-    old");
+    }
+
+    #[test]
+    fn test_highlight_body_line() {
+        assert_eq!(
+            highlight_body_line("@@ main.rs"),
+            format!("{HUNK_HEADER}@@ {BOLD}main.rs{NORMAL}")
+        );
+        assert_eq!(
+            highlight_body_line("  ## Commit message ##"),
+            format!("{BOLD}  ## Commit message ##{NORMAL}")
+        );
+        assert_eq!(highlight_body_line("  fn main() {"), "  fn main() {");
+    }
+}
@@ -1,19 +1,92 @@
-use similar::capture_diff_slices;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
+use clap::ValueEnum;
+use similar::capture_diff_slices_deadline;
+use similar::DiffOp;
+
+use crate::ansi::Color;
 use crate::ansi::Color::Green;
 use crate::ansi::Color::Red;
 use crate::ansi::Color::Yellow;
 use crate::ansi::Weight::Faint;
 use crate::ansi::ANSI_STYLE_NORMAL;
 use crate::constants::*;
+use crate::histogram_diff;
+use crate::hyperlink::Hyperlinker;
 use crate::line_collector::NO_EOF_NEWLINE_MARKER_HOLDER;
+use crate::normalizer::{normalize_line, normalize_tokens, Normalizer};
 use crate::token_collector::*;
 use crate::tokenizer;
+use crate::tokenizer::split_fields;
+use crate::tokenizer::Profile;
+
+/// How long we let each refinement attempt of a `-` / `+` block run before
+/// falling back to something cheaper.
+///
+/// Ref: https://github.com/walles/riff/issues/35
+pub(crate) const DEFAULT_REFINE_BUDGET: Duration = Duration::from_millis(500);
+
+/// Which algorithm to use when diffing tokens for intra-line refinement
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub(crate) enum RefineAlgorithm {
+    Myers,
+
+    #[default]
+    Patience,
+
+    /// Like Patience, but anchoring on rare tokens rather than unique ones
+    Histogram,
+}
+
+/// Settings for the token level diffing done by `diff()`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct DiffOptions {
+    pub(crate) algorithm: RefineAlgorithm,
+
+    /// Merge short unchanged islands into the changes surrounding them, see
+    /// `semantic_cleanup()`.
+    pub(crate) semantic_cleanup: bool,
+
+    /// How to split the texts into tokens before diffing them
+    pub(crate) tokenizer_profile: Profile,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Formatter {
     line_style_old: LineStyle,
     line_style_new: LineStyle,
+
+    /// Time limit for each refinement attempt, see `format()`.
+    refine_budget: Duration,
+
+    diff_options: DiffOptions,
+
+    /// Show reindented lines as context lines rather than as `-` / `+` pairs
+    collapse_reindented: bool,
+
+    /// Applied to the texts before refining them, see `--ignore-matching`
+    normalizers: Vec<Normalizer>,
+
+    /// Pad CSV and TSV fields so that columns line up, see `align_columns()`
+    align_columns: bool,
+
+    /// Summarize generated files and lock files in one line each, see
+    /// `generated::is_generated()`
+    collapse_generated: bool,
+
+    /// Set when the files are Jupyter notebooks rendered as cells, see
+    /// `notebook::render_notebook()`. Holds the profile for the notebook's
+    /// language, used instead of the one for the file name.
+    notebook_cells: Option<Profile>,
+
+    /// Makes the URLs of file name, hunk header and line hyperlinks
+    hyperlinker: Hyperlinker,
+
+    /// In merge diffs, show which parent each changed token came from, see
+    /// `format_refined()`
+    refine_per_parent: bool,
 }
 
 impl Formatter {
@@ -33,6 +106,15 @@ impl Formatter {
                 midlighted_style: ANSI_STYLE_NORMAL.with_color(Green),
                 highlighted_style: ANSI_STYLE_NORMAL.with_color(Green).with_inverse(true),
             },
+            refine_budget: DEFAULT_REFINE_BUDGET,
+            diff_options: DiffOptions::default(),
+            collapse_reindented: false,
+            normalizers: Vec::new(),
+            align_columns: false,
+            collapse_generated: false,
+            notebook_cells: None,
+            hyperlinker: Hyperlinker::default(),
+            refine_per_parent: false,
         }
     }
 
@@ -50,9 +132,132 @@ impl Formatter {
                 midlighted_style: ANSI_STYLE_NORMAL.with_color(Green),
                 highlighted_style: ANSI_STYLE_NORMAL.with_color(Green).with_inverse(true),
             },
+            refine_budget: DEFAULT_REFINE_BUDGET,
+            diff_options: DiffOptions::default(),
+            collapse_reindented: false,
+            normalizers: Vec::new(),
+            align_columns: false,
+            collapse_generated: false,
+            notebook_cells: None,
+            hyperlinker: Hyperlinker::default(),
+            refine_per_parent: false,
         }
     }
 
+    pub(crate) fn with_refine_budget(&self, refine_budget: Duration) -> Self {
+        return Formatter {
+            refine_budget,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_diff_options(&self, diff_options: DiffOptions) -> Self {
+        return Formatter {
+            diff_options,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_collapse_reindented(&self, collapse_reindented: bool) -> Self {
+        return Formatter {
+            collapse_reindented,
+            ..self.clone()
+        };
+    }
+
+    /// Collapsed lines stand for one removed and one added line each
+    pub(crate) fn collapse_reindented(&self) -> bool {
+        return self.collapse_reindented;
+    }
+
+    pub(crate) fn with_normalizers(&self, normalizers: Vec<Normalizer>) -> Self {
+        return Formatter {
+            normalizers,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_align_columns(&self, align_columns: bool) -> Self {
+        return Formatter {
+            align_columns,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_refine_per_parent(&self, refine_per_parent: bool) -> Self {
+        return Formatter {
+            refine_per_parent,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn with_collapse_generated(&self, collapse_generated: bool) -> Self {
+        return Formatter {
+            collapse_generated,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn collapse_generated(&self) -> bool {
+        return self.collapse_generated;
+    }
+
+    pub(crate) fn with_notebook_cells(&self, notebook_cells: Option<Profile>) -> Self {
+        return Formatter {
+            notebook_cells,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn notebook_cells(&self) -> Option<Profile> {
+        return self.notebook_cells;
+    }
+
+    pub(crate) fn with_hyperlinker(&self, hyperlinker: Hyperlinker) -> Self {
+        return Formatter {
+            hyperlinker,
+            ..self.clone()
+        };
+    }
+
+    pub(crate) fn hyperlinker(&self) -> &Hyperlinker {
+        return &self.hyperlinker;
+    }
+
+    pub(crate) fn with_tokenizer_profile(&self, tokenizer_profile: Profile) -> Self {
+        return Formatter {
+            diff_options: DiffOptions {
+                tokenizer_profile,
+                ..self.diff_options
+            },
+            ..self.clone()
+        };
+    }
+
+    /// Returns two vectors of highlighted tokens, one for the old text and one
+    /// for the new.
+    ///
+    /// `old_text` and `new_text` are multi line strings. Having or not having
+    /// trailing newlines will affect tokenization. The lines are not expected
+    /// to have any prefixes like `+` or `-`.
+    ///
+    /// Conflict diffs are highlighted somewhat differently from regular diffs.
+    pub(crate) fn diff(
+        &self,
+        old_text: &str,
+        new_text: &str,
+    ) -> (Vec<StyledToken>, Vec<StyledToken>) {
+        return diff_with_options(
+            old_text,
+            new_text,
+            self.diff_options,
+            &self.normalizers,
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+    }
+
     /// Format old and new lines in OLD and NEW colors.
     ///
     /// No intra-line refinement.
@@ -105,18 +310,6 @@ impl Formatter {
         return lines;
     }
 
-    /// LCS is O(m * n) complexity. If it gets too complex, refining will take too
-    /// much time and memory, so we shouldn't.
-    ///
-    /// Ref: https://github.com/walles/riff/issues/35
-    fn too_large_to_refine(texts: &[&str]) -> bool {
-        let size = texts.iter().map(|text| text.len()).sum::<usize>();
-
-        // This limit used to be higher, but then refining took too long on
-        // Johan's laptop. This number is based on very limited experimentation.
-        return size > 300_000usize;
-    }
-
     /// Returns a vector of ANSI highlighted lines.
     ///
     /// `prefix_texts` are multi line strings. Having or not having trailing
@@ -124,6 +317,23 @@ impl Formatter {
     /// prefixes like `+` or `-`.
     ///
     /// `prefixes` are the prefixes to use for each `prefix_texts` text.
+    ///
+    /// Refining is tried in three steps, sharing `refine_budget` between them:
+    /// 1. Diff all texts token by token
+    /// 2. Pair up the lines, and diff each line pair token by token
+    /// 3. Don't refine at all, just color the lines
+    ///
+    /// The first step gets half of the budget, so that there is always some
+    /// left for the second step.
+    ///
+    /// If the old and new texts have different line counts, we try step 2
+    /// first. Diffing those as whole texts tends to match tokens between
+    /// unrelated lines. Same thing if some lines were just reindented, since
+    /// step 2 is where we detect those.
+    ///
+    /// Prose gets its own step 1, see `format_prose()`. Blocks where lines
+    /// were just moved around are handled by `format_reordered()` before any
+    /// of the steps.
     #[must_use]
     pub fn format(&self, prefixes: &[&str], prefix_texts: &[&str]) -> Vec<String> {
         if prefixes.len() < 2 {
@@ -135,10 +345,188 @@ impl Formatter {
             return self.format_simple(prefixes, prefix_texts);
         }
 
-        if Formatter::too_large_to_refine(prefix_texts) {
-            return self.format_simple(prefixes, prefix_texts);
+        let aligned_texts: Vec<String>;
+        let aligned_text_refs: Vec<&str>;
+        let mut prefix_texts = prefix_texts;
+        if let Some(separator) = self.diff_options.tokenizer_profile.field_separator() {
+            if self.align_columns {
+                aligned_texts = align_columns(prefix_texts, separator);
+                aligned_text_refs = aligned_texts.iter().map(String::as_str).collect();
+                prefix_texts = &aligned_text_refs;
+            }
+        }
+
+        let start = Instant::now();
+        let first_deadline = start + self.refine_budget / 2;
+        let final_deadline = start + self.refine_budget;
+
+        if self.diff_options.tokenizer_profile == Profile::Prose && prefixes.len() == 2 {
+            if let Some(highlighted) = self.format_prose(prefixes, prefix_texts, first_deadline) {
+                return highlighted;
+            }
+        }
+
+        if let Some(highlighted) = self.format_reordered(prefixes, prefix_texts) {
+            return highlighted;
+        }
+
+        // Line pairing does better with these, so try that first. With just
+        // one line on either side there is nothing to pair up in order.
+        let line_paired_first = prefix_texts.len() == 2
+            && (is_uneven_block(prefix_texts[0], prefix_texts[1])
+                || has_reindented_lines(prefix_texts[0], prefix_texts[1], &self.normalizers));
+        if line_paired_first {
+            if let Some(highlighted) =
+                self.format_line_paired(prefixes, prefix_texts, first_deadline)
+            {
+                return highlighted;
+            }
+        }
+
+        let deadline = if line_paired_first {
+            final_deadline
+        } else {
+            first_deadline
+        };
+        if let Some(highlighted) = self.format_refined(prefixes, prefix_texts, deadline) {
+            return highlighted;
+        }
+
+        if !line_paired_first {
+            if let Some(highlighted) =
+                self.format_line_paired(prefixes, prefix_texts, final_deadline)
+            {
+                return highlighted;
+            }
+        }
+
+        return self.format_simple(prefixes, prefix_texts);
+    }
+
+    /// Diff the old text vs the new text word by word, treating line breaks as
+    /// any other whitespace. This way, rewrapping a paragraph doesn't make the
+    /// whole paragraph look changed.
+    ///
+    /// If only the line breaks changed, a "(reflowed)" annotation line goes
+    /// after the new lines.
+    ///
+    /// Returns `None` if we didn't make it before the deadline, or if only the
+    /// amount of whitespace changed.
+    #[must_use]
+    fn format_prose(
+        &self,
+        prefixes: &[&str],
+        prefix_texts: &[&str],
+        deadline: Instant,
+    ) -> Option<Vec<String>> {
+        let mut old_words = split_prose_words(prefix_texts[0]);
+        let mut new_words = split_prose_words(prefix_texts[1]);
+
+        // Help visualize what actually happens in "No newline at end of file"
+        // diffs
+        if prefix_texts[0].ends_with('\n') && !prefix_texts[1].ends_with('\n') {
+            old_words.insert(old_words.len() - 1, ("⏎", vec!["⏎"]));
+        } else if prefix_texts[1].ends_with('\n') && !prefix_texts[0].ends_with('\n') {
+            new_words.insert(new_words.len() - 1, ("⏎", vec!["⏎"]));
+        }
+
+        let old_keys = old_words.iter().map(|word| word.0).collect::<Vec<_>>();
+        let new_keys = new_words.iter().map(|word| word.0).collect::<Vec<_>>();
+        let diff = capture_diff_slices_deadline(
+            similar::Algorithm::Patience,
+            &old_keys,
+            &new_keys,
+            Some(deadline),
+        );
+        if Instant::now() >= deadline {
+            return None;
+        }
+
+        let mut old_tokens = Vec::new();
+        let mut new_tokens = Vec::new();
+        let mut only_whitespace_changed = true;
+        let mut line_breaks_moved = false;
+        for change in diff.iter() {
+            let old_run = old_words[change.old_range()]
+                .iter()
+                .flat_map(|word| word.1.iter().copied())
+                .collect::<Vec<_>>();
+            let new_run = new_words[change.new_range()]
+                .iter()
+                .flat_map(|word| word.1.iter().copied())
+                .collect::<Vec<_>>();
+
+            if let DiffOp::Equal { .. } = change {
+                line_breaks_moved |= old_words[change.old_range()]
+                    .iter()
+                    .zip(&new_words[change.new_range()])
+                    .any(|(old_word, new_word)| line_breaks(old_word) != line_breaks(new_word));
+                for token in old_run {
+                    old_tokens.push(StyledToken::new(
+                        token.to_string(),
+                        Style::DiffPartUnchanged,
+                    ));
+                }
+                for token in new_run {
+                    new_tokens.push(StyledToken::new(
+                        token.to_string(),
+                        Style::DiffPartUnchanged,
+                    ));
+                }
+                continue;
+            }
+
+            only_whitespace_changed = false;
+            let old_start_of_line = old_tokens
+                .last()
+                .is_none_or(|token: &StyledToken| token.token.ends_with('\n'));
+            let new_start_of_line = new_tokens
+                .last()
+                .is_none_or(|token: &StyledToken| token.token.ends_with('\n'));
+            let style = change_style(&old_run, &new_run, old_start_of_line, new_start_of_line);
+            push_styled_tokens(&mut old_tokens, old_run, style);
+            push_styled_tokens(&mut new_tokens, new_run, style);
+        }
+
+        if only_whitespace_changed && !line_breaks_moved {
+            // Only the amount of whitespace changed, word by word diffing
+            // would hide that
+            return None;
+        }
+
+        bridge_consecutive_highlighted_tokens(&mut old_tokens);
+        bridge_consecutive_highlighted_tokens(&mut new_tokens);
+        errorlight_trailing_whitespace(&mut new_tokens);
+        errorlight_nonleading_tabs(&mut new_tokens);
+
+        let mut lines = self.render_tokens(
+            &prefixes[0..1],
+            &[old_tokens],
+            prefixes[1],
+            &new_tokens,
+            false,
+        );
+        if only_whitespace_changed {
+            lines.push(annotation_line("reflowed"));
         }
 
+        return Some(lines);
+    }
+
+    /// Diff all old texts vs the new text, token by token.
+    ///
+    /// In merge diffs, there is one old text per parent. With
+    /// `refine_per_parent` set, new tokens that are unchanged from some parent are colored like
+    /// that parent's prefix, see `parent_color()`.
+    ///
+    /// Returns `None` if we didn't make it before the deadline.
+    #[must_use]
+    fn format_refined(
+        &self,
+        prefixes: &[&str],
+        prefix_texts: &[&str],
+        deadline: Instant,
+    ) -> Option<Vec<String>> {
         // This is what all old texts will be compared against
         let new_text = prefix_texts.last().unwrap();
         let new_prefix = prefixes.last().unwrap();
@@ -149,10 +537,31 @@ impl Formatter {
 
         let mut old_tokens = vec![];
         let mut new_tokens = vec![];
+        let mut new_styles_per_parent: Vec<Vec<Style>> = vec![];
+        let mut rename_legends = vec![];
         for old_text in old_prefix_texts.iter() {
-            let (old_tokens_internal, new_tokens_internal) = diff(old_text, new_text);
-
+            let (old_tokens_internal, new_tokens_internal) = diff_with_options(
+                old_text,
+                new_text,
+                self.diff_options,
+                &self.normalizers,
+                &HashMap::new(),
+                Some(deadline),
+            )?;
+
+            // Renames are explained per parent, since they can differ between
+            // parents
+            rename_legends.extend(rename_legend_entries(
+                &old_tokens_internal,
+                &new_tokens_internal,
+            ));
             old_tokens.push(old_tokens_internal);
+            new_styles_per_parent.push(
+                new_tokens_internal
+                    .iter()
+                    .map(|token| token.style)
+                    .collect(),
+            );
 
             if new_tokens.is_empty() {
                 // First iteration, just remember the new tokens
@@ -171,103 +580,1220 @@ impl Formatter {
         // We should now have one token vector per old text
         assert_eq!(old_tokens.len(), prefix_texts.len() - 1);
 
-        // Now turn all our token vectors (all vectors in old_tokens plus
-        // new_tokens) into lines of highlighted text
+        let per_parent = self.refine_per_parent && old_prefixes.len() > 1;
+        if per_parent {
+            for (i, new_token) in new_tokens.iter_mut().enumerate() {
+                if new_token.style != Style::DiffPartHighlighted {
+                    continue;
+                }
+
+                // Changed from some parent but not from this one, so this is
+                // where the token came from
+                new_token.color = old_prefixes
+                    .iter()
+                    .zip(new_styles_per_parent.iter())
+                    .find(|(_, styles)| styles[i] != Style::DiffPartHighlighted)
+                    .and_then(|(prefix, _)| parent_color(prefix));
+            }
+        }
+
+        // Not done by diff_with_options(), since an extra token in only some
+        // of the new token vectors would break the style merging above
+        add_rename_legend(rename_legends, &mut new_tokens);
+
+        return Some(self.render_tokens(
+            old_prefixes,
+            &old_tokens,
+            new_prefix,
+            &new_tokens,
+            per_parent,
+        ));
+    }
+
+    /// Diff the old text vs the new text line by line, then refine each pair
+    /// of changed lines token by token. Lines that can't be paired up are
+    /// shown as plain removals or additions.
+    ///
+    /// This is much cheaper than refining the whole texts, since every token
+    /// diff is limited to one line.
+    ///
+    /// Returns `None` for merge diffs, or if we didn't make it before the
+    /// deadline.
+    #[must_use]
+    fn format_line_paired(
+        &self,
+        prefixes: &[&str],
+        prefix_texts: &[&str],
+        deadline: Instant,
+    ) -> Option<Vec<String>> {
+        if prefixes.len() != 2 {
+            return None;
+        }
+
+        let profile = self.diff_options.tokenizer_profile;
+        let old_lines = prefix_texts[0].split_inclusive('\n').collect::<Vec<_>>();
+        let new_lines = prefix_texts[1].split_inclusive('\n').collect::<Vec<_>>();
+
+        // Compare the lines without their indentation, so that reindented
+        // lines end up as unchanged
+        let old_keys = old_lines
+            .iter()
+            .map(|line| line.trim_start())
+            .collect::<Vec<_>>();
+        let new_keys = new_lines
+            .iter()
+            .map(|line| line.trim_start())
+            .collect::<Vec<_>>();
+
+        let mut highlighted_lines = Vec::new();
+        let mut old_tokens = Vec::new();
+        let mut new_tokens = Vec::new();
+        // The first reindented line in each block decides the annotation
+        let mut reindent_marker_text = None;
+        let diff = capture_diff_slices_deadline(
+            similar::Algorithm::Patience,
+            &old_keys,
+            &new_keys,
+            Some(deadline),
+        );
+        let is_change = |change: &&DiffOp| !matches!(change, DiffOp::Equal { .. });
+        let removals = diff
+            .iter()
+            .filter(is_change)
+            .any(|change| !change.old_range().is_empty());
+        let additions = diff
+            .iter()
+            .filter(is_change)
+            .any(|change| !change.new_range().is_empty());
+
+        // Pair up the lines of all changes first, so that renames can be found
+        // across all paired lines, see `find_line_pair_renames()`
+        let mut change_pairs: Vec<Vec<(usize, usize)>> = Vec::with_capacity(diff.len());
+        let mut paired_lines = Vec::new();
+        for change in diff.iter() {
+            let old_block = &old_lines[change.old_range()];
+            let new_block = &new_lines[change.new_range()];
+            let pairs = if let similar::DiffOp::Equal { .. } = change {
+                vec![]
+            } else if old_block.len() == new_block.len() {
+                (0..old_block.len()).map(|i| (i, i)).collect()
+            } else {
+                align_lines(old_block, new_block, profile, &self.normalizers, deadline)?
+            };
+            paired_lines.extend(
+                pairs
+                    .iter()
+                    .map(|(old_index, new_index)| (old_block[*old_index], new_block[*new_index])),
+            );
+            change_pairs.push(pairs);
+        }
+        let renames = find_line_pair_renames(
+            &paired_lines,
+            self.diff_options,
+            &self.normalizers,
+            Some(deadline),
+        )?;
+
+        for (index, (change, pairs)) in diff.iter().zip(change_pairs).enumerate() {
+            let (old_range, new_range) = (change.old_range(), change.new_range());
+            if let similar::DiffOp::Equal { .. } = change {
+                let line_pairs = old_lines[old_range].iter().zip(new_lines[new_range].iter());
+                if reindent_marker_text.is_none() {
+                    reindent_marker_text = line_pairs
+                        .clone()
+                        .find_map(|(old_line, new_line)| reindent_marker(old_line, new_line));
+                }
+
+                // Context lines can go before or after the -/+ lines, but not
+                // between removed and added lines. Reindented lines there stay
+                // -/+ pairs.
+                let at_edge = index == 0 || index == diff.len() - 1;
+                if self.collapse_reindented && (at_edge || !removals || !additions) {
+                    // Render what we have so far, then these lines as context
+                    highlighted_lines.extend(self.render_line_paired(
+                        prefixes,
+                        std::mem::take(&mut old_tokens),
+                        std::mem::take(&mut new_tokens),
+                    ));
+
+                    let mut context_tokens = Vec::new();
+                    for (_, new_line) in line_pairs {
+                        push_line_tokens(
+                            &mut context_tokens,
+                            &[new_line],
+                            Style::Lowlighted,
+                            profile,
+                        );
+                    }
+                    let context_prefix = " ".repeat(prefixes[1].len());
+                    highlighted_lines.extend(to_lines(&render(
+                        &LINE_STYLE_REINDENTED,
+                        &context_prefix,
+                        &context_tokens,
+                    )));
+                    continue;
+                }
+
+                for (old_line, new_line) in line_pairs {
+                    if old_line == new_line {
+                        push_line_tokens(
+                            &mut old_tokens,
+                            &[old_line],
+                            Style::DiffPartUnchanged,
+                            profile,
+                        );
+                        push_line_tokens(
+                            &mut new_tokens,
+                            &[new_line],
+                            Style::DiffPartUnchanged,
+                            profile,
+                        );
+                        continue;
+                    }
+
+                    push_line_tokens(&mut old_tokens, &[old_line], Style::Lowlighted, profile);
+                    push_line_tokens(&mut new_tokens, &[new_line], Style::Lowlighted, profile);
+                }
+                continue;
+            }
+
+            let old_block = &old_lines[old_range];
+            let new_block = &new_lines[new_range];
+
+            // Refine each pair. Lines between the pairs are plain removals or
+            // additions.
+            let mut old_index = 0;
+            let mut new_index = 0;
+            for (old_pair_index, new_pair_index) in pairs {
+                push_line_tokens(
+                    &mut old_tokens,
+                    &old_block[old_index..old_pair_index],
+                    Style::DiffPartMidlighted,
+                    profile,
+                );
+                push_line_tokens(
+                    &mut new_tokens,
+                    &new_block[new_index..new_pair_index],
+                    Style::DiffPartMidlighted,
+                    profile,
+                );
+
+                let (old_line_tokens, new_line_tokens) = diff_with_options(
+                    old_block[old_pair_index],
+                    new_block[new_pair_index],
+                    self.diff_options,
+                    &self.normalizers,
+                    &renames,
+                    Some(deadline),
+                )?;
+                old_tokens.extend(old_line_tokens);
+                new_tokens.extend(new_line_tokens);
+
+                old_index = old_pair_index + 1;
+                new_index = new_pair_index + 1;
+            }
+            push_line_tokens(
+                &mut old_tokens,
+                &old_block[old_index..],
+                Style::DiffPartMidlighted,
+                profile,
+            );
+            push_line_tokens(
+                &mut new_tokens,
+                &new_block[new_index..],
+                Style::DiffPartMidlighted,
+                profile,
+            );
+        }
+
+        if Instant::now() >= deadline {
+            return None;
+        }
+
+        highlighted_lines.extend(self.render_line_paired(prefixes, old_tokens, new_tokens));
+        if let Some(marker) = reindent_marker_text {
+            highlighted_lines.push(annotation_line(&marker));
+        }
+        return Some(highlighted_lines);
+    }
+
+    /// Highlight blocks where lines were moved around, like when sorting
+    /// imports. Moved lines are dimmed, only lines that were actually added or
+    /// removed are highlighted. A "(reordered)" annotation line goes after the
+    /// block.
+    ///
+    /// Returns `None` unless this looks like a reordering, see
+    /// `find_reordered_lines()`.
+    #[must_use]
+    fn format_reordered(&self, prefixes: &[&str], prefix_texts: &[&str]) -> Option<Vec<String>> {
+        if prefixes.len() != 2 {
+            return None;
+        }
+
+        let profile = self.diff_options.tokenizer_profile;
+        let old_lines = prefix_texts[0].split_inclusive('\n').collect::<Vec<_>>();
+        let new_lines = prefix_texts[1].split_inclusive('\n').collect::<Vec<_>>();
+        let (old_moved, new_moved) =
+            find_reordered_lines(&old_lines, &new_lines, &self.normalizers)?;
+
+        let line_style = |moved: bool| {
+            if moved {
+                return Style::Lowlighted;
+            }
+            return Style::DiffPartMidlighted;
+        };
+
+        let mut old_tokens = Vec::new();
+        for (line, moved) in old_lines.iter().zip(old_moved) {
+            push_line_tokens(&mut old_tokens, &[line], line_style(moved), profile);
+        }
 
+        let mut new_tokens = Vec::new();
+        for (line, moved) in new_lines.iter().zip(new_moved) {
+            push_line_tokens(&mut new_tokens, &[line], line_style(moved), profile);
+        }
+
+        let mut highlighted_lines = self.render_line_paired(prefixes, old_tokens, new_tokens);
+        highlighted_lines.push(annotation_line("reordered"));
+        return Some(highlighted_lines);
+    }
+
+    /// Render the output of `format_line_paired()`. Nothing is rendered for
+    /// empty token vectors.
+    #[must_use]
+    fn render_line_paired(
+        &self,
+        prefixes: &[&str],
+        old_tokens: Vec<StyledToken>,
+        mut new_tokens: Vec<StyledToken>,
+    ) -> Vec<String> {
+        add_rename_legend(
+            rename_legend_entries(&old_tokens, &new_tokens),
+            &mut new_tokens,
+        );
+        errorlight_trailing_whitespace(&mut new_tokens);
+        errorlight_nonleading_tabs(&mut new_tokens);
+
+        let mut highlighted_lines = Vec::new();
+        if !old_tokens.is_empty() {
+            highlighted_lines.extend(to_lines(&render(
+                &self.line_style_old,
+                prefixes[0],
+                &old_tokens,
+            )));
+        }
+        if !new_tokens.is_empty() {
+            highlighted_lines.extend(to_lines(&render(
+                &self.line_style_new,
+                prefixes[1],
+                &new_tokens,
+            )));
+        }
+
+        return highlighted_lines;
+    }
+
+    /// Turn all our token vectors (all vectors in old_tokens plus new_tokens)
+    /// into lines of highlighted text.
+    ///
+    /// With `per_parent` set, old prefixes get their parents' colors.
+    #[must_use]
+    fn render_tokens(
+        &self,
+        old_prefixes: &[&str],
+        old_tokens: &[Vec<StyledToken>],
+        new_prefix: &str,
+        new_tokens: &[StyledToken],
+        per_parent: bool,
+    ) -> Vec<String> {
         // First render() into strings, then to_lines() into lines
         let mut highlighted_lines = Vec::new();
         for (prefix, tokens) in old_prefixes.iter().zip(old_tokens.iter()) {
-            let text = render(&self.line_style_old, prefix, tokens);
+            let mut line_style = self.line_style_old.clone();
+            if let Some(color) = parent_color(prefix).filter(|_| per_parent) {
+                line_style.prefix_style = line_style.prefix_style.with_color(color);
+            }
+            let text = render(&line_style, prefix, tokens);
             highlighted_lines.extend(to_lines(&text));
         }
-        let new_text = render(&self.line_style_new, new_prefix, &new_tokens);
+        let new_text = render(&self.line_style_new, new_prefix, new_tokens);
         highlighted_lines.extend(to_lines(&new_text));
 
         return highlighted_lines;
     }
 }
 
-fn should_highlight_change(tokens: &[&str], whitespace_only_is_fine: bool) -> bool {
-    let whitespace_only = tokens
-        .iter()
-        .all(|token| token.chars().all(|c| c.is_whitespace()));
-    let contains_newline = tokens.iter().any(|token| token.contains('\n'));
-    let is_newline_marker = tokens.len() == 2 && tokens[0] == "⏎" && tokens[1] == "\n";
+/// Merge diff prefixes have one column per parent. With `--refine-per-parent`,
+/// each parent gets its own color, picked by the first `-` column of the old
+/// text's prefix.
+fn parent_color(prefix: &str) -> Option<Color> {
+    const PARENT_COLORS: [Color; 3] = [Color::Cyan, Color::Blue, Color::Magenta];
 
-    if is_newline_marker {
-        return true;
-    }
+    let parent = prefix.find('-')?;
+    return Some(PARENT_COLORS[parent % PARENT_COLORS.len()]);
+}
 
-    if (whitespace_only && !whitespace_only_is_fine) || contains_newline {
-        return false;
+/// Pair up old lines with similar new lines, keeping the pairs in order.
+///
+/// Returns `(old_index, new_index)` pairs, chosen to maximize the total
+/// similarity of all pairs. Lines not similar enough to anything are left out.
+///
+/// Returns `None` if the blocks are too large to align, or if we didn't make
+/// it before the deadline.
+fn align_lines(
+    old_lines: &[&str],
+    new_lines: &[&str],
+    profile: Profile,
+    normalizers: &[Normalizer],
+    deadline: Instant,
+) -> Option<Vec<(usize, usize)>> {
+    if old_lines.len() * new_lines.len() > MAX_ALIGNMENT_LINE_PAIRS {
+        // This would compare each old line with each new line, give up
+        return None;
     }
 
-    return true;
-}
-
-fn is_whitepace_replacement(old_run: &[&str], new_run: &[&str]) -> bool {
-    let old_whitespace_only = old_run
+    // Compare the diff keys, so that normalized differences don't count
+    let old_keys = old_lines
         .iter()
-        .all(|token| token.chars().all(|c| c.is_whitespace()));
-    let new_whitespace_only = new_run
+        .map(|line| normalize_tokens(line, tokenizer::tokenize(line, profile), normalizers).1)
+        .collect::<Vec<_>>();
+    let new_keys = new_lines
         .iter()
-        .all(|token| token.chars().all(|c| c.is_whitespace()));
+        .map(|line| normalize_tokens(line, tokenizer::tokenize(line, profile), normalizers).1)
+        .collect::<Vec<_>>();
+    let old_tokenized = old_keys
+        .iter()
+        .map(|keys| keys.iter().map(AsRef::as_ref).collect::<Vec<&str>>())
+        .collect::<Vec<_>>();
+    let new_tokenized = new_keys
+        .iter()
+        .map(|keys| keys.iter().map(AsRef::as_ref).collect::<Vec<&str>>())
+        .collect::<Vec<_>>();
+
+    // scores[i][j] is the best total similarity for aligning the first i old
+    // lines with the first j new lines
+    let mut scores = vec![vec![0.0f32; new_lines.len() + 1]; old_lines.len() + 1];
+    let mut paired = vec![vec![false; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in 1..=old_lines.len() {
+        if Instant::now() >= deadline {
+            return None;
+        }
 
-    return old_whitespace_only && new_whitespace_only;
+        for j in 1..=new_lines.len() {
+            let mut best = f32::max(scores[i - 1][j], scores[i][j - 1]);
+            let similarity = line_similarity(&old_tokenized[i - 1], &new_tokenized[j - 1]);
+            if similarity >= MIN_PAIRING_SIMILARITY && scores[i - 1][j - 1] + similarity > best {
+                best = scores[i - 1][j - 1] + similarity;
+                paired[i][j] = true;
+            }
+            scores[i][j] = best;
+        }
+    }
+
+    // Walk back through the table to find which pairs we picked
+    let mut pairs = Vec::new();
+    let mut i = old_lines.len();
+    let mut j = new_lines.len();
+    while i > 0 && j > 0 {
+        if paired[i][j] {
+            pairs.push((i - 1, j - 1));
+            i -= 1;
+            j -= 1;
+        } else if scores[i - 1][j] >= scores[i][j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+
+    return Some(pairs);
 }
 
-fn push_styled_tokens(destination: &mut Vec<StyledToken>, run: Vec<&str>, style: Style) {
-    // Except for just pushing the tokens, any leading or trailing
-    // whitespace-only tokens in the run should always be midlighted.
+/// Lines less similar than this won't be paired by `align_lines()`
+const MIN_PAIRING_SIMILARITY: f32 = 1.0 / 3.0;
 
-    let first_non_leading_whitespace_index = run
-        .iter()
-        .position(|token| !token.chars().all(|c| c.is_whitespace()));
+/// `align_lines()` compares all old lines with all new lines, this is the max
+/// number of comparisons we're willing to do.
+const MAX_ALIGNMENT_LINE_PAIRS: usize = 10_000;
 
-    let last_non_trailing_whitespace_index = run
-        .iter()
-        .rposition(|token| !token.chars().all(|c| c.is_whitespace()));
+/// How similar are these two lines? 0.0 means nothing in common, 1.0 means
+/// the lines are identical.
+///
+/// Only words are counted, since whitespace and punctuation are shared by
+/// lots of unrelated lines. Each changed run of words counts as one, so that
+/// a line with only one long changed part, like a hash, is still considered
+/// similar.
+fn line_similarity(old_tokens: &[&str], new_tokens: &[&str]) -> f32 {
+    fn word_count(tokens: &[&str]) -> usize {
+        return tokens
+            .iter()
+            .filter(|token| token.chars().any(char::is_alphanumeric))
+            .count();
+    }
 
-    for (index, token) in run.iter().enumerate() {
-        let in_leading_whitespace = first_non_leading_whitespace_index.is_some()
-            && index < first_non_leading_whitespace_index.unwrap();
-        let in_trailing_whitespace = last_non_trailing_whitespace_index.is_some()
-            && index > last_non_trailing_whitespace_index.unwrap();
-        let style = if in_leading_whitespace || in_trailing_whitespace {
-            Style::DiffPartMidlighted
-        } else {
-            style
-        };
+    let diff = similar::capture_diff_slices(similar::Algorithm::Patience, old_tokens, new_tokens);
+    let mut unchanged_words = 0;
+    let mut changed_runs = 0;
+    for change in diff.iter() {
+        if let DiffOp::Equal { old_index, len, .. } = change {
+            unchanged_words += word_count(&old_tokens[*old_index..*old_index + *len]);
+            continue;
+        }
+        if word_count(&old_tokens[change.old_range()]) > 0 {
+            changed_runs += 1;
+        }
+        if word_count(&new_tokens[change.new_range()]) > 0 {
+            changed_runs += 1;
+        }
+    }
 
-        destination.push(StyledToken::new(token.to_string(), style));
+    let total = 2 * unchanged_words + changed_runs;
+    if total == 0 {
+        return 1.0;
     }
+    return (2 * unchanged_words) as f32 / total as f32;
 }
 
-/// Returns two vectors of highlighted tokens, one for the old text and one for
-/// the new.
-///
-/// `old_text` and `new_text` are multi line strings. Having or not having
-/// trailing newlines will affect tokenization. The lines are not expected to
-/// have any prefixes like `+` or `-`.
+/// How many line breaks there are in one of the words from
+/// `split_prose_words()`
+fn line_breaks(word: &(&str, Vec<&str>)) -> usize {
+    return word.1.iter().filter(|token| token.contains('\n')).count();
+}
+
+/// A faint line of its own, for saying something about the lines before it
+/// without changing them. Rendered like the hunk header annotations.
+pub(crate) fn annotation_line(text: &str) -> String {
+    return format!("{FAINT}({text}){NORMAL}");
+}
+
+/// Was this line made by `annotation_line()`? Diff lines always start with
+/// their prefix, so they never look like this.
+pub(crate) fn is_annotation_line(line: &str) -> bool {
+    return line.starts_with(FAINT) && line[FAINT.len()..].starts_with('(');
+}
+
+/// Split prose into words for `format_prose()`.
 ///
-/// Conflict diffs are highlighted somewhat differently from regular diffs.
-pub fn diff(old_text: &str, new_text: &str) -> (Vec<StyledToken>, Vec<StyledToken>) {
-    // Find diffs between adds and removals
-    let mut old_tokens = Vec::new();
-    let mut new_tokens = Vec::new();
+/// Each word is a comparison key plus the tokens making up that word. Runs of
+/// whitespace, including line breaks, all get the same `" "` key, except for
+/// any trailing whitespace at the end of the text.
+fn split_prose_words(text: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut words: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in tokenizer::tokenize(text, Profile::Prose) {
+        let is_whitespace = token.chars().all(char::is_whitespace);
+        if is_whitespace {
+            if let Some((" ", tokens)) = words.last_mut() {
+                tokens.push(token);
+                continue;
+            }
+            words.push((" ", vec![token]));
+            continue;
+        }
+
+        words.push((token, vec![token]));
+    }
+
+    if let Some((key, _)) = words.last_mut() {
+        if *key == " " {
+            // Line ends should preferably be matched with other line ends
+            *key = "\n";
+        }
+    }
+
+    return words;
+}
+
+/// Pad all fields except the last one on each line, so that the columns of
+/// all lines in all texts line up.
+///
+/// Since all texts get the same column widths, unchanged fields are still
+/// equal after padding.
+fn align_columns(texts: &[&str], separator: char) -> Vec<String> {
+    let mut widths: Vec<usize> = Vec::new();
+    for line in texts.iter().flat_map(|text| text.lines()) {
+        let fields = split_fields(line, separator);
+        let padded_count = fields.len() - 1;
+        for (column, field) in fields[..padded_count].iter().enumerate() {
+            let width = field.chars().count();
+            if column < widths.len() {
+                widths[column] = widths[column].max(width);
+            } else {
+                widths.push(width);
+            }
+        }
+    }
+
+    return texts
+        .iter()
+        .map(|text| {
+            let mut aligned = String::with_capacity(text.len());
+            for line in text.split_inclusive('\n') {
+                let line_break = &line[line.trim_end_matches(['\n', '\r']).len()..];
+                let fields = split_fields(line, separator);
+                let last = fields.len() - 1;
+                for (column, field) in fields.iter().enumerate() {
+                    aligned.push_str(field);
+                    if column == last {
+                        break;
+                    }
+                    let padding = widths[column] - field.chars().count();
+                    aligned.push_str(&" ".repeat(padding));
+                    aligned.push(separator);
+                }
+                aligned.push_str(line_break);
+            }
+            return aligned;
+        })
+        .collect();
+}
+
+/// Do the texts have different line counts, with more than one line on each
+/// side?
+fn is_uneven_block(old_text: &str, new_text: &str) -> bool {
+    let old_count = old_text.lines().count();
+    let new_count = new_text.lines().count();
+    return old_count != new_count && old_count > 1 && new_count > 1;
+}
+
+/// Does any new line look like an old line with different indentation?
+///
+/// Lines without any alphanumerics, like `},`, are too common to say anything
+/// and are ignored. Lines are compared after normalization.
+fn has_reindented_lines(old_text: &str, new_text: &str, normalizers: &[Normalizer]) -> bool {
+    let is_significant = |line: &&str| line.chars().any(char::is_alphanumeric);
+    let old_lines = old_text
+        .lines()
+        .filter(is_significant)
+        .map(|line| normalize_line(line, normalizers))
+        .collect::<HashSet<_>>();
+    let old_trimmed = old_lines
+        .iter()
+        .map(|line| line.trim_start())
+        .collect::<HashSet<_>>();
+
+    return new_text
+        .lines()
+        .filter(is_significant)
+        .map(|line| normalize_line(line, normalizers))
+        .any(|line| !old_lines.contains(&line) && old_trimmed.contains(line.trim_start()));
+}
+
+/// Figure out whether the new lines are mostly the old lines in a different
+/// order.
+///
+/// Returns one flag per old line and one per new line, telling whether that
+/// line was moved rather than added or removed. Returns `None` if the lines
+/// were not reordered, or if too few lines were moved for this to be a
+/// reordering.
+fn find_reordered_lines(
+    old_lines: &[&str],
+    new_lines: &[&str],
+    normalizers: &[Normalizer],
+) -> Option<(Vec<bool>, Vec<bool>)> {
+    // Compare without the newlines, so that a missing newline at end of file
+    // doesn't make the last line look changed
+    let old_keys = old_lines
+        .iter()
+        .map(|line| normalize_line(line, normalizers))
+        .collect::<Vec<_>>();
+    let new_keys = new_lines
+        .iter()
+        .map(|line| normalize_line(line, normalizers))
+        .collect::<Vec<_>>();
+
+    let mut old_counts: HashMap<&str, usize> = HashMap::new();
+    for key in &old_keys {
+        *old_counts.entry(key).or_default() += 1;
+    }
+
+    let mut new_moved = Vec::with_capacity(new_lines.len());
+    let mut moved_counts: HashMap<&str, usize> = HashMap::new();
+    for key in &new_keys {
+        let count = old_counts.entry(key).or_default();
+        let moved = *count > 0;
+        if moved {
+            *count -= 1;
+            *moved_counts.entry(key).or_default() += 1;
+        }
+        new_moved.push(moved);
+    }
+
+    let mut old_moved = Vec::with_capacity(old_lines.len());
+    for key in &old_keys {
+        let count = moved_counts.entry(key).or_default();
+        let moved = *count > 0;
+        if moved {
+            *count -= 1;
+        }
+        old_moved.push(moved);
+    }
+
+    // Blank lines and lines like `}` move around all the time without anybody
+    // reordering anything, so only count lines with some actual content
+    let is_significant = |line: &&str| line.chars().any(char::is_alphanumeric);
+    let significant_moved_count = new_lines
+        .iter()
+        .zip(new_moved.iter())
+        .filter(|(line, moved)| **moved && is_significant(line))
+        .count();
+    if significant_moved_count < 2 {
+        return None;
+    }
+
+    // Most lines on both sides must have moved
+    let moved_count = new_moved.iter().filter(|moved| **moved).count();
+    if moved_count * 2 < old_lines.len() || moved_count * 2 < new_lines.len() {
+        return None;
+    }
+
+    // If the moved lines are in the same order on both sides, nothing was
+    // reordered
+    let old_order = old_keys
+        .iter()
+        .zip(old_moved.iter())
+        .filter(|(_, moved)| **moved)
+        .map(|(key, _)| key);
+    let new_order = new_keys
+        .iter()
+        .zip(new_moved.iter())
+        .filter(|(_, moved)| **moved)
+        .map(|(key, _)| key);
+    if old_order.eq(new_order) {
+        return None;
+    }
+
+    return Some((old_moved, new_moved));
+}
+
+/// How much `old_line` was reindented into `new_line`, for the annotation
+/// line after a block with reindented lines. `None` if the line wasn't
+/// reindented.
+fn reindent_marker(old_line: &str, new_line: &str) -> Option<String> {
+    if old_line == new_line {
+        return None;
+    }
+
+    let old_indent = &old_line[..old_line.len() - old_line.trim_start().len()];
+    let new_indent = &new_line[..new_line.len() - new_line.trim_start().len()];
+    let same_kind = old_indent
+        .chars()
+        .chain(new_indent.chars())
+        .collect::<HashSet<_>>()
+        .len()
+        <= 1;
+    if !same_kind {
+        return Some("reindented".to_string());
+    }
 
+    let delta = new_indent.chars().count() as isize - old_indent.chars().count() as isize;
+    return Some(format!("reindented {delta:+}"));
+}
+
+/// Tokenize the lines and push them all with the same style
+fn push_line_tokens(
+    destination: &mut Vec<StyledToken>,
+    lines: &[&str],
+    style: Style,
+    profile: Profile,
+) {
+    for line in lines {
+        for token in tokenizer::tokenize(line, profile) {
+            destination.push(StyledToken::new(token.to_string(), style));
+        }
+    }
+}
+
+fn should_highlight_change(tokens: &[&str], whitespace_only_is_fine: bool) -> bool {
+    let whitespace_only = tokens
+        .iter()
+        .all(|token| token.chars().all(|c| c.is_whitespace()));
+    let contains_newline = tokens.iter().any(|token| token.contains('\n'));
+    let is_newline_marker = tokens.len() == 2 && tokens[0] == "⏎" && tokens[1] == "\n";
+
+    if is_newline_marker {
+        return true;
+    }
+
+    if (whitespace_only && !whitespace_only_is_fine) || contains_newline {
+        return false;
+    }
+
+    return true;
+}
+
+fn is_whitepace_replacement(old_run: &[&str], new_run: &[&str]) -> bool {
+    let old_whitespace_only = old_run
+        .iter()
+        .all(|token| token.chars().all(|c| c.is_whitespace()));
+    let new_whitespace_only = new_run
+        .iter()
+        .all(|token| token.chars().all(|c| c.is_whitespace()));
+
+    return old_whitespace_only && new_whitespace_only;
+}
+
+/// How should a changed run of tokens be styled?
+///
+/// One of the runs will be empty for pure additions or removals.
+fn change_style(
+    old_run: &[&str],
+    new_run: &[&str],
+    old_start_of_line: bool,
+    new_start_of_line: bool,
+) -> Style {
+    let highlight = if old_run.is_empty() {
+        should_highlight_change(new_run, !new_start_of_line)
+    } else if new_run.is_empty() {
+        should_highlight_change(old_run, !old_start_of_line)
+    } else {
+        should_highlight_change(old_run, false)
+            && should_highlight_change(new_run, false)
+            && !is_whitepace_replacement(old_run, new_run)
+    };
+
+    if highlight {
+        return Style::DiffPartHighlighted;
+    }
+    return Style::DiffPartMidlighted;
+}
+
+fn push_styled_tokens(destination: &mut Vec<StyledToken>, run: Vec<&str>, style: Style) {
+    // Except for just pushing the tokens, any leading or trailing
+    // whitespace-only tokens in the run should always be midlighted.
+
+    let first_non_leading_whitespace_index = run
+        .iter()
+        .position(|token| !token.chars().all(|c| c.is_whitespace()));
+
+    let last_non_trailing_whitespace_index = run
+        .iter()
+        .rposition(|token| !token.chars().all(|c| c.is_whitespace()));
+
+    for (index, token) in run.iter().enumerate() {
+        let in_leading_whitespace = first_non_leading_whitespace_index.is_some()
+            && index < first_non_leading_whitespace_index.unwrap();
+        let in_trailing_whitespace = last_non_trailing_whitespace_index.is_some()
+            && index > last_non_trailing_whitespace_index.unwrap();
+        let style = if in_leading_whitespace || in_trailing_whitespace {
+            Style::DiffPartMidlighted
+        } else {
+            style
+        };
+
+        destination.push(StyledToken::new(token.to_string(), style));
+    }
+}
+
+/// Like `diff()`, but with configurable diffing, and giving up and returning
+/// `None` if the deadline passes before we're done.
+///
+/// Parts of the texts matching any of the `normalizers` are compared after
+/// normalization, see `normalizer::normalize_tokens()`.
+fn diff_with_options(
+    old_text: &str,
+    new_text: &str,
+    options: DiffOptions,
+    normalizers: &[Normalizer],
+    known_renames: &HashMap<String, String>,
+    deadline: Option<Instant>,
+) -> Option<(Vec<StyledToken>, Vec<StyledToken>)> {
     // Tokenize adds and removes before diffing them
-    let mut tokenized_old = tokenizer::tokenize(old_text);
-    let mut tokenized_new = tokenizer::tokenize(new_text);
+    let (mut tokenized_old, mut old_keys) = normalize_tokens(
+        old_text,
+        tokenizer::tokenize(old_text, options.tokenizer_profile),
+        normalizers,
+    );
+    let (mut tokenized_new, mut new_keys) = normalize_tokens(
+        new_text,
+        tokenizer::tokenize(new_text, options.tokenizer_profile),
+        normalizers,
+    );
 
     // Help visualize what actually happens in "No newline at end of file" diffs
     if old_text.ends_with('\n') && !new_text.ends_with('\n') {
         tokenized_old.insert(tokenized_old.len() - 1, "⏎");
+        old_keys.insert(old_keys.len() - 1, "⏎".into());
     } else if new_text.ends_with('\n') && !old_text.ends_with('\n') {
         tokenized_new.insert(tokenized_new.len() - 1, "⏎");
+        new_keys.insert(new_keys.len() - 1, "⏎".into());
+    }
+
+    // Find diffs between adds and removals
+    let old_keys = old_keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+    let new_keys = new_keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+    let (mut old_tokens, mut new_tokens) = styled_diff(
+        &tokenized_old,
+        &tokenized_new,
+        &old_keys,
+        &new_keys,
+        options,
+        known_renames,
+        deadline,
+    )?;
+
+    // In CSV and TSV files, separators are structure rather than content.
+    // Bridging them would highlight two changed cells as one, and TSV tabs
+    // are never misplaced.
+    let is_delimited = options.tokenizer_profile.field_separator().is_some();
+
+    // Refine old tokens highlighting
+    if !is_delimited {
+        bridge_consecutive_highlighted_tokens(&mut old_tokens);
+    }
+    dim_normalized_lines(&mut old_tokens);
+
+    // Refine new tokens highlighting
+    if !is_delimited {
+        bridge_consecutive_highlighted_tokens(&mut new_tokens);
+    }
+    dim_normalized_lines(&mut new_tokens);
+    errorlight_trailing_whitespace(&mut new_tokens);
+    if !is_delimited {
+        errorlight_nonleading_tabs(&mut new_tokens);
+    }
+
+    return Some((old_tokens, new_tokens));
+}
+
+/// Dim lines where all differences were normalized away, see
+/// `normalizer::normalize_tokens()`. Those differences are the only reason for
+/// `Style::Lowlighted` tokens in `styled_diff()` output.
+fn dim_normalized_lines(tokens: &mut [StyledToken]) {
+    for line in tokens.split_inclusive_mut(|token| token.token == "\n") {
+        let is_normalized = line.iter().any(|token| token.style == Style::Lowlighted);
+        let is_changed = line.iter().any(|token| {
+            token.style != Style::Lowlighted && token.style != Style::DiffPartUnchanged
+        });
+        if !is_normalized || is_changed {
+            continue;
+        }
+
+        for token in line {
+            token.style = Style::Lowlighted;
+        }
+    }
+}
+
+/// Is this an identifier, like `foo_bar` or `FooBar`?
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    return (first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+}
+
+/// `userId` -> `UserId`
+fn is_case_change(old_token: &str, new_token: &str) -> bool {
+    return old_token != new_token
+        && is_identifier(old_token)
+        && old_token.to_lowercase() == new_token.to_lowercase();
+}
+
+/// Find identifiers that were consistently renamed, so that every occurrence
+/// of `foo` in the old tokens became `bar` in the new tokens.
+///
+/// To count, a rename must happen on at least two lines. Once is just a
+/// change, and the same name repeated on one line is usually something like
+/// an URL. Also, the lines with the renames can't have any other changes, or
+/// the renames would just break up those changes.
+///
+/// Returns a map from old to new identifiers.
+fn find_renames<'a>(
+    diff: &[DiffOp],
+    tokenized_old: &[&'a str],
+    tokenized_new: &[&'a str],
+) -> HashMap<&'a str, &'a str> {
+    // Zero based line number of each old token
+    let mut old_line_numbers = Vec::with_capacity(tokenized_old.len());
+    let mut line_number = 0;
+    for token in tokenized_old {
+        old_line_numbers.push(line_number);
+        if *token == "\n" {
+            line_number += 1;
+        }
+    }
+
+    // Old identifier -> (new identifier, count, line numbers), or None if the
+    // identifier was replaced by different things in different places
+    #[allow(clippy::type_complexity)]
+    let mut candidates: HashMap<&str, Option<(&str, usize, HashSet<usize>)>> = HashMap::new();
+    let mut changed_line_numbers = HashSet::new();
+    for change in diff {
+        let (old_index, new_index) = match *change {
+            similar::DiffOp::Equal { .. } => continue,
+            similar::DiffOp::Replace {
+                old_index,
+                old_len: 1,
+                new_index,
+                new_len: 1,
+            } if is_identifier(tokenized_old[old_index])
+                && is_identifier(tokenized_new[new_index]) =>
+            {
+                (old_index, new_index)
+            }
+            _ => {
+                let old_range = change.old_range();
+                let first = old_range.start.min(tokenized_old.len().saturating_sub(1));
+                let last = old_range
+                    .end
+                    .max(old_range.start + 1)
+                    .min(tokenized_old.len());
+                changed_line_numbers.extend(&old_line_numbers[first..last]);
+                continue;
+            }
+        };
+        let (old_token, new_token) = (tokenized_old[old_index], tokenized_new[new_index]);
+        if is_case_change(old_token, new_token) {
+            continue;
+        }
+
+        let candidate = candidates
+            .entry(old_token)
+            .or_insert(Some((new_token, 0, HashSet::new())));
+        match candidate {
+            Some((renamed_to, count, line_numbers)) if *renamed_to == new_token => {
+                *count += 1;
+                line_numbers.insert(old_line_numbers[old_index]);
+            }
+            _ => *candidate = None,
+        }
+    }
+
+    let mut renames = HashMap::new();
+    for (old_token, candidate) in candidates {
+        let Some((new_token, count, line_numbers)) = candidate else {
+            continue;
+        };
+        if line_numbers.len() < 2 || !line_numbers.is_disjoint(&changed_line_numbers) {
+            continue;
+        }
+
+        // If some occurrences were kept, this wasn't a rename
+        let old_count = tokenized_old
+            .iter()
+            .filter(|token| **token == old_token)
+            .count();
+        if old_count != count {
+            continue;
+        }
+
+        renames.insert(old_token, new_token);
+    }
+
+    return renames;
+}
+
+/// Renames can't be found in single lines, since `find_renames()` wants them on
+/// at least two lines. So when diffing line by line, find them in all paired
+/// lines together.
+///
+/// Returns `None` if the deadline passes before we're done.
+fn find_line_pair_renames(
+    line_pairs: &[(&str, &str)],
+    options: DiffOptions,
+    normalizers: &[Normalizer],
+    deadline: Option<Instant>,
+) -> Option<HashMap<String, String>> {
+    let mut old_keys = Vec::new();
+    let mut new_keys = Vec::new();
+    let mut diff = Vec::new();
+    for (old_line, new_line) in line_pairs {
+        let (_, old_line_keys) = normalize_tokens(
+            old_line,
+            tokenizer::tokenize(old_line, options.tokenizer_profile),
+            normalizers,
+        );
+        let (_, new_line_keys) = normalize_tokens(
+            new_line,
+            tokenizer::tokenize(new_line, options.tokenizer_profile),
+            normalizers,
+        );
+        let old_line_keys = old_line_keys.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let new_line_keys = new_line_keys.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+
+        let (old_offset, new_offset) = (old_keys.len(), new_keys.len());
+        for change in diff_keys(&old_line_keys, &new_line_keys, options, deadline)? {
+            diff.push(offset_diff_op(change, old_offset, new_offset));
+        }
+        old_keys.extend(old_line_keys.into_iter().map(str::to_string));
+        new_keys.extend(new_line_keys.into_iter().map(str::to_string));
+    }
+
+    let old_keys = old_keys.iter().map(String::as_str).collect::<Vec<_>>();
+    let new_keys = new_keys.iter().map(String::as_str).collect::<Vec<_>>();
+    return Some(
+        find_renames(&diff, &old_keys, &new_keys)
+            .into_iter()
+            .map(|(old_key, new_key)| (old_key.to_string(), new_key.to_string()))
+            .collect(),
+    );
+}
+
+/// `change` with its indices moved forward by the given offsets
+fn offset_diff_op(change: DiffOp, old_offset: usize, new_offset: usize) -> DiffOp {
+    return match change {
+        DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } => DiffOp::Equal {
+            old_index: old_index + old_offset,
+            new_index: new_index + new_offset,
+            len,
+        },
+        DiffOp::Delete {
+            old_index,
+            old_len,
+            new_index,
+        } => DiffOp::Delete {
+            old_index: old_index + old_offset,
+            old_len,
+            new_index: new_index + new_offset,
+        },
+        DiffOp::Insert {
+            old_index,
+            new_index,
+            new_len,
+        } => DiffOp::Insert {
+            old_index: old_index + old_offset,
+            new_index: new_index + new_offset,
+            new_len,
+        },
+        DiffOp::Replace {
+            old_index,
+            old_len,
+            new_index,
+            new_len,
+        } => DiffOp::Replace {
+            old_index: old_index + old_offset,
+            old_len,
+            new_index: new_index + new_offset,
+            new_len,
+        },
+    };
+}
+
+/// What each renamed new token was renamed from, like `foo → bar`, by new
+/// token index. `old_tokens` and `new_tokens` must come from the same diff.
+fn rename_legend_entries(
+    old_tokens: &[StyledToken],
+    new_tokens: &[StyledToken],
+) -> Vec<(usize, String)> {
+    let old_renamed = old_tokens
+        .iter()
+        .filter(|token| token.style == Style::DiffPartRenamed);
+    let new_renamed = new_tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.style == Style::DiffPartRenamed);
+
+    return old_renamed
+        .zip(new_renamed)
+        .map(|(old_token, (index, new_token))| {
+            (index, format!("{} → {}", old_token.token, new_token.token))
+        })
+        .collect();
+}
+
+/// Explain the renamed tokens at the end of the first line with a renamed
+/// token in it, like `(renamed foo → bar)`. This way, the renamed tokens can be
+/// highlighted lightly rather than being inverse-video spots all over.
+///
+/// `entries` come from `rename_legend_entries()`. In merge diffs there is one
+/// set of entries per parent, and only the ones for tokens that are still
+/// renamed after merging the parents' styles are explained.
+fn add_rename_legend(mut entries: Vec<(usize, String)>, new_tokens: &mut Vec<StyledToken>) {
+    entries.retain(|(index, _)| new_tokens[*index].style == Style::DiffPartRenamed);
+    entries.sort_by_key(|(index, _)| *index);
+    let Some(first_renamed) = entries.first().map(|(index, _)| *index) else {
+        return;
+    };
+
+    let mut renames: Vec<String> = Vec::new();
+    for (_, rename) in entries {
+        if !renames.contains(&rename) {
+            renames.push(rename);
+        }
+    }
+
+    let legend_index = new_tokens[first_renamed..]
+        .iter()
+        .position(|token| token.token == "\n")
+        .map_or(new_tokens.len(), |index| first_renamed + index);
+    new_tokens.insert(
+        legend_index,
+        StyledToken::new(
+            format!(" (renamed {})", renames.join(", ")),
+            Style::Lowlighted,
+        ),
+    );
+}
+
+/// Diff the token keys with the configured algorithm.
+///
+/// Returns `None` if the deadline passes before we're done.
+fn diff_keys(
+    old_keys: &[&str],
+    new_keys: &[&str],
+    options: DiffOptions,
+    deadline: Option<Instant>,
+) -> Option<Vec<DiffOp>> {
+    let diff = match options.algorithm {
+        RefineAlgorithm::Myers => {
+            capture_diff_slices_deadline(similar::Algorithm::Myers, old_keys, new_keys, deadline)
+        }
+        RefineAlgorithm::Patience => {
+            capture_diff_slices_deadline(similar::Algorithm::Patience, old_keys, new_keys, deadline)
+        }
+        RefineAlgorithm::Histogram => {
+            histogram_diff::capture_diff_slices_deadline(old_keys, new_keys, deadline)
+        }
+    };
+    let diff = if options.semantic_cleanup {
+        semantic_cleanup(diff, old_keys, new_keys)
+    } else {
+        diff
+    };
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        // Similar falls back to an approximation when running out of time,
+        // but we'd rather try something else in that case.
+        return None;
     }
 
-    let diff = capture_diff_slices(similar::Algorithm::Patience, &tokenized_old, &tokenized_new);
+    return Some(diff);
+}
+
+/// Diff two token sequences and style the tokens accordingly.
+///
+/// The keys are what's actually diffed, one per token. Tokens with different
+/// text but equal keys are considered equal, but are dimmed to show that they
+/// differ. Without any normalization, the keys are the same as the tokens.
+///
+/// Returns `None` if the deadline passes before we're done.
+fn styled_diff(
+    tokenized_old: &[&str],
+    tokenized_new: &[&str],
+    old_keys: &[&str],
+    new_keys: &[&str],
+    options: DiffOptions,
+    known_renames: &HashMap<String, String>,
+    deadline: Option<Instant>,
+) -> Option<(Vec<StyledToken>, Vec<StyledToken>)> {
+    let mut old_tokens = Vec::new();
+    let mut new_tokens = Vec::new();
+
+    let diff = diff_keys(old_keys, new_keys, options, deadline)?;
+    let renames = find_renames(&diff, old_keys, new_keys);
+    let is_rename = |old_key: &str, new_key: &str| {
+        return renames.get(old_key) == Some(&new_key)
+            || known_renames
+                .get(old_key)
+                .is_some_and(|known| known == new_key);
+    };
+
     let mut old_start_of_line = true;
     let mut new_start_of_line = true;
     for change in diff.iter() {
@@ -277,66 +1803,94 @@ pub fn diff(old_text: &str, new_text: &str) -> (Vec<StyledToken>, Vec<StyledToke
                 new_index,
                 len,
             } => {
-                for token in tokenized_old.iter().skip(*old_index).take(*len) {
-                    old_tokens.push(StyledToken::new(
-                        token.to_string(),
-                        Style::DiffPartUnchanged,
-                    ));
-                }
-                for token in tokenized_new.iter().skip(*new_index).take(*len) {
-                    new_tokens.push(StyledToken::new(
-                        token.to_string(),
-                        Style::DiffPartUnchanged,
-                    ));
+                let old_run = &tokenized_old[*old_index..*old_index + *len];
+                let new_run = &tokenized_new[*new_index..*new_index + *len];
+                for (old_token, new_token) in old_run.iter().zip(new_run) {
+                    // Normalized tokens can be equal without being identical
+                    let style = if old_token == new_token {
+                        Style::DiffPartUnchanged
+                    } else {
+                        Style::Lowlighted
+                    };
+                    old_tokens.push(StyledToken::new(old_token.to_string(), style));
+                    new_tokens.push(StyledToken::new(new_token.to_string(), style));
                 }
             }
 
-            similar::DiffOp::Insert {
-                old_index: _,
+            similar::DiffOp::Replace {
+                old_index,
+                old_len: 1,
                 new_index,
-                new_len,
-            } => {
-                let run = tokenized_new[*new_index..*new_index + *new_len].to_vec();
-                let style = if should_highlight_change(&run, !new_start_of_line) {
-                    Style::DiffPartHighlighted
-                } else {
-                    Style::DiffPartMidlighted
-                };
-                push_styled_tokens(&mut new_tokens, run, style);
+                new_len: 1,
+            } if old_keys[*old_index] == tokenized_old[*old_index]
+                && new_keys[*new_index] == tokenized_new[*new_index]
+                && tokenizer::is_string_literal(
+                    tokenized_old[*old_index],
+                    options.tokenizer_profile,
+                )
+                && tokenizer::is_string_literal(
+                    tokenized_new[*new_index],
+                    options.tokenizer_profile,
+                ) =>
+            {
+                // String literals are diffed as single tokens to not match
+                // parts of unrelated strings. But if one string replaces
+                // another, show what changed inside of it.
+                let old_string_tokens =
+                    tokenizer::tokenize(tokenized_old[*old_index], Profile::Generic);
+                let new_string_tokens =
+                    tokenizer::tokenize(tokenized_new[*new_index], Profile::Generic);
+                let (old_string_tokens, new_string_tokens) = styled_diff(
+                    &old_string_tokens,
+                    &new_string_tokens,
+                    &old_string_tokens,
+                    &new_string_tokens,
+                    options,
+                    &HashMap::new(),
+                    deadline,
+                )?;
+                old_tokens.extend(old_string_tokens);
+                new_tokens.extend(new_string_tokens);
             }
 
-            similar::DiffOp::Delete {
+            similar::DiffOp::Replace {
                 old_index,
-                old_len,
-                new_index: _,
-            } => {
-                let run = tokenized_old[*old_index..*old_index + *old_len].to_vec();
-                let style = if should_highlight_change(&run, !old_start_of_line) {
-                    Style::DiffPartHighlighted
-                } else {
-                    Style::DiffPartMidlighted
-                };
-                push_styled_tokens(&mut old_tokens, run, style);
+                old_len: 1,
+                new_index,
+                new_len: 1,
+            } if is_case_change(old_keys[*old_index], new_keys[*new_index]) => {
+                let (old_token, new_token) = (tokenized_old[*old_index], tokenized_new[*new_index]);
+                old_tokens.push(StyledToken::new(
+                    old_token.to_string(),
+                    Style::DiffPartCaseChanged,
+                ));
+                new_tokens.push(StyledToken::new(
+                    new_token.to_string(),
+                    Style::DiffPartCaseChanged,
+                ));
             }
 
             similar::DiffOp::Replace {
                 old_index,
-                old_len,
+                old_len: 1,
                 new_index,
-                new_len,
-            } => {
-                let old_run = tokenized_old[*old_index..*old_index + *old_len].to_vec();
-                let new_run = tokenized_new[*new_index..*new_index + *new_len].to_vec();
-
-                let style = if should_highlight_change(&old_run, false)
-                    && should_highlight_change(&new_run, false)
-                    && !is_whitepace_replacement(&old_run, &new_run)
-                {
-                    Style::DiffPartHighlighted
-                } else {
-                    Style::DiffPartMidlighted
-                };
+                new_len: 1,
+            } if is_rename(old_keys[*old_index], new_keys[*new_index]) => {
+                let (old_token, new_token) = (tokenized_old[*old_index], tokenized_new[*new_index]);
+                old_tokens.push(StyledToken::new(
+                    old_token.to_string(),
+                    Style::DiffPartRenamed,
+                ));
+                new_tokens.push(StyledToken::new(
+                    new_token.to_string(),
+                    Style::DiffPartRenamed,
+                ));
+            }
 
+            _ => {
+                let old_run = tokenized_old[change.old_range()].to_vec();
+                let new_run = tokenized_new[change.new_range()].to_vec();
+                let style = change_style(&old_run, &new_run, old_start_of_line, new_start_of_line);
                 push_styled_tokens(&mut old_tokens, old_run, style);
                 push_styled_tokens(&mut new_tokens, new_run, style);
             }
@@ -350,15 +1904,64 @@ pub fn diff(old_text: &str, new_text: &str) -> (Vec<StyledToken>, Vec<StyledToke
             .is_none_or(|token| token.token.ends_with('\n'));
     }
 
-    // Refine old tokens highlighting
-    bridge_consecutive_highlighted_tokens(&mut old_tokens);
+    return Some((old_tokens, new_tokens));
+}
 
-    // Refine new tokens highlighting
-    bridge_consecutive_highlighted_tokens(&mut new_tokens);
-    errorlight_trailing_whitespace(&mut new_tokens);
-    errorlight_nonleading_tabs(&mut new_tokens);
+/// Merge short unchanged islands into the changes on both sides of them.
+///
+/// `bridge_consecutive_highlighted_tokens()` does this for single tokens, this
+/// function does it for any unchanged run that is no longer than the changes
+/// surrounding it. This turns things like `-[a]b[c]` / `+[x]b[y]` into
+/// `-[abc]` / `+[xby]`, which is usually easier to read.
+///
+/// Unchanged runs containing newlines are never merged.
+fn semantic_cleanup(diff: Vec<DiffOp>, old_tokens: &[&str], new_tokens: &[&str]) -> Vec<DiffOp> {
+    fn char_count(tokens: &[&str]) -> usize {
+        return tokens.iter().map(|token| token.chars().count()).sum();
+    }
 
-    return (old_tokens, new_tokens);
+    // How many chars are changed on the widest side of this op?
+    let change_size = |op: &DiffOp| -> usize {
+        return cmp::max(
+            char_count(&old_tokens[op.old_range()]),
+            char_count(&new_tokens[op.new_range()]),
+        );
+    };
+
+    let mut result: Vec<DiffOp> = Vec::with_capacity(diff.len());
+    for op in diff {
+        if let DiffOp::Equal { .. } = op {
+            result.push(op);
+            continue;
+        }
+
+        // Do we have a change followed by a short equal run before this one?
+        let mergeable = match result.as_slice() {
+            [.., before, equal @ DiffOp::Equal { .. }]
+                if !matches!(before, DiffOp::Equal { .. }) =>
+            {
+                let equal_tokens = &old_tokens[equal.old_range()];
+                !equal_tokens.iter().any(|token| token.contains('\n'))
+                    && char_count(equal_tokens) <= cmp::min(change_size(before), change_size(&op))
+            }
+            _ => false,
+        };
+        if !mergeable {
+            result.push(op);
+            continue;
+        }
+
+        result.pop(); // The equal run
+        let before = result.pop().unwrap();
+        result.push(DiffOp::Replace {
+            old_index: before.old_range().start,
+            old_len: op.old_range().end - before.old_range().start,
+            new_index: before.new_range().start,
+            new_len: op.new_range().end - before.new_range().start,
+        });
+    }
+
+    return result;
 }
 
 /// Highlight single space between two highlighted tokens
@@ -469,6 +2072,19 @@ pub(crate) mod tests {
     pub(crate) static FORMATTER: Lazy<Formatter> = Lazy::new(|| Formatter {
         line_style_old: LINE_STYLE_OLD.clone(),
         line_style_new: LINE_STYLE_NEW.clone(),
+        refine_budget: DEFAULT_REFINE_BUDGET,
+        diff_options: DiffOptions {
+            algorithm: RefineAlgorithm::Patience,
+            semantic_cleanup: false,
+            tokenizer_profile: Profile::Generic,
+        },
+        collapse_reindented: false,
+        normalizers: Vec::new(),
+        align_columns: false,
+        collapse_generated: false,
+        notebook_cells: None,
+        hyperlinker: Hyperlinker::default(),
+        refine_per_parent: false,
     });
 
     #[test]
@@ -524,6 +2140,595 @@ pub(crate) mod tests {
         assert_eq!(text.lines().count(), result.len());
     }
 
+    #[test]
+    fn test_out_of_refine_budget() {
+        let prefixes = ["-", "+"];
+        let texts = ["a\n", "b\n"];
+
+        let result = FORMATTER
+            .with_refine_budget(Duration::ZERO)
+            .format(&prefixes, &texts);
+        assert_eq!(result, FORMATTER.format_simple(&prefixes, &texts));
+    }
+
+    #[test]
+    fn test_format_line_paired() {
+        let far_away = Instant::now() + Duration::from_secs(3600);
+        let result = FORMATTER
+            .format_line_paired(
+                &["-", "+"],
+                &["same\nold x\n", "same\nnew x\nextra\n"],
+                far_away,
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{YELLOW}same{NORMAL}"),
+                format!("{OLD}-{INVERSE_VIDEO}old{NO_INVERSE_VIDEO}{YELLOW} x{NORMAL}"),
+                format!("{GREEN}+{YELLOW}same{NORMAL}"),
+                format!("{GREEN}+{INVERSE_VIDEO}new{NO_INVERSE_VIDEO}{YELLOW} x{NORMAL}"),
+                format!("{GREEN}+extra{NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_align_lines() {
+        let far_away = Instant::now() + Duration::from_secs(3600);
+        assert_eq!(
+            align_lines(
+                &["let x = 5;\n", "return x;\n"],
+                &[
+                    "// Compute x\n",
+                    "let x = 6;\n",
+                    "println!(\"{x}\");\n",
+                    "return x;\n",
+                ],
+                Profile::Rust,
+                &[],
+                far_away
+            ),
+            Some(vec![(0, 1), (1, 3)])
+        );
+
+        // Nothing similar, nothing paired
+        assert_eq!(
+            align_lines(
+                &["apa\n"],
+                &["bepa\n", "cepa\n"],
+                Profile::Generic,
+                &[],
+                far_away
+            ),
+            Some(vec![])
+        );
+
+        // Only similar after normalization
+        let normalizers = [Normalizer::from_normalize_spec("[0-9a-f]{8}=HASH").unwrap()];
+        assert_eq!(
+            align_lines(
+                &["x\n", "12345678\n"],
+                &["abcdef01\n"],
+                Profile::Generic,
+                &normalizers,
+                far_away
+            ),
+            Some(vec![(1, 0)])
+        );
+    }
+
+    #[test]
+    fn test_line_similarity() {
+        let similarity = |old: &str, new: &str| {
+            line_similarity(
+                &tokenizer::tokenize(old, Profile::Generic),
+                &tokenizer::tokenize(new, Profile::Generic),
+            )
+        };
+
+        assert_eq!(similarity("a = b;\n", "a = b;\n"), 1.0);
+
+        // Shared punctuation doesn't make lines similar
+        assert_eq!(similarity("foo(bar);\n", "baz(qux);\n"), 0.0);
+
+        // One long changed part, like a hash, is just one change
+        assert!(
+            similarity(
+                "\"integrity\": \"sha512-abc+def/ghi==\",\n",
+                "\"integrity\": \"sha512-jkl+mno/pqr==\",\n"
+            ) > MIN_PAIRING_SIMILARITY
+        );
+    }
+
+    #[test]
+    fn test_uneven_block_pairs_similar_lines() {
+        let result = FORMATTER.format(
+            &["-", "+"],
+            &[
+                "let x = 5;\nlet y = 7;\n",
+                "// Compute x\nlet x = 6;\nlet y = 8;\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{YELLOW}let x = {INVERSE_VIDEO}{OLD}5{NO_INVERSE_VIDEO}{YELLOW};{NORMAL}"),
+                format!("{OLD}-{YELLOW}let y = {INVERSE_VIDEO}{OLD}7{NO_INVERSE_VIDEO}{YELLOW};{NORMAL}"),
+                format!("{GREEN}+// Compute x{NORMAL}"),
+                format!("{GREEN}+{YELLOW}let x = {INVERSE_VIDEO}{GREEN}6{NO_INVERSE_VIDEO}{YELLOW};{NORMAL}"),
+                format!("{GREEN}+{YELLOW}let y = {INVERSE_VIDEO}{GREEN}8{NO_INVERSE_VIDEO}{YELLOW};{NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reindented() {
+        let result = FORMATTER.format(
+            &["-", "+"],
+            &["foo();\nbar();\n", "if x {\n    foo();\n    bar(1);\n}\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}foo();{NORMAL}"),
+                format!("{OLD}-{YELLOW}bar();{NORMAL}"),
+                format!("{GREEN}+if x {{{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}    foo();{NORMAL}"),
+                format!("{GREEN}+    {YELLOW}bar({INVERSE_VIDEO}{GREEN}1{NO_INVERSE_VIDEO}{YELLOW});{NORMAL}"),
+                format!("{GREEN}+}}{NORMAL}"),
+                format!("{FAINT}(reindented +4){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_reindented() {
+        let result = FORMATTER.with_collapse_reindented(true).format(
+            &["-", "+"],
+            &["foo();\nbar();\n", "if x {\n    foo();\n    bar();\n}\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{GREEN}+if x {{{NORMAL}"),
+                format!(" {FAINT}    foo();{NORMAL}"),
+                format!(" {FAINT}    bar();{NORMAL}"),
+                format!("{GREEN}+}}{NORMAL}"),
+                format!("{FAINT}(reindented +4){NORMAL}"),
+            ]
+        );
+
+        // Removed and added lines stay together, with only the reindented
+        // lines around them collapsed
+        let result = FORMATTER.with_collapse_reindented(true).format(
+            &["-", "+"],
+            &["a\nfoo();\nb\nbar();\n", "x\n    foo();\ny\n    bar();\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{INVERSE_VIDEO}a{NORMAL}"),
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}foo();{NORMAL}"),
+                format!("{OLD}-{INVERSE_VIDEO}b{NORMAL}"),
+                format!("{GREEN}+{INVERSE_VIDEO}x{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}    foo();{NORMAL}"),
+                format!("{GREEN}+{INVERSE_VIDEO}y{NORMAL}"),
+                format!(" {FAINT}    bar();{NORMAL}"),
+                format!("{FAINT}(reindented +4){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reordered() {
+        let result = FORMATTER.format(
+            &["-", "+"],
+            &[
+                "use b;\nuse c;\nuse a;\n",
+                "use a;\nuse b;\nuse c;\nuse d;\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}use b;{NORMAL}"),
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}use c;{NORMAL}"),
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}use a;{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}use a;{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}use b;{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}use c;{NORMAL}"),
+                format!("{GREEN}+use d;{NORMAL}"),
+                format!("{FAINT}(reordered){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_reordered_lines() {
+        assert_eq!(
+            find_reordered_lines(&["a\n", "b\n", "x\n"], &["b\n", "a\n", "y\n"], &[]),
+            Some((vec![true, true, false], vec![true, true, false]))
+        );
+
+        // Same order, nothing reordered
+        assert_eq!(
+            find_reordered_lines(&["a\n", "b\n"], &["a\n", "x\n", "b\n"], &[]),
+            None
+        );
+
+        // Only punctuation moved
+        assert_eq!(
+            find_reordered_lines(&["{\n", "}\n"], &["}\n", "{\n"], &[]),
+            None
+        );
+
+        // Mostly new lines
+        assert_eq!(
+            find_reordered_lines(&["a\n", "b\n"], &["b\n", "a\n", "x\n", "y\n", "z\n"], &[]),
+            None
+        );
+
+        // Lines are compared after normalization
+        let normalizers = [Normalizer::from_ignore_pattern("[0-9]+").unwrap()];
+        assert_eq!(
+            find_reordered_lines(&["a 1\n", "b 2\n"], &["b 3\n", "a 4\n"], &normalizers),
+            Some((vec![true, true], vec![true, true]))
+        );
+    }
+
+    #[test]
+    fn test_has_reindented_lines() {
+        assert!(has_reindented_lines("a\n", "  a\n", &[]));
+        assert!(!has_reindented_lines("a\n", "a\n", &[]));
+        assert!(!has_reindented_lines("\n", "  \n", &[]));
+        assert!(!has_reindented_lines("a\n", "b\n", &[]));
+        assert!(!has_reindented_lines("}\n", "  }\n", &[]));
+
+        // Lines are compared after normalization
+        let normalizers = [Normalizer::from_ignore_pattern("[0-9]+").unwrap()];
+        assert!(has_reindented_lines("a 1\n", "  a 2\n", &normalizers));
+        assert!(!has_reindented_lines("a 1\n", "a 2\n", &normalizers));
+    }
+
+    #[test]
+    fn test_align_columns() {
+        assert_eq!(
+            align_columns(&["1,\"a, b\",x\n2,c,y\n", "1,de,xyz\r\n"], ','),
+            ["1,\"a, b\",x\n2,c     ,y\n", "1,de    ,xyz\r\n"]
+        );
+
+        // No trailing newline
+        assert_eq!(
+            align_columns(&["a\tb", "ccc\td"], '\t'),
+            ["a  \tb", "ccc\td"]
+        );
+    }
+
+    #[test]
+    fn test_prose_reflowed() {
+        let result = FORMATTER.with_tokenizer_profile(Profile::Prose).format(
+            &["-", "+"],
+            &[
+                "Some words here\nand there.\n",
+                "Some words\nhere and there.\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{YELLOW}Some words here{NORMAL}"),
+                format!("{OLD}-{YELLOW}and there.{NORMAL}"),
+                format!("{GREEN}+{YELLOW}Some words{NORMAL}"),
+                format!("{GREEN}+{YELLOW}here and there.{NORMAL}"),
+                format!("{FAINT}(reflowed){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prose_whitespace_changed() {
+        // Same line breaks, just more spaces. Not reflowed, and the word diff
+        // would hide the change, so this should be refined like any other
+        // text.
+        let result = FORMATTER
+            .with_tokenizer_profile(Profile::Prose)
+            .format(&["-", "+"], &["Some words\n", "Some  words\n"]);
+        assert!(!result.iter().any(|line| line.contains("reflowed")));
+        assert_eq!(
+            result,
+            FORMATTER.format(&["-", "+"], &["Some words\n", "Some  words\n"])
+        );
+    }
+
+    #[test]
+    fn test_prose_rewrapped_with_change() {
+        let result = FORMATTER.with_tokenizer_profile(Profile::Prose).format(
+            &["-", "+"],
+            &[
+                "Some words here\nand there.\n",
+                "Some words\nhere and everywhere.\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{YELLOW}Some words here{NORMAL}"),
+                format!("{OLD}-{YELLOW}and {INVERSE_VIDEO}{OLD}there{NO_INVERSE_VIDEO}{YELLOW}.{NORMAL}"),
+                format!("{GREEN}+{YELLOW}Some words{NORMAL}"),
+                format!("{GREEN}+{YELLOW}here and {INVERSE_VIDEO}{GREEN}everywhere{NO_INVERSE_VIDEO}{YELLOW}.{NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refine_algorithms() {
+        for algorithm in RefineAlgorithm::value_variants() {
+            let options = DiffOptions {
+                algorithm: *algorithm,
+                ..DiffOptions::default()
+            };
+            let (old_tokens, new_tokens) =
+                diff_with_options("a b c\n", "a x c\n", options, &[], &HashMap::new(), None)
+                    .unwrap();
+            assert_eq!(
+                old_tokens,
+                vec![
+                    StyledToken::new("a".to_string(), Style::DiffPartUnchanged),
+                    StyledToken::new(" ".to_string(), Style::DiffPartUnchanged),
+                    StyledToken::new("b".to_string(), Style::DiffPartHighlighted),
+                    StyledToken::new(" ".to_string(), Style::DiffPartUnchanged),
+                    StyledToken::new("c".to_string(), Style::DiffPartUnchanged),
+                    StyledToken::new("\n".to_string(), Style::DiffPartUnchanged),
+                ],
+                "{algorithm:?}"
+            );
+            assert_eq!(new_tokens[2].token, "x", "{algorithm:?}");
+            assert_eq!(new_tokens[2].style, Style::DiffPartHighlighted);
+        }
+    }
+
+    #[test]
+    fn test_diff_uses_options() {
+        let (_, new_tokens) = FORMATTER.diff("abcd ef ghij\n", "wxyz ef klmn\n");
+        assert_eq!(new_tokens[2].token, "ef");
+        assert_eq!(new_tokens[2].style, Style::DiffPartUnchanged);
+
+        let options = DiffOptions {
+            semantic_cleanup: true,
+            ..DiffOptions::default()
+        };
+        let (_, new_tokens) = FORMATTER
+            .with_diff_options(options)
+            .diff("abcd ef ghij\n", "wxyz ef klmn\n");
+        assert_eq!(new_tokens[2].token, "ef");
+        assert_eq!(new_tokens[2].style, Style::DiffPartHighlighted);
+    }
+
+    #[test]
+    fn test_changed_string_literal() {
+        let options = DiffOptions {
+            tokenizer_profile: Profile::Rust,
+            ..DiffOptions::default()
+        };
+        let (_, new_tokens) = diff_with_options(
+            "f(\"a b\");\n",
+            "f(\"a c\");\n",
+            options,
+            &[],
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            new_tokens,
+            vec![
+                StyledToken::new("f".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new("(".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new("\"".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new("a".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new(" ".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new("c".to_string(), Style::DiffPartHighlighted),
+                StyledToken::new("\"".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new(")".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new(";".to_string(), Style::DiffPartUnchanged),
+                StyledToken::new("\n".to_string(), Style::DiffPartUnchanged),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_changed() {
+        let (old_tokens, new_tokens) = FORMATTER.diff("get(userId)\n", "get(UserId)\n");
+        assert_eq!(
+            old_tokens[2],
+            StyledToken::new("userId".to_string(), Style::DiffPartCaseChanged)
+        );
+        assert_eq!(
+            new_tokens[2],
+            StyledToken::new("UserId".to_string(), Style::DiffPartCaseChanged)
+        );
+    }
+
+    #[test]
+    fn test_renamed() {
+        let result = FORMATTER.format(
+            &["-", "+"],
+            &["foo = 1\nprint(foo)\n", "bar = 1\nprint(bar)\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{UNDERLINE}foo{NO_UNDERLINE}{YELLOW} = 1{NORMAL}"),
+                format!("{OLD}-{YELLOW}print({UNDERLINE}{OLD}foo{NO_UNDERLINE}{YELLOW}){NORMAL}"),
+                format!("{GREEN}+{UNDERLINE}bar{NO_UNDERLINE}{YELLOW} = 1{FAINT}{DEFAULT_COLOR} (renamed foo → bar){NORMAL}"),
+                format!("{GREEN}+{YELLOW}print({UNDERLINE}{GREEN}bar{NO_UNDERLINE}{YELLOW}){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renamed_in_merge() {
+        // Each parent has its own rename, a → x from the second parent and
+        // b → y from the first one
+        let result = FORMATTER.format(
+            &["- ", " -", "++"],
+            &[
+                "x = 1\nprint(x)\nb = 2\nprint(b)\n",
+                "a = 1\nprint(a)\ny = 2\nprint(y)\n",
+                "x = 1\nprint(x)\ny = 2\nprint(y)\n",
+            ],
+        );
+        let legends = result
+            .iter()
+            .filter(|line| line.contains("(renamed"))
+            .collect::<Vec<_>>();
+        assert_eq!(legends.len(), 1);
+        assert!(legends[0].starts_with(&format!("{GREEN}++")));
+        assert!(legends[0].contains(" (renamed a → x, b → y)"));
+    }
+
+    #[test]
+    fn test_renamed_line_paired() {
+        // The reindented line makes this go through format_line_paired(),
+        // which diffs one line pair at a time
+        let result = FORMATTER.format(
+            &["-", "+"],
+            &["foo = 1\nprint(foo)\nz\n", "bar = 1\nprint(bar)\n  z\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{UNDERLINE}foo{NO_UNDERLINE}{YELLOW} = 1{NORMAL}"),
+                format!("{OLD}-{YELLOW}print({UNDERLINE}{OLD}foo{NO_UNDERLINE}{YELLOW}){NORMAL}"),
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}z{NORMAL}"),
+                format!("{GREEN}+{UNDERLINE}bar{NO_UNDERLINE}{YELLOW} = 1{FAINT}{DEFAULT_COLOR} (renamed foo → bar){NORMAL}"),
+                format!("{GREEN}+{YELLOW}print({UNDERLINE}{GREEN}bar{NO_UNDERLINE}{YELLOW}){NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}  z{NORMAL}"),
+                format!("{FAINT}(reindented +2){NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refine_per_parent() {
+        let prefixes = ["- ", " -", "++"];
+        let texts = [
+            "first main middle old last\n",
+            "first old middle branch last\n",
+            "first main middle branch last\n",
+        ];
+
+        // Without per-parent refinement, tokens from both parents look the same
+        let result = FORMATTER.format(&prefixes, &texts);
+        assert_eq!(
+            result[2],
+            format!("{GREEN}++{YELLOW}first {INVERSE_VIDEO}{GREEN}main{NO_INVERSE_VIDEO}{YELLOW} middle {INVERSE_VIDEO}{GREEN}branch{NO_INVERSE_VIDEO}{YELLOW} last{NORMAL}")
+        );
+
+        let result = FORMATTER
+            .with_refine_per_parent(true)
+            .format(&prefixes, &texts);
+        assert_eq!(
+            result,
+            [
+                format!("{CYAN}- {YELLOW}first main middle {INVERSE_VIDEO}{OLD}old{NO_INVERSE_VIDEO}{YELLOW} last{NORMAL}"),
+                format!("{BLUE} -{YELLOW}first {INVERSE_VIDEO}{OLD}old{NO_INVERSE_VIDEO}{YELLOW} middle branch last{NORMAL}"),
+                format!("{GREEN}++{YELLOW}first {INVERSE_VIDEO}{CYAN}main{NO_INVERSE_VIDEO}{YELLOW} middle {INVERSE_VIDEO}{BLUE}branch{NO_INVERSE_VIDEO}{YELLOW} last{NORMAL}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_renames() {
+        let old = ["a", "\n", "a", "\n", "b"];
+
+        // Both a:s renamed
+        let new = ["x", "\n", "x", "\n", "b"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+        assert_eq!(find_renames(&diff, &old, &new), HashMap::from([("a", "x")]));
+
+        // Just one a renamed
+        let new = ["x", "\n", "a", "\n", "b"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+        assert_eq!(find_renames(&diff, &old, &new), HashMap::new());
+
+        // The a:s renamed to different things
+        let new = ["x", "\n", "y", "\n", "b"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+        assert_eq!(find_renames(&diff, &old, &new), HashMap::new());
+
+        // Both renames on the same line
+        let old = ["a", " ", "a", "\n"];
+        let new = ["x", " ", "x", "\n"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+        assert_eq!(find_renames(&diff, &old, &new), HashMap::new());
+    }
+
+    #[test]
+    fn test_normalized() {
+        let normalizers = vec![Normalizer::from_ignore_pattern("[0-9]+:[0-9]+").unwrap()];
+        let result = FORMATTER.with_normalizers(normalizers).format(
+            &["-", "+"],
+            &["12:01 start x\n12:02 done\n", "12:05 start y\n12:09 done\n"],
+        );
+        assert_eq!(
+            result,
+            [
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}12:01{NORMAL_INTENSITY}{YELLOW} start {INVERSE_VIDEO}{OLD}x{NORMAL}"),
+                format!("{OLD}-{FAINT}{DEFAULT_COLOR}12:02 done{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}12:05{NORMAL_INTENSITY}{YELLOW} start {INVERSE_VIDEO}{GREEN}y{NORMAL}"),
+                format!("{GREEN}+{FAINT}{DEFAULT_COLOR}12:09 done{NORMAL}"),
+            ]
+        );
+
+        // A match spanning lines must not swallow the newline between them
+        let normalizers = vec![Normalizer::from_ignore_pattern(r"\s+").unwrap()];
+        let result = FORMATTER
+            .with_normalizers(normalizers)
+            .format(&["-", "+"], &["a \nb x\n", "a\n  b y\n"]);
+        let plain = result
+            .iter()
+            .map(|line| {
+                String::from_utf8(crate::ansi::without_ansi_escape_codes(line.as_bytes())).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(plain, ["-a ", "-b x", "+a", "+  b y"]);
+    }
+
+    #[test]
+    fn test_semantic_cleanup() {
+        let old = ["ab", "_", "cd", " ", "keep"];
+        let new = ["xy", "_", "zw", " ", "keep"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+
+        assert_eq!(
+            semantic_cleanup(diff, &old, &new),
+            [
+                DiffOp::Replace {
+                    old_index: 0,
+                    old_len: 3,
+                    new_index: 0,
+                    new_len: 3
+                },
+                DiffOp::Equal {
+                    old_index: 3,
+                    new_index: 3,
+                    len: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_semantic_cleanup_keeps_long_islands() {
+        let old = ["a", " ", "unchanged", " ", "b"];
+        let new = ["x", " ", "unchanged", " ", "y"];
+        let diff = capture_diff_slices_deadline(similar::Algorithm::Patience, &old, &new, None);
+
+        assert_eq!(semantic_cleanup(diff.clone(), &old, &new), diff);
+    }
+
     #[test]
     fn test_quote_change() {
         let result = FORMATTER.format(
@@ -558,7 +2763,7 @@ pub(crate) mod tests {
     #[test]
     fn test_space_highlighting() {
         // Add new initial spacing (indentation). We don't want to highlight indentation.
-        let (_, new_tokens) = diff("x", " x");
+        let (_, new_tokens) = FORMATTER.diff("x", " x");
         assert_eq!(
             new_tokens,
             vec![
@@ -568,7 +2773,7 @@ pub(crate) mod tests {
         );
 
         // Increase indentation. Do not highlight this.
-        let (_, new_tokens) = diff(" x", "  x");
+        let (_, new_tokens) = FORMATTER.diff(" x", "  x");
         assert_eq!(
             new_tokens,
             vec![
@@ -581,7 +2786,7 @@ pub(crate) mod tests {
         //
         // This particular example is from a Markdown heading where someone forgot
         // the space after the leading `#`.
-        let (_, new_tokens) = diff("#x", "# x");
+        let (_, new_tokens) = FORMATTER.diff("#x", "# x");
         assert_eq!(
             new_tokens,
             vec![
@@ -592,7 +2797,7 @@ pub(crate) mod tests {
         );
 
         // Increase internal space. We do not want to highlight this. Probably code reformatting.
-        let (_, new_tokens) = diff("x y", "x  y");
+        let (_, new_tokens) = FORMATTER.diff("x y", "x  y");
         assert_eq!(
             new_tokens,
             vec![
@@ -603,7 +2808,7 @@ pub(crate) mod tests {
         );
 
         // Remove trailing space. We do want to highlight this.
-        let (old_tokens, _) = diff("x ", "x");
+        let (old_tokens, _) = FORMATTER.diff("x ", "x");
         assert_eq!(
             old_tokens,
             vec![
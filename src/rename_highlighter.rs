@@ -1,9 +1,11 @@
 use crate::{
     file_highlighter::decorate_paths,
+    hyperlink::Hyperlinker,
     lines_highlighter::{LineAcceptance, LinesHighlighter, Response},
-    refiner::diff,
+    refiner::Formatter,
     string_future::StringFuture,
     token_collector::{render, LINE_STYLE_NEW_FILENAME, LINE_STYLE_OLD_FILENAME},
+    tokenizer::Profile,
 };
 
 // Parses sections looking like this:
@@ -11,6 +13,7 @@ use crate::{
 //   rename to new_name
 pub(crate) struct RenameHighlighter {
     old_name: String,
+    formatter: Formatter,
 }
 
 impl LinesHighlighter for RenameHighlighter {
@@ -44,10 +47,13 @@ impl LinesHighlighter for RenameHighlighter {
 
 impl RenameHighlighter {
     /// Returns None if this line doesn't start a new renames section
-    pub(crate) fn from_line(line: &str) -> Option<Self> {
+    pub(crate) fn from_line(line: &str, formatter: Formatter) -> Option<Self> {
         if let Some(rest) = line.strip_prefix("rename from ") {
             let old_name = rest.trim().to_string();
-            Some(Self { old_name })
+            Some(Self {
+                old_name,
+                formatter,
+            })
         } else {
             None
         }
@@ -56,11 +62,19 @@ impl RenameHighlighter {
     fn highlighted(&self, new_name: String) -> String {
         use crate::constants::{BOLD, NORMAL};
 
-        let (mut old_tokens, mut new_tokens) = diff(&self.old_name, &new_name);
+        let (mut old_tokens, mut new_tokens) = self
+            .formatter
+            .with_tokenizer_profile(Profile::Generic)
+            .diff(&self.old_name, &new_name);
         let old_filename = render(&LINE_STYLE_OLD_FILENAME, "", &old_tokens);
         let new_filename = render(&LINE_STYLE_NEW_FILENAME, "", &new_tokens);
 
-        decorate_paths(&mut old_tokens, &mut new_tokens, None);
+        decorate_paths(
+            &mut old_tokens,
+            &mut new_tokens,
+            None,
+            &Hyperlinker::default(),
+        );
 
         return format!(
             "rename from {old_filename}{NORMAL}\n{BOLD}rename to {new_filename}{NORMAL}\n"
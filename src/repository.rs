@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+/// The closest directory containing `.git`, starting at `path` and going up
+pub(crate) fn toplevel(path: &Path) -> Option<PathBuf> {
+    return path
+        .ancestors()
+        .find(|directory| directory.join(".git").exists())
+        .map(Path::to_path_buf);
+}
+
+/// A remote repository, as configured in `.git/config`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Remote {
+    /// Like `origin`
+    pub(crate) name: String,
+
+    /// Like `github.com`. Includes the port for HTTP remotes on custom ports.
+    pub(crate) host: String,
+
+    /// Like `walles/riff`
+    pub(crate) path: String,
+}
+
+/// The `origin` remote, or the first remote if there is no `origin`. Read
+/// from the repository's config file, no `git` or network access involved.
+pub(crate) fn remote(toplevel: &Path) -> Option<Remote> {
+    let config = fs::read_to_string(git_directory(toplevel)?.join("config")).ok()?;
+    let (name, url) = parse_remote_url(&config)?;
+    return parse_remote(name, &url);
+}
+
+/// `.git` is a directory, except in worktrees and submodules where it is a
+/// file pointing to the real one
+fn git_directory(toplevel: &Path) -> Option<PathBuf> {
+    let dot_git = toplevel.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let git_directory = toplevel.join(contents.strip_prefix("gitdir:")?.trim());
+
+    // Worktrees share their config with the main repository
+    return match fs::read_to_string(git_directory.join("commondir")) {
+        Ok(common_directory) => Some(git_directory.join(common_directory.trim())),
+        Err(_) => Some(git_directory),
+    };
+}
+
+/// Is there an object whose hash starts with `prefix` in the repository?
+/// Looks at loose objects and pack indexes, no `git` involved.
+pub(crate) fn has_object(toplevel: &Path, prefix: &str) -> bool {
+    if prefix.len() < 4 || !prefix.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return false;
+    }
+    let prefix = prefix.to_ascii_lowercase();
+    let Some(objects) = git_directory(toplevel).map(|git_directory| git_directory.join("objects"))
+    else {
+        return false;
+    };
+
+    // Loose objects are in files like `objects/12/34abcd...`
+    if let Ok(entries) = fs::read_dir(objects.join(&prefix[..2])) {
+        let rest = &prefix[2..];
+        if entries
+            .flatten()
+            .any(|entry| entry.file_name().to_string_lossy().starts_with(rest))
+        {
+            return true;
+        }
+    }
+
+    let mut pack_indexes = PACK_INDEXES.lock().unwrap();
+    return pack_indexes
+        .entry(objects.join("pack"))
+        .or_insert_with_key(|pack_directory| read_pack_indexes(pack_directory))
+        .iter()
+        .any(|index| index.has_object(&prefix));
+}
+
+/// Pack indexes by pack directory, so that each index is read only once
+static PACK_INDEXES: Lazy<Mutex<HashMap<PathBuf, Vec<PackIndex>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn read_pack_indexes(pack_directory: &Path) -> Vec<PackIndex> {
+    let Ok(packs) = fs::read_dir(pack_directory) else {
+        return vec![];
+    };
+    return packs
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .filter_map(|index| PackIndex::read(&index))
+        .collect();
+}
+
+/// The object names from a version 2 pack index, which starts with a header,
+/// then 256 fanout counts, then the sorted 20 byte object names.
+///
+/// Ref: https://git-scm.com/docs/gitformat-pack#_version_2_pack_idx_files_support_packs_larger_than_4_gib_and
+struct PackIndex {
+    /// How many objects have a first byte less than or equal to the index
+    fanout: Vec<usize>,
+
+    /// Sorted
+    names: Vec<[u8; 20]>,
+}
+
+impl PackIndex {
+    fn read(path: &Path) -> Option<PackIndex> {
+        let index = fs::read(path).ok()?;
+        if index.get(..8) != Some(&[0xff, b't', b'O', b'c', 0, 0, 0, 2]) {
+            return None;
+        }
+
+        let fanout = index
+            .get(8..8 + 256 * 4)?
+            .chunks_exact(4)
+            .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+            .collect::<Vec<_>>();
+        let names_start = 8 + 256 * 4;
+        let names = index
+            .get(names_start..names_start + 20 * fanout[255])?
+            .chunks_exact(20)
+            .map(|name| name.try_into().unwrap())
+            .collect();
+        return Some(PackIndex { fanout, names });
+    }
+
+    /// `prefix` must be lowercase hex
+    fn has_object(&self, prefix: &str) -> bool {
+        // Objects starting with this byte come after the ones counted in the
+        // previous fanout entry
+        let Ok(first_byte) = usize::from_str_radix(&prefix[..2], 16) else {
+            return false;
+        };
+        let start = if first_byte == 0 {
+            0
+        } else {
+            self.fanout[first_byte - 1]
+        };
+        let Some(candidates) = self.names.get(start..self.fanout[first_byte]) else {
+            return false;
+        };
+
+        // The first name at or after the prefix padded with zeroes is the only
+        // one that needs checking
+        let Some(lowest) = padded_object_name(prefix) else {
+            return false;
+        };
+        let first = candidates.partition_point(|name| *name < lowest);
+        return candidates.get(first).is_some_and(|name| {
+            let hex = name
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            return hex.starts_with(prefix);
+        });
+    }
+}
+
+/// The lowest object name starting with `prefix`, which must be hex
+fn padded_object_name(prefix: &str) -> Option<[u8; 20]> {
+    let padded = format!("{prefix:0<40}");
+    let mut name = [0; 20];
+    for (i, byte) in name.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(padded.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    return Some(name);
+}
+
+/// Names of all remotes, like `origin` and `upstream`
+pub(crate) fn remote_names(toplevel: &Path) -> Vec<String> {
+    let Some(config) = git_directory(toplevel)
+        .and_then(|git_directory| fs::read_to_string(git_directory.join("config")).ok())
+    else {
+        return vec![];
+    };
+    return parse_remotes(&config)
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .collect();
+}
+
+/// Name and URL of the remote to use
+fn parse_remote_url(config: &str) -> Option<(String, String)> {
+    let remotes = parse_remotes(config);
+    let (name, url) = remotes
+        .iter()
+        .find(|(name, _)| *name == "origin")
+        .or(remotes.first())?;
+    return Some((name.to_string(), url.to_string()));
+}
+
+/// Remote names and URLs, in file order
+fn parse_remotes(config: &str) -> Vec<(&str, &str)> {
+    let mut remotes: Vec<(&str, &str)> = Vec::new();
+    let mut current_remote: Option<&str> = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if let Some(section) = line.strip_prefix('[') {
+            current_remote = section
+                .strip_prefix("remote \"")
+                .and_then(|rest| rest.strip_suffix("\"]"));
+            continue;
+        }
+
+        let Some(remote) = current_remote else {
+            continue;
+        };
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim() == "url" {
+                remotes.push((remote, value.trim()));
+            }
+        }
+    }
+    return remotes;
+}
+
+/// Parse remote URLs like `git@github.com:owner/name.git` or
+/// `https://github.com/owner/name`
+fn parse_remote(name: String, url: &str) -> Option<Remote> {
+    let (host, path) = if let Some((scheme, rest)) = url.split_once("://") {
+        // https://user@host:port/owner/name
+        let (authority, path) = rest.split_once('/')?;
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let host = if scheme.starts_with("http") {
+            host
+        } else {
+            // SSH ports aren't web UI ports
+            host.split(':').next().unwrap_or(host)
+        };
+        (host, path)
+    } else {
+        // git@host:owner/name
+        let (authority, path) = url.split_once(':')?;
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        (host, path)
+    };
+
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    return Some(Remote {
+        name,
+        host: host.to_string(),
+        path: path.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_remote_url() {
+        let config = r#"[core]
+	bare = false
+[remote "upstream"]
+	url = https://github.com/walles/riff.git
+	fetch = +refs/heads/*:refs/remotes/upstream/*
+[remote "origin"]
+	url = git@github.com:johan/riff.git
+[branch "main"]
+	remote = origin
+"#;
+        assert_eq!(
+            parse_remote_url(config),
+            Some((
+                "origin".to_string(),
+                "git@github.com:johan/riff.git".to_string()
+            ))
+        );
+
+        let no_origin = config.replace("\"origin\"", "\"fork\"");
+        assert_eq!(
+            parse_remote_url(&no_origin),
+            Some((
+                "upstream".to_string(),
+                "https://github.com/walles/riff.git".to_string()
+            ))
+        );
+
+        assert_eq!(parse_remote_url("[core]\n\tbare = false\n"), None);
+    }
+
+    #[test]
+    fn test_parse_remote() {
+        let parse = |url| {
+            return parse_remote("origin".to_string(), url)
+                .map(|remote| (remote.host, remote.path));
+        };
+        assert_eq!(
+            parse("git@github.com:walles/riff.git"),
+            Some(("github.com".to_string(), "walles/riff".to_string()))
+        );
+        assert_eq!(
+            parse("https://github.com/walles/riff"),
+            Some(("github.com".to_string(), "walles/riff".to_string()))
+        );
+        assert_eq!(
+            parse("ssh://git@gitlab.example.com:2222/group/sub/project.git"),
+            Some((
+                "gitlab.example.com".to_string(),
+                "group/sub/project".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("https://user@git.example.com:8443/team/app.git"),
+            Some(("git.example.com:8443".to_string(), "team/app".to_string()))
+        );
+        assert_eq!(parse("https://github.com/"), None);
+        assert_eq!(parse("/srv/git/riff.git"), None);
+    }
+
+    #[test]
+    fn test_has_object() {
+        let toplevel = tempfile::tempdir().unwrap();
+        let objects = toplevel.path().join(".git/objects");
+        fs::create_dir_all(objects.join("97")).unwrap();
+        fs::create_dir_all(objects.join("pack")).unwrap();
+        fs::write(objects.join("97/f653d0123456789abcdef0123456789abcdef"), "").unwrap();
+
+        // A pack index with the objects 2760ae6..., 2760af1... and 27ff...
+        let mut index = vec![0xff, b't', b'O', b'c', 0, 0, 0, 2];
+        for first_byte in 0..256 {
+            let count: u32 = if first_byte < 0x27 { 0 } else { 3 };
+            index.extend(count.to_be_bytes());
+        }
+        for name_start in [
+            [0x27, 0x60, 0xae, 0x60],
+            [0x27, 0x60, 0xaf, 0x10],
+            [0x27, 0xff, 0, 0],
+        ] {
+            index.extend(name_start);
+            index.extend([0; 16]);
+        }
+        fs::write(objects.join("pack/pack-1234.idx"), index).unwrap();
+
+        assert!(has_object(toplevel.path(), "97f653d"));
+        assert!(has_object(toplevel.path(), "2760ae6"));
+        assert!(has_object(toplevel.path(), "2760AE60"));
+        assert!(!has_object(toplevel.path(), "2760ae7"));
+        assert!(has_object(toplevel.path(), "2760af1"));
+        assert!(has_object(toplevel.path(), "27ff0000"));
+        assert!(!has_object(toplevel.path(), "27ff0001"));
+        assert!(!has_object(toplevel.path(), "1234567"));
+        assert!(!has_object(toplevel.path(), "97f"));
+    }
+}
@@ -15,6 +15,10 @@ pub(crate) struct StringFuture {
     // If available, get() will await a result on this receiver, then populate
     // the result field and return it
     result_receiver: Option<Receiver<String>>,
+
+    // If available, get() will apply this to the result before returning it
+    #[allow(clippy::type_complexity)]
+    post_process: Option<Box<dyn FnOnce(String) -> String + Send>>,
 }
 
 impl StringFuture {
@@ -23,6 +27,7 @@ impl StringFuture {
         return StringFuture {
             result,
             result_receiver: None,
+            post_process: None,
         };
     }
 
@@ -45,9 +50,20 @@ impl StringFuture {
         return StringFuture {
             result: "".to_string(),
             result_receiver: Some(receiver),
+            post_process: None,
         };
     }
 
+    /// Transform the result once somebody asks for it, without waiting for
+    /// it now
+    pub fn map(mut self, f: impl FnOnce(String) -> String + Send + 'static) -> StringFuture {
+        self.post_process = Some(match self.post_process.take() {
+            Some(previous) => Box::new(move |result| f(previous(result))),
+            None => Box::new(f),
+        });
+        return self;
+    }
+
     #[allow(clippy::wrong_self_convention)]
     pub fn is_empty(&mut self) -> bool {
         return self.get().is_empty();
@@ -60,7 +76,16 @@ impl StringFuture {
             self.result = receiver.recv().unwrap();
             self.result_receiver = None;
         }
+        if let Some(post_process) = self.post_process.take() {
+            self.result = post_process(std::mem::take(&mut self.result));
+        }
 
         return &self.result;
     }
 }
+
+impl std::fmt::Debug for StringFuture {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "StringFuture");
+    }
+}
@@ -1,4 +1,5 @@
 use crate::ansi::AnsiStyle;
+use crate::ansi::Color;
 use crate::ansi::Color::Green;
 use crate::ansi::Color::Red;
 use crate::ansi::Weight;
@@ -12,6 +13,8 @@ pub(crate) enum Style {
     Bright,              // Bold
     DiffPartUnchanged,   // Yellow
     DiffPartMidlighted,  // Red or Green
+    DiffPartCaseChanged, // Underlined Yellow
+    DiffPartRenamed,     // Underlined Red or Green
     DiffPartHighlighted, // Inverse Red or Green
     Error,               // Inverse Red
 }
@@ -21,6 +24,9 @@ pub(crate) struct StyledToken {
     pub(crate) token: String,
     pub(crate) style: Style,
     pub(crate) url: Option<url::Url>,
+
+    /// Overrides the color of the style, see `--refine-per-parent`
+    pub(crate) color: Option<Color>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -54,6 +60,14 @@ pub(crate) static LINE_STYLE_CONFLICT_NEW: Lazy<LineStyle> = Lazy::new(|| LineSt
     highlighted_style: ANSI_STYLE_NORMAL.with_color(Green).with_inverse(true),
 });
 
+/// For reindented lines shown as context, see `--collapse-reindented`
+pub(crate) static LINE_STYLE_REINDENTED: Lazy<LineStyle> = Lazy::new(|| LineStyle {
+    prefix_style: ANSI_STYLE_NORMAL,
+    unchanged_style: ANSI_STYLE_NORMAL,
+    midlighted_style: ANSI_STYLE_NORMAL,
+    highlighted_style: ANSI_STYLE_NORMAL.with_inverse(true),
+});
+
 pub(crate) static LINE_STYLE_OLD_FILENAME: Lazy<LineStyle> = Lazy::new(|| LineStyle {
     prefix_style: ANSI_STYLE_NORMAL.with_weight(Weight::Bold),
     unchanged_style: ANSI_STYLE_NORMAL,
@@ -75,6 +89,7 @@ impl StyledToken {
                 token,
                 style,
                 url: None,
+                color: None,
             };
         }
 
@@ -84,6 +99,7 @@ impl StyledToken {
                 token,
                 style,
                 url: None,
+                color: None,
             };
         }
 
@@ -93,6 +109,7 @@ impl StyledToken {
             token: symbol.to_string(),
             style,
             url: None,
+            color: None,
         };
     }
 
@@ -126,6 +143,8 @@ pub(crate) fn render_row(
             Style::Bright => ANSI_STYLE_NORMAL.with_weight(Weight::Bold),
             Style::DiffPartUnchanged => line_style.unchanged_style.clone(),
             Style::DiffPartMidlighted => line_style.midlighted_style.clone(),
+            Style::DiffPartCaseChanged => line_style.unchanged_style.with_underline(true),
+            Style::DiffPartRenamed => line_style.midlighted_style.with_underline(true),
             Style::DiffPartHighlighted => line_style.highlighted_style.clone(),
             Style::Error => ANSI_STYLE_NORMAL.with_color(Red).with_inverse(true),
         };
@@ -134,6 +153,10 @@ pub(crate) fn render_row(
             new_style = new_style.with_weight(Weight::Faint);
         }
 
+        if let Some(color) = token.color {
+            new_style = new_style.with_color(color);
+        }
+
         if let Some(url) = &token.url {
             new_style = new_style.with_url(url.clone());
         }
@@ -252,18 +275,20 @@ mod tests {
     #[test]
     fn test_basic() {
         let rendered = render(
-            &*LINE_STYLE_NEW,
+            &LINE_STYLE_NEW,
             "+",
             &[
                 StyledToken {
                     token: "hej".to_string(),
                     style: Style::DiffPartMidlighted,
                     url: None,
+                    color: None,
                 },
                 StyledToken {
                     token: "\n".to_string(),
                     style: Style::DiffPartMidlighted,
                     url: None,
+                    color: None,
                 },
             ],
         );
@@ -274,7 +299,7 @@ mod tests {
     fn test_removed_trailing_whitespace() {
         // It shouldn't be highlighted, just added ones should
         let actual = render(
-            &*LINE_STYLE_OLD,
+            &LINE_STYLE_OLD,
             "-",
             &[StyledToken::new(" ".to_string(), Style::DiffPartMidlighted)],
         );
@@ -286,7 +311,7 @@ mod tests {
     fn test_removed_nonleading_tab() {
         // It shouldn't be highlighted, just added ones should
         let actual = render(
-            &*LINE_STYLE_OLD,
+            &LINE_STYLE_OLD,
             "-",
             &[
                 StyledToken::new("x".to_string(), Style::DiffPartMidlighted),
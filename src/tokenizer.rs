@@ -1,3 +1,31 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Scripts written without spaces between words.
+///
+/// We can't tell where words in these start and end just by looking for
+/// spaces, so we leave them to the UAX #29 word segmentation instead, see
+/// `generic_token_length()`.
+fn is_unspaced_script(character: char) -> bool {
+    return matches!(character,
+        '\u{0e00}'..='\u{0eff}' // Thai and Lao
+        | '\u{1000}'..='\u{109f}' // Myanmar
+        | '\u{1780}'..='\u{17ff}' // Khmer
+        | '\u{19e0}'..='\u{19ff}' // Khmer symbols
+        | '\u{2e80}'..='\u{2fdf}' // CJK radicals
+        | '\u{3005}' | '\u{3007}' // Ideographic iteration mark and number zero
+        | '\u{3021}'..='\u{3029}' // Hangzhou numerals
+        | '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{31f0}'..='\u{31ff}' // Katakana phonetic extensions
+        | '\u{3400}'..='\u{4dbf}' // CJK extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK unified ideographs
+        | '\u{a9e0}'..='\u{a9ff}' // Myanmar extended B
+        | '\u{aa60}'..='\u{aa7f}' // Myanmar extended A
+        | '\u{f900}'..='\u{faff}' // CJK compatibility ideographs
+        | '\u{ff66}'..='\u{ff9f}' // Halfwidth Katakana
+        | '\u{20000}'..='\u{3134f}' // CJK extensions B and up
+    );
+}
+
 fn is_word_part(character: char) -> bool {
     if character == '_' {
         return true;
@@ -7,70 +35,437 @@ fn is_word_part(character: char) -> bool {
         return false;
     }
 
-    if ('\u{3040}'..='\u{30ff}').contains(&character) {
-        // Hiragana and Katakana.
-        //
-        // Neither has any spacing so we can't tell where words start and end. Treat them as individuals.
+    if is_unspaced_script(character) {
         return false;
     }
 
     return true;
 }
 
-// FIXME: The doctest doesn't run on `cargo test`, why?
-/// Splits string into a vector of words. A word is any sequence of alphanumeric
-/// characters. Non-words get into the vector one and one.
+/// How to split text into tokens. Selected based on the file name, see
+/// `Profile::from_filename()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Profile {
+    /// Words, whitespace runs and single characters
+    #[default]
+    Generic,
+
+    /// C, C++, Java, JavaScript, Go and friends
+    CLike,
+
+    Rust,
+    Python,
+    Shell,
+    Lisp,
+
+    /// Like `Generic`, but keeps words like "don't" and "well-known" together
+    Prose,
+
+    /// Comma separated values, one token per field
+    Csv,
+
+    /// Tab separated values, one token per field
+    Tsv,
+}
+
+impl Profile {
+    /// Pick a tokenizer profile based on a file name or path
+    pub(crate) fn from_filename(filename: &str) -> Profile {
+        let basename = filename.rsplit('/').next().unwrap_or(filename);
+        let extension = match basename.rsplit_once('.') {
+            Some((_, extension)) => extension.to_ascii_lowercase(),
+            None => return Profile::Generic,
+        };
+
+        return match extension.as_str() {
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "m" | "mm" | "java"
+            | "kt" | "kts" | "scala" | "swift" | "cs" | "go" | "js" | "jsx" | "mjs" | "cjs"
+            | "ts" | "tsx" | "dart" | "php" | "groovy" | "gradle" => Profile::CLike,
+            "rs" => Profile::Rust,
+            "py" | "pyi" | "pyw" => Profile::Python,
+            "sh" | "bash" | "zsh" | "ksh" | "fish" => Profile::Shell,
+            "lisp" | "lsp" | "cl" | "el" | "scm" | "ss" | "rkt" | "clj" | "cljs" | "cljc"
+            | "edn" | "fnl" => Profile::Lisp,
+            "md" | "markdown" | "rst" | "txt" | "tex" | "adoc" | "org" => Profile::Prose,
+            "csv" => Profile::Csv,
+            "tsv" | "tab" => Profile::Tsv,
+            _ => Profile::Generic,
+        };
+    }
+
+    /// Multi character operators, longest first so that we can pick the first
+    /// match
+    fn operators(self) -> &'static [&'static str] {
+        return match self {
+            Profile::CLike => &[
+                ">>>=", "<<=", ">>=", ">>>", "...", "===", "!==", "->", "::", "==", "!=", "<=",
+                ">=", "&&", "||", "??", "?.", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=",
+                "^=", "<<", ">>", "=>",
+            ],
+            Profile::Rust => &[
+                "<<=", ">>=", "..=", "...", "->", "=>", "::", "==", "!=", "<=", ">=", "&&", "||",
+                "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "..",
+            ],
+            Profile::Python => &[
+                "**=", "//=", "<<=", ">>=", "->", ":=", "==", "!=", "<=", ">=", "**", "//", "+=",
+                "-=", "*=", "/=", "%=", "&=", "|=", "^=", "@=", "<<", ">>",
+            ],
+            Profile::Shell => &[
+                "$((", "<<<", "<<-", "&&", "||", ";;", "<<", ">>", "&>", ">&", "|&", "$(", "${",
+                "))", "[[", "]]",
+            ],
+            Profile::Generic | Profile::Lisp | Profile::Prose | Profile::Csv | Profile::Tsv => &[],
+        };
+    }
+
+    /// Comment markers that should be tokens of their own, longest first
+    fn comment_markers(self) -> &'static [&'static str] {
+        return match self {
+            Profile::CLike => &["/**", "//", "/*", "*/"],
+            Profile::Rust => &["///", "//!", "/**", "/*!", "//", "/*", "*/"],
+            Profile::Lisp => &[";;;;", ";;;", ";;"],
+            Profile::Python
+            | Profile::Shell
+            | Profile::Generic
+            | Profile::Prose
+            | Profile::Csv
+            | Profile::Tsv => &[],
+        };
+    }
+
+    /// Characters starting string literals
+    fn quotes(self) -> &'static [char] {
+        return match self {
+            Profile::CLike | Profile::Shell => &['"', '\'', '`'],
+            Profile::Python => &['"', '\''],
+            Profile::Rust | Profile::Lisp => &['"'],
+            // Quoted CSV fields are handled by `field_length()`
+            Profile::Generic | Profile::Prose | Profile::Csv | Profile::Tsv => &[],
+        };
+    }
+
+    /// The field separator of delimited formats like CSV
+    pub(crate) fn field_separator(self) -> Option<char> {
+        return match self {
+            Profile::Csv => Some(','),
+            Profile::Tsv => Some('\t'),
+            _ => None,
+        };
+    }
+}
+
+fn is_lisp_symbol_part(character: char) -> bool {
+    return !character.is_whitespace() && !"()[]{}\"';`,".contains(character);
+}
+
+/// Is this token a complete string or character literal?
+pub(crate) fn is_string_literal(token: &str, profile: Profile) -> bool {
+    let Some(first) = token.chars().next() else {
+        return false;
+    };
+    let is_quote = profile.quotes().contains(&first) || (profile == Profile::Rust && first == '\'');
+
+    return is_quote && token.len() >= 2 && token.ends_with(first);
+}
+
+/// Length in bytes of the first token in `input`, as split by the `Generic`
+/// profile.
 ///
-/// ```rust
-/// assert_eq!(tokenize("Adam, Bea"), ["Adam", ",", " ", "Bea"]);
-/// ```
-pub fn tokenize(input: &str) -> Vec<&str> {
-    let mut run_start_byte_index: Option<usize> = None;
-    let mut run_start_char: Option<char> = None;
-    let mut result: Vec<&str> = Vec::with_capacity(input.len());
-    let mut byte_index = 0;
-    for character in input.chars() {
-        let mut still_in_run = false;
-        if let (Some(start_index), Some(start_char)) = (run_start_byte_index, run_start_char) {
-            let current_is_word_part = is_word_part(character);
-            let last_was_word_part = (start_char == '_') || start_char.is_alphanumeric();
-            let still_in_word = current_is_word_part && last_was_word_part;
-            let still_in_whitespace =
-                start_char.is_whitespace() && start_char != '\n' && character == start_char;
-            if still_in_word || still_in_whitespace {
-                still_in_run = true;
-            } else {
-                run_start_byte_index = None;
-                run_start_char = None;
-
-                // Push the token that just ended
-                result.push(&input[start_index..byte_index]);
-            }
+/// Grapheme clusters are never split, so emoji and characters with combining
+/// marks stay in one piece.
+fn generic_token_length(input: &str) -> usize {
+    let first = input.chars().next().unwrap();
+
+    if is_unspaced_script(first) {
+        // UAX #29 word segmentation. This keeps Katakana runs together but
+        // splits Hiragana, Han and Thai into individual characters, which is
+        // better than highlighting whole sentences.
+        return input.split_word_bounds().next().unwrap().len();
+    }
+
+    if is_word_part(first) {
+        return input
+            .graphemes(true)
+            .take_while(|grapheme| grapheme.chars().next().is_some_and(is_word_part))
+            .map(str::len)
+            .sum();
+    }
+
+    if first.is_whitespace() && first != '\n' {
+        return input
+            .chars()
+            .take_while(|c| *c == first)
+            .map(char::len_utf8)
+            .sum();
+    }
+
+    if first.is_control() {
+        // Keep "\r\n" as two separate tokens, a lot of code looks for "\n"
+        // tokens
+        return first.len_utf8();
+    }
+
+    return input.graphemes(true).next().unwrap().len();
+}
+
+/// Length in bytes of a string literal starting with the quote character at
+/// the start of `input`. String literals never span lines.
+///
+/// Returns `None` if there is no closing quote on this line.
+fn string_literal_length(input: &str, backslash_escapes: bool) -> Option<usize> {
+    let quote = input.chars().next().unwrap();
+    let mut escaped = false;
+    for (index, character) in input.char_indices().skip(1) {
+        if character == '\n' {
+            return None;
+        }
+        if escaped {
+            escaped = false;
+            continue;
         }
+        if character == '\\' && backslash_escapes {
+            escaped = true;
+            continue;
+        }
+        if character == quote {
+            return Some(index + character.len_utf8());
+        }
+    }
 
-        if !still_in_run {
-            // Start a new run?
-            if (character == '_')
-                || character.is_alphanumeric()
-                || (character.is_whitespace() && character != '\n')
-            {
-                run_start_byte_index = Some(byte_index);
-                run_start_char = Some(character);
-                still_in_run = true;
-            }
+    return None;
+}
+
+/// Length in bytes of a Rust character literal at the start of `input`. Rust
+/// lifetimes also start with a single quote, so we have to be careful here.
+fn rust_char_literal_length(input: &str) -> Option<usize> {
+    let mut chars = input.char_indices().skip(1);
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        return string_literal_length(input, true);
+    }
+
+    let (index, second) = chars.next()?;
+    if second == '\'' && first != '\n' {
+        return Some(index + 1);
+    }
+
+    return None;
+}
+
+/// Length in bytes of a numeric literal at the start of `input`, like `0xFF`,
+/// `1_000u32` or `1.5e-3`.
+fn number_literal_length(input: &str) -> usize {
+    let bytes = input.as_bytes();
+    let word_end = |start: usize| -> usize {
+        return start
+            + input[start..]
+                .chars()
+                .take_while(|c| *c == '_' || c.is_ascii_alphanumeric())
+                .count();
+    };
+
+    let mut length = word_end(0);
+    let is_hex = bytes.len() > 1 && bytes[0] == b'0' && (bytes[1] | 0x20) == b'x';
+    if is_hex {
+        return length;
+    }
+
+    let is_digit_at = |index: usize| -> bool {
+        return index < bytes.len() && bytes[index].is_ascii_digit();
+    };
+
+    // Decimals, but not Rust ranges like `0..5`
+    if length < bytes.len() && bytes[length] == b'.' && is_digit_at(length + 1) {
+        length = word_end(length + 1);
+    }
+
+    // Negative exponent, like in `1.5e-3`
+    if (bytes[length - 1] | 0x20) == b'e'
+        && length < bytes.len()
+        && (bytes[length] == b'-' || bytes[length] == b'+')
+        && is_digit_at(length + 1)
+    {
+        length = word_end(length + 1);
+    }
+
+    return length;
+}
+
+/// Length in bytes of the first token in `input` for a programming language
+/// profile.
+fn language_token_length(input: &str, profile: Profile) -> usize {
+    if let Some(marker) = profile
+        .comment_markers()
+        .iter()
+        .find(|marker| input.starts_with(*marker))
+    {
+        return marker.len();
+    }
+
+    let first = input.chars().next().unwrap();
+
+    if profile == Profile::Python && (input.starts_with("\"\"\"") || input.starts_with("'''")) {
+        // Docstring delimiter, the contents may span lines so we don't try to
+        // make the whole thing one token
+        return 3;
+    }
+
+    if profile.quotes().contains(&first) {
+        let backslash_escapes = !(profile == Profile::Shell && first == '\'');
+        if let Some(length) = string_literal_length(input, backslash_escapes) {
+            return length;
+        }
+    }
+
+    if profile == Profile::Rust && first == '\'' {
+        if let Some(length) = rust_char_literal_length(input) {
+            return length;
         }
+    }
+
+    if first.is_ascii_digit() {
+        return number_literal_length(input);
+    }
 
-        if !still_in_run {
-            // Push the current character
-            result.push(&input[byte_index..(byte_index + character.len_utf8())]);
+    if profile == Profile::Lisp && is_lisp_symbol_part(first) && !first.is_alphanumeric() {
+        // Symbols like `*global*` or `->>`. Symbols starting with letters are
+        // handled below together with the other words.
+        return input
+            .chars()
+            .take_while(|c| is_lisp_symbol_part(*c))
+            .map(char::len_utf8)
+            .sum();
+    }
+
+    if let Some(operator) = profile
+        .operators()
+        .iter()
+        .find(|operator| input.starts_with(*operator))
+    {
+        return operator.len();
+    }
+
+    let length = generic_token_length(input);
+    if profile == Profile::Lisp && (first == '_' || first.is_alphanumeric()) {
+        // Symbols like `with-open-file` or `empty?`
+        return length
+            + input[length..]
+                .chars()
+                .take_while(|c| is_lisp_symbol_part(*c))
+                .map(char::len_utf8)
+                .sum::<usize>();
+    }
+
+    return length;
+}
+
+/// Length in bytes of the first token in `input` for the `Prose` profile
+fn prose_token_length(input: &str) -> usize {
+    let mut length = generic_token_length(input);
+    if !is_word_part(input[..length].chars().next_back().unwrap()) {
+        return length;
+    }
+
+    // Join words separated by single apostrophes or hyphens
+    loop {
+        let mut rest = input[length..].chars();
+        let Some(joiner) = rest.next() else {
+            return length;
+        };
+        if !['\'', '’', '-'].contains(&joiner) {
+            return length;
+        }
+        if !rest.next().is_some_and(is_word_part) {
+            return length;
+        }
+
+        let joiner_length = joiner.len_utf8();
+        length += joiner_length + generic_token_length(&input[length + joiner_length..]);
+    }
+}
+
+/// Length in bytes of the field at the start of `input`, up to the next
+/// separator or line break. Quoted CSV fields may contain separators.
+fn field_length(input: &str, separator: char) -> usize {
+    // TSV has no quoting, tabs just aren't allowed inside of fields
+    let quoting = separator == ',';
+
+    let mut quoted = false;
+    for (index, character) in input.char_indices() {
+        if character == '\n' || character == '\r' {
+            return index;
+        }
+        if character == '"' && quoting {
+            // Doubled quotes inside of quoted fields toggle this twice
+            quoted = !quoted;
+        } else if character == separator && !quoted {
+            return index;
         }
+    }
+
+    return input.len();
+}
 
-        byte_index += character.len_utf8();
+/// Length in bytes of the first token in `input` for delimited formats like
+/// CSV. Fields are tokens, and so are separators, line breaks and whitespace
+/// around fields.
+fn delimited_token_length(input: &str, separator: char) -> usize {
+    let first = input.chars().next().unwrap();
+    if first == separator || first == '\n' || first == '\r' {
+        return first.len_utf8();
     }
 
-    if let Some(run_start) = run_start_byte_index {
-        // Push the ending token
-        result.push(&input[run_start..]);
+    if first.is_whitespace() {
+        return input
+            .chars()
+            .take_while(|c| c.is_whitespace() && *c != separator && *c != '\n' && *c != '\r')
+            .map(char::len_utf8)
+            .sum();
+    }
+
+    return input[..field_length(input, separator)].trim_end().len();
+}
+
+/// Split one line of a delimited file into its fields, without the
+/// separators and without the line break.
+pub(crate) fn split_fields(line: &str, separator: char) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim_end_matches(['\n', '\r']);
+    loop {
+        let length = field_length(rest, separator);
+        fields.push(&rest[..length]);
+        rest = &rest[length..];
+        match rest.strip_prefix(separator) {
+            Some(after_separator) => rest = after_separator,
+            None => return fields,
+        }
+    }
+}
+
+// FIXME: The doctest doesn't run on `cargo test`, why?
+/// Splits string into a vector of words. A word is any sequence of alphanumeric
+/// characters. Non-words get into the vector one and one.
+///
+/// Non-`Generic` profiles add rules for operators, numbers, string literals
+/// and comment markers.
+///
+/// ```rust
+/// assert_eq!(tokenize("Adam, Bea", Profile::Generic), ["Adam", ",", " ", "Bea"]);
+/// ```
+pub fn tokenize(input: &str, profile: Profile) -> Vec<&str> {
+    let mut result: Vec<&str> = Vec::with_capacity(input.len());
+    let mut rest = input;
+    while !rest.is_empty() {
+        let length = match profile {
+            Profile::Generic => generic_token_length(rest),
+            Profile::Prose => prose_token_length(rest),
+            Profile::Csv => delimited_token_length(rest, ','),
+            Profile::Tsv => delimited_token_length(rest, '\t'),
+            _ => language_token_length(rest, profile),
+        };
+
+        result.push(&rest[..length]);
+        rest = &rest[length..];
     }
 
     return result;
@@ -86,54 +481,245 @@ mod tests {
     #[test]
     fn test_empty() {
         let no_strings: Vec<String> = Vec::new();
-        assert_eq!(tokenize(""), no_strings);
+        assert_eq!(tokenize("", Profile::Generic), no_strings);
     }
 
     #[test]
     fn test_words() {
-        assert_eq!(tokenize("word"), ["word"]);
-        assert_eq!(tokenize("Adam Bea"), ["Adam", " ", "Bea"]);
+        assert_eq!(tokenize("word", Profile::Generic), ["word"]);
+        assert_eq!(tokenize("Adam Bea", Profile::Generic), ["Adam", " ", "Bea"]);
     }
 
     #[test]
     fn test_cjk() {
         assert_eq!(
-            tokenize("こんにちは。"),
+            tokenize("こんにちは。", Profile::Generic),
             ["こ", "ん", "に", "ち", "は", "。"]
         );
     }
 
+    #[test]
+    fn test_han() {
+        assert_eq!(
+            tokenize("我喜欢猫。", Profile::Generic),
+            ["我", "喜", "欢", "猫", "。"]
+        );
+        assert_eq!(tokenize("Rust語", Profile::Generic), ["Rust", "語"]);
+    }
+
+    #[test]
+    fn test_katakana_word() {
+        assert_eq!(
+            tokenize("カタカナです", Profile::Generic),
+            ["カタカナ", "で", "す"]
+        );
+    }
+
+    #[test]
+    fn test_thai() {
+        // Vowel and tone marks stay with their consonants
+        assert_eq!(
+            tokenize("กินข้าว", Profile::Generic),
+            ["กิ", "น", "ข้", "า", "ว"]
+        );
+    }
+
+    #[test]
+    fn test_grapheme_clusters() {
+        // Combining acute accent
+        assert_eq!(
+            tokenize("cafe\u{0301} ok", Profile::Generic),
+            ["cafe\u{0301}", " ", "ok"]
+        );
+
+        // Family emoji, joined by zero width joiners
+        assert_eq!(
+            tokenize("a👨\u{200d}👩\u{200d}👧b", Profile::Generic),
+            ["a", "👨\u{200d}👩\u{200d}👧", "b"]
+        );
+
+        // Flag, made from two regional indicators
+        assert_eq!(tokenize("🇸🇪!", Profile::Generic), ["🇸🇪", "!"]);
+    }
+
+    #[test]
+    fn test_crlf() {
+        assert_eq!(tokenize("a\r\n", Profile::Generic), ["a", "\r", "\n"]);
+    }
+
     #[test]
     fn test_numbers() {
-        assert_eq!(tokenize("123"), ["123"]);
-        assert_eq!(tokenize("123 456"), ["123", " ", "456"]);
+        assert_eq!(tokenize("123", Profile::Generic), ["123"]);
+        assert_eq!(tokenize("123 456", Profile::Generic), ["123", " ", "456"]);
     }
 
     #[test]
     fn test_alphanumeric() {
-        assert_eq!(tokenize("0xC0deCafe"), ["0xC0deCafe"]);
+        assert_eq!(tokenize("0xC0deCafe", Profile::Generic), ["0xC0deCafe"]);
     }
 
     #[test]
     fn test_others() {
-        assert_eq!(tokenize("+!,"), ["+", "!", ","]);
+        assert_eq!(tokenize("+!,", Profile::Generic), ["+", "!", ","]);
     }
 
     #[test]
     fn test_non_breaking_space() {
-        assert_eq!(tokenize("\u{00a0}"), ["\u{00a0}"]);
-        assert_eq!(tokenize("\u{00a0}s"), ["\u{00a0}", "s"]);
+        assert_eq!(tokenize("\u{00a0}", Profile::Generic), ["\u{00a0}"]);
+        assert_eq!(tokenize("\u{00a0}s", Profile::Generic), ["\u{00a0}", "s"]);
     }
 
     #[test]
     fn test_leading_whitespace() {
-        assert_eq!(tokenize("  word"), ["  ", "word"]);
-        assert_eq!(tokenize("  \t  word"), ["  ", "\t", "  ", "word"]);
-        assert_eq!(tokenize("\t\t\tword"), ["\t\t\t", "word"]);
+        assert_eq!(tokenize("  word", Profile::Generic), ["  ", "word"]);
+        assert_eq!(
+            tokenize("  \t  word", Profile::Generic),
+            ["  ", "\t", "  ", "word"]
+        );
+        assert_eq!(tokenize("\t\t\tword", Profile::Generic), ["\t\t\t", "word"]);
     }
 
     #[test]
     fn test_consecutive_newlines() {
-        assert_eq!(tokenize("\n\n\n"), ["\n", "\n", "\n"]);
+        assert_eq!(tokenize("\n\n\n", Profile::Generic), ["\n", "\n", "\n"]);
+    }
+
+    #[test]
+    fn test_from_filename() {
+        assert_eq!(Profile::from_filename("src/main.rs"), Profile::Rust);
+        assert_eq!(Profile::from_filename("a/b.d/Main.JAVA"), Profile::CLike);
+        assert_eq!(Profile::from_filename("README.md"), Profile::Prose);
+        assert_eq!(Profile::from_filename("fixtures/users.CSV"), Profile::Csv);
+        assert_eq!(Profile::from_filename("Makefile"), Profile::Generic);
+        assert_eq!(Profile::from_filename("dir.py/Makefile"), Profile::Generic);
+    }
+
+    #[test]
+    fn test_rust_tokens() {
+        assert_eq!(
+            tokenize("a::b -> c != 1..=5 // x", Profile::Rust),
+            [
+                "a", "::", "b", " ", "->", " ", "c", " ", "!=", " ", "1", "..=", "5", " ", "//",
+                " ", "x"
+            ]
+        );
+        assert_eq!(
+            tokenize("f(\"a \\\" b\", 'x', '\\n')", Profile::Rust),
+            [
+                "f",
+                "(",
+                "\"a \\\" b\"",
+                ",",
+                " ",
+                "'x'",
+                ",",
+                " ",
+                "'\\n'",
+                ")"
+            ]
+        );
+
+        // Lifetimes are not char literals
+        assert_eq!(
+            tokenize("&'a str", Profile::Rust),
+            ["&", "'", "a", " ", "str"]
+        );
+    }
+
+    #[test]
+    fn test_numbers_with_profile() {
+        assert_eq!(
+            tokenize("1.5e-3+0xFF-2", Profile::CLike),
+            ["1.5e-3", "+", "0xFF", "-", "2"]
+        );
+        assert_eq!(
+            tokenize("x.len() - 1_000u32", Profile::Rust),
+            ["x", ".", "len", "(", ")", " ", "-", " ", "1_000u32"]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        assert_eq!(
+            tokenize("echo \"hello\n\"", Profile::Shell),
+            ["echo", " ", "\"", "hello", "\n", "\""]
+        );
+    }
+
+    #[test]
+    fn test_shell_single_quotes() {
+        assert_eq!(
+            tokenize("echo 'a\\' && ls", Profile::Shell),
+            ["echo", " ", "'a\\'", " ", "&&", " ", "ls"]
+        );
+    }
+
+    #[test]
+    fn test_lisp_symbols() {
+        assert_eq!(
+            tokenize("(with-open-file (*s* \"f\")) ;; hi", Profile::Lisp),
+            [
+                "(",
+                "with-open-file",
+                " ",
+                "(",
+                "*s*",
+                " ",
+                "\"f\"",
+                ")",
+                ")",
+                " ",
+                ";;",
+                " ",
+                "hi"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prose() {
+        assert_eq!(
+            tokenize("Don't re-run it - ok?", Profile::Prose),
+            ["Don't", " ", "re-run", " ", "it", " ", "-", " ", "ok", "?"]
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            tokenize("id,\"Doe, John\", 42 ,,x\r\n", Profile::Csv),
+            [
+                "id",
+                ",",
+                "\"Doe, John\"",
+                ",",
+                " ",
+                "42",
+                " ",
+                ",",
+                ",",
+                "x",
+                "\r",
+                "\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tsv() {
+        assert_eq!(
+            tokenize("a b\t\"c\td\n", Profile::Tsv),
+            ["a b", "\t", "\"c", "\t", "d", "\n"]
+        );
+    }
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(
+            split_fields("a,\"b,\"\"c\"\"\",,d\n", ','),
+            ["a", "\"b,\"\"c\"\"\"", "", "d"]
+        );
+        assert_eq!(split_fields("", ','), [""]);
+        assert_eq!(split_fields("a\tb", '\t'), ["a", "b"]);
     }
 }
@@ -46,7 +46,13 @@ EOF
     chmod +x /tmp/before.sh
 
     # Capture the actual output
-    cargo run -- --color=on <"$INPUT" >"$WORKFILE" || true
+    # Examples named collapsed-* test collapsing generated files
+    COLLAPSE="--no-collapse"
+    if [[ "$(basename "$INPUT")" == collapsed-* ]]; then
+        COLLAPSE=""
+    fi
+    # shellcheck disable=SC2086
+    cargo run -- --color=on $COLLAPSE --refine-budget 3600000 <"$INPUT" >"$WORKFILE" || true
 
     # Is the output different?
     if diff -u "$EXPECTED" "$WORKFILE" >/dev/null; then